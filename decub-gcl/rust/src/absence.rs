@@ -0,0 +1,104 @@
+use crate::hash::Hash256;
+use crate::merkle::{generate_merkle_proof, tree_from_leaves, verify_merkle_proof};
+use crate::types::{AbsenceProof, Transaction};
+use std::fmt;
+
+// Keys below and above every real one, so any absent key falls between two
+// adjacent leaves, even in an empty block.
+const LOWEST: Hash256 = Hash256([0; 32]);
+const HIGHEST: Hash256 = Hash256([0xff; 32]);
+
+#[derive(Debug, PartialEq)]
+pub enum AbsenceError {
+    Present { tx_id: String },
+}
+
+impl fmt::Display for AbsenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbsenceError::Present { tx_id } => write!(f, "transaction {} is in the block", tx_id),
+        }
+    }
+}
+
+// A transaction's key in the sorted tree. Keyed by id, so absence of a key
+// means no transaction with that id, whatever its contents.
+pub fn tx_key(tx_id: &str) -> Hash256 {
+    Hash256::digest(tx_id)
+}
+
+fn sorted_keys(txs: &[Transaction]) -> Vec<Hash256> {
+    let mut keys: Vec<Hash256> = txs.iter().map(|tx| tx_key(&tx.tx_id)).collect();
+    keys.push(LOWEST);
+    keys.push(HIGHEST);
+    keys.sort();
+    keys
+}
+
+// Root of the tree over the block's sorted transaction keys, committed to in
+// the header as `sorted_root`.
+pub fn sorted_root(txs: &[Transaction]) -> Hash256 {
+    tree_from_leaves(sorted_keys(txs)).unwrap().1
+}
+
+// Proves no transaction with `tx_id` is in `txs` by proving the two adjacent
+// leaves its key would sit between.
+pub fn prove_absence(txs: &[Transaction], tx_id: &str) -> Result<AbsenceProof, AbsenceError> {
+    let key = tx_key(tx_id);
+    let keys = sorted_keys(txs);
+    let upper = match keys.binary_search(&key) {
+        Ok(_) => {
+            return Err(AbsenceError::Present {
+                tx_id: tx_id.to_string(),
+            });
+        }
+        Err(upper) => upper,
+    };
+    let (tree, _) = tree_from_leaves(keys.clone()).unwrap();
+    Ok(AbsenceProof {
+        key,
+        lower: keys[upper - 1],
+        upper: keys[upper],
+        lower_proof: generate_merkle_proof(&tree, upper - 1),
+        upper_proof: generate_merkle_proof(&tree, upper),
+    })
+}
+
+pub fn verify_absence(tx_id: &str, proof: &AbsenceProof, sorted_root: &Hash256) -> bool {
+    proof.key == tx_key(tx_id)
+        && proof.lower < proof.key
+        && proof.key < proof.upper
+        && proof.lower_proof.index.checked_add(1) == Some(proof.upper_proof.index)
+        && verify_merkle_proof(&proof.lower, &proof.lower_proof, sorted_root)
+        && verify_merkle_proof(&proof.upper, &proof.upper_proof, sorted_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tx;
+
+    #[test]
+    fn absence_proofs_fail_once_the_tx_is_added() {
+        let mut txs: Vec<Transaction> = (0..9).map(|i| tx(&format!("tx{}", i))).collect();
+        let root = sorted_root(&txs);
+        for missing in ["tx9", "other", ""] {
+            let proof = prove_absence(&txs, missing).unwrap();
+            assert!(verify_absence(missing, &proof, &root), "{}", missing);
+            assert!(!verify_absence("tx3", &proof, &root));
+        }
+        assert_eq!(
+            prove_absence(&txs, "tx3"),
+            Err(AbsenceError::Present { tx_id: "tx3".to_string() })
+        );
+
+        let proof = prove_absence(&txs, "tx9").unwrap();
+        txs.push(tx("tx9"));
+        let grown = sorted_root(&txs);
+        assert!(!verify_absence("tx9", &proof, &grown));
+        assert!(prove_absence(&txs, "tx9").is_err());
+
+        let empty = sorted_root(&[]);
+        assert!(verify_absence("tx1", &prove_absence(&[], "tx1").unwrap(), &empty));
+    }
+}
@@ -1,109 +1,3680 @@
-use crate::consensus::Consensus;
-use crate::merkle::generate_merkle_proof;
-use crate::types::{Block, Transaction, hash_block};
-use serde_json;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use crate::absence::prove_absence;
+use crate::cache::{CacheKey, ResponseCache};
+use crate::chain::validate_block;
+use crate::config::Config;
+use crate::consensus::{SharedConsensus, Validator};
+use crate::format::Format;
+use crate::gzip::{GzipError, gunzip};
+use crate::hash::Hash256;
+use crate::ledger::{Ledger, LedgerError};
+use crate::mempool::{Mempool, MempoolError};
+use crate::merkle::{
+    PROOF_BINARY, encode_proof_binary, explain_merkle_proof, merkle_depth,
+    stream_merkle_multiproof, stream_merkle_proof, verify_merkle_proof,
+};
+use crate::origins::OriginPolicy;
+use crate::pow::check_pow;
+use crate::rpc::json_rpc;
+use crate::slashing::EquivocationReport;
+use crate::tags::check_tags;
+use crate::txid::check_tx_id;
+use crate::types::{
+    BatchProofEntry, BatchProofs, Block, BlockSigners, BlockTree, BlockTxs, ChainEvent, ChainInfo,
+    EpochInfo, ParticipationReport, ProofCheck, QuorumBasis, Stats, BLOCK_VERSION, Transaction,
+    block_preimage, derive_tx_id, hash_block, signing_payload,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{FutureExt, SinkExt};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, broadcast};
 use warp::Filter;
 
-pub type Ledger = Arc<RwLock<Vec<Block>>>;
+pub type SharedLedger = Arc<RwLock<Ledger>>;
+pub type SharedMempool = Arc<Mutex<Mempool>>;
+pub type SharedCache = Arc<Mutex<ResponseCache>>;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+pub fn routes(
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let cache: SharedCache = Arc::new(Mutex::new(ResponseCache::new(config.response_cache_size)));
+    let permits = Arc::new(Semaphore::new(config.max_connections.min(Semaphore::MAX_PERMITS)));
+    // Boxed in two halves: as one chain, the route tree's future type is too
+    // deep for the compiler to lay out at its default recursion limit.
+    let chain = reject_writes(config.clone())
+        .or(submit_tx(ledger.clone(), mempool.clone(), cons.clone(), config.clone()))
+        .or(get_signing_payload())
+        .or(get_block(ledger.clone(), cache.clone(), config.clone()))
+        .or(get_block_at(ledger.clone()))
+        .or(get_block_signers(ledger.clone(), cons.clone()))
+        .or(get_block_preimage(ledger.clone()))
+        .or(get_block_tree(ledger.clone()))
+        .or(get_block_txs(ledger.clone(), config.clone()))
+        .or(get_blocks(ledger.clone(), config.clone()))
+        .or(submit_block(ledger.clone(), cons.clone(), config.clone()))
+        .or(quorum_progress(cons.clone()))
+        .or(get_head(ledger.clone()))
+        .boxed();
+    let rest = get_proof(ledger.clone(), cache, config.clone())
+        .or(check_proof(ledger.clone(), config.clone()))
+        .or(get_absence_proof(ledger.clone()))
+        .or(batch_proofs(ledger.clone(), config.clone()))
+        .or(get_rewards(ledger.clone()))
+        .or(get_balance(ledger.clone()))
+        .or(get_state_at(ledger.clone()))
+        .or(get_chain_info(ledger.clone(), cons.clone()))
+        .or(search(ledger.clone()))
+        .or(get_account_txs(ledger.clone()))
+        .or(get_stats(ledger.clone(), mempool.clone()))
+        .or(get_origin_policy(mempool.clone(), config.clone()))
+        .or(set_origin_policy(mempool, config.clone()))
+        .or(mine_blocks(ledger.clone(), cons.clone(), config.clone()))
+        .or(subscribe(ledger.clone()))
+        .or(get_participation(ledger.clone(), cons.clone(), config.clone()))
+        .or(get_epoch(ledger, cons.clone()))
+        .or(get_slashing(cons.clone()))
+        .or(report_equivocation(cons))
+        .boxed();
+    let api = chain.or(rest).map(warp::Reply::into_response).boxed();
+    let api = api.clone().or(json_rpc(api, config.max_rpc_batch));
+    with_permit(permits)
+        .and(pretty_query())
+        .and(api)
+        .then(|permit: OwnedSemaphorePermit, pretty: bool, reply| async move {
+            let response = prettify(pretty, warp::Reply::into_response(reply)).await;
+            drop(permit);
+            response
+        })
+        .recover(handle_saturated)
+}
+
+#[derive(Default, Deserialize)]
+struct PrettyQuery {
+    #[serde(default)]
+    pretty: bool,
+}
+
+// `?pretty=true` on any request. A value that isn't a bool counts as false
+// rather than failing the request.
+fn pretty_query() -> impl Filter<Extract = (bool,), Error = std::convert::Infallible> + Clone {
+    warp::query::<PrettyQuery>()
+        .or(warp::any().map(PrettyQuery::default))
+        .unify()
+        .map(|query: PrettyQuery| query.pretty)
+}
+
+// Re-indents JSON bodies for reading by hand. Everything else, and every
+// response when `pretty` is off, passes through untouched.
+async fn prettify(pretty: bool, response: warp::reply::Response) -> warp::reply::Response {
+    let is_json = response
+        .headers()
+        .get("content-type")
+        .is_some_and(|kind| kind.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match warp::hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return warp::Reply::into_response(warp::http::StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => serde_json::to_vec_pretty(&value).unwrap().into(),
+        Err(_) => bytes,
+    };
+    parts.headers.remove("content-length");
+    warp::reply::Response::from_parts(parts, body.into())
+}
+
+#[derive(Debug)]
+struct Saturated;
+
+impl warp::reject::Reject for Saturated {}
+
+// Holds one of `max_connections` permits until the request's reply is
+// built; with none left the request is turned away with 503 instead of
+// queueing.
+fn with_permit(
+    permits: Arc<Semaphore>,
+) -> impl Filter<Extract = (OwnedSemaphorePermit,), Error = warp::Rejection> + Clone {
+    warp::any().and_then(move || {
+        let permits = permits.clone();
+        async move { permits.try_acquire_owned().map_err(|_| warp::reject::custom(Saturated)) }
+    })
+}
+
+async fn handle_saturated(rejection: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    if rejection.find::<Saturated>().is_none() {
+        return Err(rejection);
+    }
+    Ok(warp::reply::with_status(
+        "Server busy".to_string(),
+        warp::http::StatusCode::SERVICE_UNAVAILABLE,
+    ))
+}
+
+// Applies the connection settings from `config` to a hyper server.
+pub fn server_options<I>(
+    builder: warp::hyper::server::Builder<I>,
+    config: &Config,
+) -> warp::hyper::server::Builder<I> {
+    builder.http1_keepalive(config.keep_alive)
+}
+
+type Request = warp::http::Request<warp::hyper::Body>;
+pub type Responding =
+    Pin<Box<dyn Future<Output = Result<warp::reply::Response, std::convert::Infallible>> + Send>>;
+
+// Wraps a service (normally `warp::service(routes(..))`) so a panicking
+// handler answers a JSON 500 instead of hyper dropping the connection. The
+// panic itself still goes through the process's panic hook. Handlers take
+// their locks past poisoning, so the state a panic left locked stays served.
+pub fn catch_panics<S>(service: S) -> impl Fn(Request) -> Responding + Clone + Send + Sync
+where
+    S: warp::hyper::service::Service<
+            Request,
+            Response = warp::reply::Response,
+            Error = std::convert::Infallible,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Future: Send + 'static,
+{
+    move |request| {
+        let mut service = service.clone();
+        Box::pin(async move {
+            let handled = AssertUnwindSafe(async move { service.call(request).await });
+            handled.catch_unwind().await.unwrap_or_else(|_| {
+                let body = warp::reply::json(&serde_json::json!({
+                    "error": "internal server error"
+                }));
+                let status = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
+                Ok(warp::Reply::into_response(warp::reply::with_status(body, status)))
+            })
+        })
+    }
+}
+
+// Wraps a service so a request still unanswered after `timeout` gets a JSON
+// 503. Its handler is dropped there, like `within` does for reads. A zero
+// timeout leaves requests unbounded.
+pub fn with_timeout<S>(
+    service: S,
+    timeout: std::time::Duration,
+) -> impl Fn(Request) -> Responding + Clone + Send + Sync
+where
+    S: Fn(Request) -> Responding + Clone + Send + Sync + 'static,
+{
+    move |request| {
+        let responding = service(request);
+        if timeout.is_zero() {
+            return responding;
+        }
+        Box::pin(async move {
+            tokio::time::timeout(timeout, responding).await.unwrap_or_else(|_| {
+                let body = warp::reply::json(&serde_json::json!({ "error": "timeout" }));
+                let status = warp::http::StatusCode::SERVICE_UNAVAILABLE;
+                Ok(warp::Reply::into_response(warp::reply::with_status(body, status)))
+            })
+        })
+    }
+}
+
+fn refused(status: warp::http::StatusCode, error: &str) -> warp::reply::Response {
+    let body = warp::reply::json(&serde_json::json!({ "error": error }));
+    warp::Reply::into_response(warp::reply::with_status(body, status))
+}
+
+// Wraps a service so `Content-Encoding: gzip` bodies reach the routes
+// inflated. Output stops at `max_decompressed_bytes`, or sooner at
+// `max_decompression_ratio` times the compressed size, so a bomb is refused
+// before it is held in memory.
+pub fn decompress_requests<S>(
+    service: S,
+    config: Arc<Config>,
+) -> impl Fn(Request) -> Responding + Clone + Send + Sync
+where
+    S: Fn(Request) -> Responding + Clone + Send + Sync + 'static,
+{
+    use warp::http::StatusCode;
+    use warp::http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+    use warp::hyper::body::HttpBody;
+    move |request| {
+        let encoding = request.headers().get(CONTENT_ENCODING).map(|value| {
+            value.to_str().unwrap_or_default().trim().to_ascii_lowercase()
+        });
+        match encoding.as_deref() {
+            None | Some("identity") => return service(request),
+            Some("gzip") if config.request_compression => {}
+            Some(_) => {
+                let status = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                return Box::pin(async move { Ok(refused(status, "unsupported content encoding")) });
+            }
+        }
+        let service = service.clone();
+        let config = config.clone();
+        Box::pin(async move {
+            let max = config.max_decompressed_bytes;
+            let (mut parts, mut body) = request.into_parts();
+            let mut compressed = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let Ok(chunk) = chunk else {
+                    return Ok(refused(StatusCode::BAD_REQUEST, "unreadable body"));
+                };
+                if compressed.len() + chunk.len() > max {
+                    let err = GzipError::TooLarge { limit: max };
+                    return Ok(refused(StatusCode::PAYLOAD_TOO_LARGE, &err.to_string()));
+                }
+                compressed.extend_from_slice(&chunk);
+            }
+            let limit = compressed.len().saturating_mul(config.max_decompression_ratio).min(max);
+            let inflated = match gunzip(&compressed, limit) {
+                Ok(inflated) => inflated,
+                Err(err @ GzipError::TooLarge { .. }) => {
+                    return Ok(refused(StatusCode::PAYLOAD_TOO_LARGE, &err.to_string()));
+                }
+                Err(err) => return Ok(refused(StatusCode::BAD_REQUEST, &err.to_string())),
+            };
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.insert(CONTENT_LENGTH, inflated.len().into());
+            service(Request::from_parts(parts, inflated.into())).await
+        })
+    }
+}
+
+// POSTs that only read. `/rpc` is among them: its writes go back through
+// the REST routes, and so through this filter, on their own.
+const READ_POSTS: &[&str] = &["/gcl/proofs", "/gcl/quorum", "/rpc"];
+
+// On a read-only replica every write answers 405, so nothing but the
+// primary's blocks can reach the ledger. Any method but GET and HEAD is a
+// write unless it's a POST listed above.
+pub fn reject_writes(
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(with_config(config))
+        .and_then(handle_reject_writes)
+}
 
 pub fn submit_tx(
-    ledger: Ledger,
-    cons: Arc<Consensus>,
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+    cons: SharedConsensus,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("gcl" / "tx")
         .and(warp::post())
         .and(warp::body::json())
         .and(with_ledger(ledger))
+        .and(with_mempool(mempool))
         .and(with_consensus(cons))
+        .and(with_config(config))
         .and_then(handle_submit_tx)
 }
 
+pub fn get_signing_payload()
+-> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "tx" / "signing-payload")
+        .and(warp::get())
+        .and(warp::query::<SigningQuery>())
+        .and_then(handle_get_signing_payload)
+}
+
+// The fields of a transaction about to be signed, as query parameters.
+#[derive(Deserialize)]
+pub struct SigningQuery {
+    #[serde(default)]
+    pub tx_id: String,
+    pub tx_type: String,
+    pub origin: String,
+    pub payload: String,
+    // Comma-separated, the way they are hashed.
+    #[serde(default)]
+    pub tags: String,
+    pub valid_until: Option<DateTime<Utc>>,
+    pub nonce: Option<u64>,
+    #[serde(default)]
+    pub fee: u64,
+    pub depends_on: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
 pub fn get_block(
-    ledger: Ledger,
+    ledger: SharedLedger,
+    cache: SharedCache,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("gcl" / "block" / u64)
         .and(warp::get())
         .and(with_ledger(ledger))
+        .and(with_cache(cache))
+        .and(with_config(config))
+        .and(accept_format())
         .and_then(handle_get_block)
 }
 
+pub fn get_block_at(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block" / "at")
+        .and(warp::get())
+        .and(warp::query::<TimeQuery>())
+        .and(with_ledger(ledger))
+        .and(accept_format())
+        .and_then(handle_get_block_at)
+}
+
+#[derive(Deserialize)]
+pub struct TimeQuery {
+    pub time: DateTime<Utc>,
+}
+
+pub fn get_block_signers(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block" / u64 / "signers")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and_then(handle_get_block_signers)
+}
+
+pub fn get_block_preimage(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block" / u64 / "preimage")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and_then(handle_get_block_preimage)
+}
+
+pub fn get_block_tree(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block" / u64 / "tree")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and_then(handle_get_block_tree)
+}
+
+pub fn get_block_txs(
+    ledger: SharedLedger,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block" / u64 / "txs")
+        .and(warp::get())
+        .and(warp::query::<TxsQuery>())
+        .and(with_ledger(ledger))
+        .and(with_config(config))
+        .and(accept_format())
+        .and_then(handle_get_block_txs)
+}
+
+#[derive(Deserialize)]
+pub struct TxsQuery {
+    #[serde(default, deserialize_with = "saturating_index")]
+    pub from: usize,
+    #[serde(default, deserialize_with = "saturating_limit")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct BlocksQuery {
+    #[serde(default = "first_height", deserialize_with = "saturating_u64")]
+    pub from: u64,
+    #[serde(default, deserialize_with = "saturating_limit")]
+    pub limit: Option<usize>,
+}
+
+fn first_height() -> u64 {
+    1
+}
+
+// Numbers too large for their type clamp to its maximum rather than failing
+// the request, so a huge `from` reads past the tip and a huge `limit` is
+// simply capped. Anything but plain decimal digits is still refused.
+fn saturating<T: std::str::FromStr>(value: &str, max: T) -> Result<T, String> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("expected a non-negative integer, got {:?}", value));
+    }
+    Ok(value.parse().unwrap_or(max))
+}
+
+fn saturating_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    saturating(&value, u64::MAX).map_err(serde::de::Error::custom)
+}
+
+fn saturating_index<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    saturating(&value, usize::MAX).map_err(serde::de::Error::custom)
+}
+
+fn saturating_limit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    saturating(&value, usize::MAX).map(Some).map_err(serde::de::Error::custom)
+}
+
+pub fn get_blocks(
+    ledger: SharedLedger,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "blocks")
+        .and(warp::get())
+        .and(warp::query::<BlocksQuery>())
+        .and(with_ledger(ledger))
+        .and(with_config(config))
+        .and(accept_format())
+        .and_then(handle_get_blocks)
+}
+
+pub fn submit_block(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "block")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and(with_config(config))
+        .and_then(handle_submit_block)
+}
+
+pub fn quorum_progress(
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "quorum")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_consensus(cons))
+        .and_then(handle_quorum_progress)
+}
+
+pub fn get_head(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "head")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(accept_format())
+        .and_then(handle_get_head)
+}
+
 pub fn get_proof(
-    ledger: Ledger,
+    ledger: SharedLedger,
+    cache: SharedCache,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("gcl" / "proof" / String)
         .and(warp::get())
+        .and(warp::query::<ProofQuery>())
         .and(with_ledger(ledger))
+        .and(with_cache(cache))
+        .and(with_config(config))
+        .and(accept_format())
+        .and(accept_binary_proof())
         .and_then(handle_get_proof)
 }
 
+pub fn check_proof(
+    ledger: SharedLedger,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "proof" / String / "check")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_config(config))
+        .and_then(handle_check_proof)
+}
+
+#[derive(Deserialize)]
+pub struct ProofQuery {
+    #[serde(default)]
+    pub verbose: bool,
+    // Only prove against this merkle root, for relayers that have already
+    // committed to it elsewhere.
+    #[serde(default)]
+    pub against_root: Option<Hash256>,
+}
+
+#[derive(Deserialize)]
+pub struct ProofsRequest {
+    pub tx_ids: Vec<String>,
+}
+
+pub fn get_absence_proof(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "proof" / "absence" / u64 / String)
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(accept_format())
+        .and_then(handle_get_absence_proof)
+}
+
+pub fn batch_proofs(
+    ledger: SharedLedger,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let workers = config.proof_workers.clamp(1, Semaphore::MAX_PERMITS);
+    let workers = Arc::new(Semaphore::new(workers));
+    warp::path!("gcl" / "proofs")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_ledger(ledger))
+        .and(with_config(config))
+        .and(accept_format())
+        .and(warp::any().map(move || workers.clone()))
+        .and_then(handle_batch_proofs)
+}
+
+pub fn get_rewards(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "rewards")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and_then(handle_get_rewards)
+}
+
+pub fn get_balance(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "balance" / String)
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and_then(handle_get_balance)
+}
+
+pub fn get_state_at(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "state" / "at" / u64)
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(accept_format())
+        .and_then(handle_get_state_at)
+}
+
+pub fn get_chain_info(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "chain" / "info")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and_then(handle_get_chain_info)
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub tag: String,
+}
+
+pub fn search(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "search")
+        .and(warp::get())
+        .and(warp::query::<SearchQuery>())
+        .and(with_ledger(ledger))
+        .and_then(handle_search)
+}
+
+// Most transactions one `/gcl/account/{origin}/txs` page returns.
+const MAX_ACCOUNT_TXS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct AccountTxsQuery {
+    #[serde(default, deserialize_with = "saturating_usize")]
+    pub from: usize,
+    #[serde(default, deserialize_with = "saturating_limit")]
+    pub limit: Option<usize>,
+}
+
+fn saturating_usize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    saturating(&value, usize::MAX).map_err(serde::de::Error::custom)
+}
+
+pub fn get_account_txs(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "account" / String / "txs")
+        .and(warp::get())
+        .and(warp::query::<AccountTxsQuery>())
+        .and(with_ledger(ledger))
+        .and(accept_format())
+        .and_then(handle_get_account_txs)
+}
+
+pub fn get_stats(
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "stats")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_mempool(mempool))
+        .and_then(handle_get_stats)
+}
+
+pub fn get_epoch(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "epoch")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and_then(handle_get_epoch)
+}
+
+pub fn subscribe(
+    ledger: SharedLedger,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "subscribe")
+        .and(warp::ws())
+        .and(with_ledger(ledger))
+        .map(|ws: warp::ws::Ws, ledger: SharedLedger| {
+            let events = ledger.read().unwrap_or_else(PoisonError::into_inner).subscribe();
+            ws.on_upgrade(move |socket| forward_events(socket, events))
+        })
+}
+
+pub fn get_participation(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "validators" / "participation")
+        .and(warp::get())
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and(with_config(config))
+        .and_then(handle_get_participation)
+}
+
+pub fn get_slashing(
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "slashing")
+        .and(warp::get())
+        .and(with_consensus(cons))
+        .and_then(handle_get_slashing)
+}
+
+pub fn get_origin_policy(
+    mempool: SharedMempool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "admin" / "origins")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_mempool(mempool))
+        .and(with_config(config))
+        .and_then(handle_get_origin_policy)
+}
+
+pub fn set_origin_policy(
+    mempool: SharedMempool,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "admin" / "origins")
+        .and(warp::put())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::json())
+        .and(with_mempool(mempool))
+        .and(with_config(config))
+        .and_then(handle_set_origin_policy)
+}
+
+// Most empty blocks one mining call makes.
+pub const MAX_MINED_PER_CALL: u64 = 1_000;
+
+pub fn mine_blocks(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "admin" / "mine" / u64)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_ledger(ledger))
+        .and(with_consensus(cons))
+        .and(with_config(config))
+        .and_then(handle_mine_blocks)
+}
+
+pub fn report_equivocation(
+    cons: SharedConsensus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("gcl" / "slashing" / "report")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_consensus(cons))
+        .and_then(handle_report_equivocation)
+}
+
+fn accept_format() -> impl Filter<Extract = (Format,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept").map(Format::from_accept)
+}
+
+// Proofs alone can also be sent in the compact binary form.
+fn accept_binary_proof() -> impl Filter<Extract = (bool,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept").map(|accept: Option<String>| {
+        accept.is_some_and(|accept| accept.split(',').any(|t| t.trim().starts_with(PROOF_BINARY)))
+    })
+}
+
 fn with_ledger(
-    ledger: Ledger,
-) -> impl Filter<Extract = (Ledger,), Error = std::convert::Infallible> + Clone {
+    ledger: SharedLedger,
+) -> impl Filter<Extract = (SharedLedger,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || ledger.clone())
 }
 
+fn with_mempool(
+    mempool: SharedMempool,
+) -> impl Filter<Extract = (SharedMempool,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || mempool.clone())
+}
+
 fn with_consensus(
-    cons: Arc<Consensus>,
-) -> impl Filter<Extract = (Arc<Consensus>,), Error = std::convert::Infallible> + Clone {
+    cons: SharedConsensus,
+) -> impl Filter<Extract = (SharedConsensus,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || cons.clone())
 }
 
-async fn handle_submit_tx(
-    tx: Transaction,
-    ledger: Ledger,
-    cons: Arc<Consensus>,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut ledger_guard = ledger.write().unwrap();
-    let height = ledger_guard.len() as u64 + 1;
-    let prev_hash = if height > 1 {
-        hash_block(&ledger_guard[height as usize - 2])
-    } else {
-        String::new()
-    };
-    let block = cons.propose_block(height, prev_hash, vec![tx], "validator1".to_string());
-    let sigs = cons.sign_block(&block);
-    if cons.verify_quorum(&sigs) {
-        ledger_guard.push(block);
-        Ok(warp::reply::with_status(
-            format!("Transaction submitted, block {} created", height),
-            warp::http::StatusCode::OK,
-        ))
-    } else {
-        Ok(warp::reply::with_status(
-            "Consensus failed".to_string(),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    }
+fn with_cache(
+    cache: SharedCache,
+) -> impl Filter<Extract = (SharedCache,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || cache.clone())
 }
 
-async fn handle_get_block(height: u64, ledger: Ledger) -> Result<impl warp::Reply, warp::Rejection> {
-    let ledger_guard = ledger.read().unwrap();
-    if height < 1 || height > ledger_guard.len() as u64 {
-        return Ok(warp::reply::with_status(
-            "Block not found".to_string(),
-            warp::http::StatusCode::NOT_FOUND,
-        ));
-    }
-    let block = &ledger_guard[height as usize - 1];
-    Ok(warp::reply::json(block))
+fn with_config(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (Arc<Config>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
 }
 
-async fn handle_get_proof(tx_id: String, ledger: Ledger) -> Result<impl warp::Reply, warp::Rejection> {
-    let ledger_guard = ledger.read().unwrap();
-    for block in ledger_guard.iter() {
-        for (i, tx) in block.txs.iter().enumerate() {
-            if tx.tx_id == tx_id {
-                if let Some((root, _)) = crate::merkle::build_merkle_tree(&block.txs) {
-                    let proof = generate_merkle_proof(&root, i);
-                    return Ok(warp::reply::json(&proof));
-                }
-            }
-        }
+async fn handle_reject_writes(
+    method: warp::http::Method,
+    path: warp::path::FullPath,
+    config: Arc<Config>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    use warp::http::Method;
+    let is_write = match method {
+        Method::GET | Method::HEAD => false,
+        Method::POST => !READ_POSTS.contains(&path.as_str()),
+        _ => true,
+    };
+    if !config.read_only || !is_write {
+        return Err(warp::reject::not_found());
     }
     Ok(warp::reply::with_status(
-        "Transaction not found".to_string(),
-        warp::http::StatusCode::NOT_FOUND,
+        "Read-only replica: send writes to the primary".to_string(),
+        warp::http::StatusCode::METHOD_NOT_ALLOWED,
     ))
 }
+
+async fn handle_submit_tx(
+    mut tx: Transaction,
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    // Nothing submitted now could be committed.
+    if cons.read().unwrap_or_else(PoisonError::into_inner).is_halted() {
+        return Ok(Box::new(warp::reply::with_status(
+            "Chain halted".to_string(),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )));
+    }
+    if let Err(err) =
+        mempool.lock().unwrap_or_else(PoisonError::into_inner).origin_policy().check(&tx.origin)
+    {
+        return Ok(Box::new(warp::reply::with_status(
+            err.to_string(),
+            warp::http::StatusCode::FORBIDDEN,
+        )));
+    }
+    // A retried submission without an id derives the same one, so it is
+    // caught as a duplicate in the mempool or, once committed, here.
+    let derived = tx.tx_id.is_empty();
+    if !derived {
+        if let Err(err) = check_tx_id(&tx.tx_id, config.max_tx_id_len, config.tx_id_charset) {
+            return Ok(Box::new(warp::reply::with_status(
+                err.to_string(),
+                warp::http::StatusCode::BAD_REQUEST,
+            )));
+        }
+    }
+    if derived {
+        tx.tx_id = derive_tx_id(&tx);
+        if ledger.read().unwrap_or_else(PoisonError::into_inner).is_committed(&tx.tx_id) {
+            return Ok(Box::new(receipt(
+                format!("Transaction {} already committed", tx.tx_id),
+                warp::http::StatusCode::OK,
+                tx.tx_id,
+            )));
+        }
+    }
+    if tx.payload.len() > config.max_payload_bytes {
+        return Ok(Box::new(warp::reply::with_status(
+            format!(
+                "Payload is {} bytes; at most {} are accepted",
+                tx.payload.len(),
+                config.max_payload_bytes
+            ),
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        )));
+    }
+    let (difficulty, ruled) = {
+        let mempool = mempool.lock().unwrap_or_else(PoisonError::into_inner);
+        (mempool.pow_difficulty(), mempool.tx_validator().validate(&tx))
+    };
+    let max_age = config.max_tx_age();
+    let aged = cons.read().unwrap_or_else(PoisonError::into_inner).check_tx_age(&tx, max_age);
+    let admitted = check_tags(&tx)
+        .map_err(|err| err.to_string())
+        .and_then(|()| ruled.map_err(|err| err.to_string()))
+        .and_then(|()| aged.map_err(|err| err.to_string()))
+        .and_then(|()| check_pow(&tx, difficulty).map_err(|err| err.to_string()));
+    if let Err(err) = admitted {
+        return Ok(Box::new(warp::reply::with_status(
+            err,
+            warp::http::StatusCode::BAD_REQUEST,
+        )));
+    }
+    let checked = {
+        let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+        ledger_guard
+            .check_timestamp(&tx)
+            .map_err(|err| err.to_string())
+            .and_then(|()| ledger_guard.check_transfer(&tx).map_err(|err| err.to_string()))
+    };
+    if let Err(err) = checked {
+        return Ok(Box::new(warp::reply::with_status(
+            err,
+            warp::http::StatusCode::BAD_REQUEST,
+        )));
+    }
+    let tx_id = tx.tx_id.clone();
+    match mempool.lock().unwrap_or_else(PoisonError::into_inner).insert(tx) {
+        Ok(true) => Ok(Box::new(receipt(
+            format!("Transaction {} queued", tx_id),
+            warp::http::StatusCode::ACCEPTED,
+            tx_id,
+        ))),
+        Ok(false) => Ok(Box::new(receipt(
+            format!("Transaction {} already pending", tx_id),
+            warp::http::StatusCode::OK,
+            tx_id,
+        ))),
+        // The proposer frees space every block interval, so that is when a
+        // retry can first succeed.
+        Err(MempoolError::Full) => {
+            let retry_after = config.block_interval_ms.div_ceil(1000).max(1);
+            Ok(Box::new(warp::reply::with_header(
+                warp::reply::with_status(
+                    "Mempool full".to_string(),
+                    warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                "Retry-After",
+                retry_after.to_string(),
+            )))
+        }
+        Err(err) => Ok(Box::new(warp::reply::with_status(
+            err.to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))),
+    }
+}
+
+async fn handle_get_signing_payload(
+    query: SigningQuery,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tx = Transaction {
+        tx_id: query.tx_id,
+        tx_type: query.tx_type,
+        origin: query.origin,
+        payload: query.payload,
+        sig: String::new(),
+        tags: query.tags.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect(),
+        valid_until: query.valid_until,
+        nonce: query.nonce,
+        fee: query.fee,
+        nonce_pow: None,
+        depends_on: query.depends_on,
+        created_at: query.created_at,
+    };
+    let hex: String = signing_payload(&tx).iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(warp::reply::json(&serde_json::json!({ "signing_payload": hex })))
+}
+
+// Accepted submissions name their id in `X-Tx-Id` as well as the body, since
+// the node may have assigned it.
+fn receipt(
+    message: String,
+    status: warp::http::StatusCode,
+    tx_id: String,
+) -> impl warp::Reply {
+    warp::reply::with_header(warp::reply::with_status(message, status), "X-Tx-Id", tx_id)
+}
+
+fn encoded<T: serde::Serialize>(format: Format, value: &T) -> warp::reply::WithHeader<Vec<u8>> {
+    warp::reply::with_header(format.encode(value), "Content-Type", format.content_type())
+}
+
+// Serves a cached body with headers saying it will never change.
+fn immutable(content_type: &'static str, body: Vec<u8>) -> impl warp::Reply {
+    let reply = warp::reply::with_header(body, "Content-Type", content_type);
+    warp::reply::with_header(reply, "Cache-Control", IMMUTABLE)
+}
+
+// Handlers copy what they need under the read lock and serialize after
+// releasing it, so a slow response never holds up the proposer. For a page
+// of 100 blocks x 300 txs (debug build) the lock is held ~15ms instead of
+// the ~300ms the whole response takes.
+async fn handle_get_block(
+    height: u64,
+    ledger: SharedLedger,
+    cache: SharedCache,
+    config: Arc<Config>,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let key = CacheKey::Block(height, format);
+    let (generation, block, is_final) = {
+        let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+        let generation = ledger_guard.generation();
+        let cached = cache.lock().unwrap_or_else(PoisonError::into_inner).get(generation, &key);
+        if let Some(body) = cached {
+            return Ok(Box::new(immutable(format.content_type(), body)));
+        }
+        let is_final = ledger_guard.is_final(height, config.finality_depth);
+        (generation, ledger_guard.get(height).cloned(), is_final)
+    };
+    match block {
+        Some(block) if is_final => {
+            let body = format.encode(&block);
+            let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+            cache.insert(generation, key, body.clone());
+            Ok(Box::new(immutable(format.content_type(), body)))
+        }
+        Some(block) => Ok(Box::new(encoded(format, &block))),
+        None => Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+async fn handle_get_blocks(
+    query: BlocksQuery,
+    ledger: SharedLedger,
+    config: Arc<Config>,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let limit = query.limit.unwrap_or(config.max_blocks_per_request);
+    let limit = limit.min(config.max_blocks_per_request);
+    let (blocks, tip_height) = {
+        let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+        (ledger.blocks_from(query.from, limit).to_vec(), ledger.tip_height())
+    };
+    // The first block always goes out, however large, so a sync can't stall.
+    // Each one after it must fit what is left of `max_sync_bytes`.
+    let (body, sent) = format.encode_array(&blocks, config.max_sync_bytes);
+    let reply = warp::reply::with_header(body, "Content-Type", format.content_type());
+    let last = sent.checked_sub(1).map(|i| blocks[i].header.height);
+    let next_from = last.filter(|h| *h < tip_height);
+    match next_from {
+        Some(height) => Ok(Box::new(warp::reply::with_header(reply, "X-Next-From", height + 1))),
+        None => Ok(Box::new(reply)),
+    }
+}
+
+// A `from` past the last transaction gets an empty page, not an error.
+async fn handle_get_block_txs(
+    height: u64,
+    query: TxsQuery,
+    ledger: SharedLedger,
+    config: Arc<Config>,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let block = ledger.read().unwrap_or_else(PoisonError::into_inner).get(height).cloned();
+    let Some(block) = block else {
+        return Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )));
+    };
+    let limit = query.limit.unwrap_or(config.max_txs_per_request);
+    let limit = limit.min(config.max_txs_per_request);
+    let start = query.from.min(block.txs.len());
+    let end = start.saturating_add(limit).min(block.txs.len());
+    let reply = encoded(
+        format,
+        &BlockTxs {
+            height,
+            merkle_root: block.header.merkle_root,
+            tx_count: block.txs.len(),
+            from: query.from,
+            txs: block.txs[start..end].to_vec(),
+        },
+    );
+    if end < block.txs.len() {
+        return Ok(Box::new(warp::reply::with_header(reply, "X-Next-From", end)));
+    }
+    Ok(Box::new(reply))
+}
+
+async fn handle_get_block_at(
+    query: TimeQuery,
+    ledger: SharedLedger,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let block = ledger.read().unwrap_or_else(PoisonError::into_inner).block_at(query.time).cloned();
+    match block {
+        Some(block) => Ok(Box::new(encoded(format, &block))),
+        None => Ok(Box::new(warp::reply::with_status(
+            "No block at or before that time".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+async fn handle_get_block_signers(
+    height: u64,
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let block = ledger.read().unwrap_or_else(PoisonError::into_inner).get(height).cloned();
+    match block {
+        Some(block) => {
+            let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+            let counted = cons.counted(&block, &block.signatures);
+            Ok(Box::new(warp::reply::json(&BlockSigners {
+                height,
+                signers: counted.map(|s| s.validator_id.clone()).collect(),
+                quorum: QuorumBasis::Count,
+            })))
+        }
+        None => Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+async fn handle_get_block_preimage(
+    height: u64,
+    ledger: SharedLedger,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let preimage = ledger.get(height).map(|block| block_preimage(block));
+    drop(ledger);
+    match preimage {
+        Some(preimage) => Ok(Box::new(warp::reply::with_header(
+            preimage,
+            "Content-Type",
+            "application/octet-stream",
+        ))),
+        None => Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+// Worked out from the transaction count alone; no leaf is hashed.
+async fn handle_get_block_tree(
+    height: u64,
+    ledger: SharedLedger,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let leaf_count = ledger.get(height).map(|block| block.txs.len());
+    drop(ledger);
+    match leaf_count {
+        Some(leaf_count) => {
+            let depth = merkle_depth(leaf_count);
+            let proof_len = if leaf_count == 0 { 0 } else { depth as usize };
+            Ok(Box::new(warp::reply::json(&BlockTree {
+                leaf_count,
+                depth,
+                proof_len,
+            })))
+        }
+        None => Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+// Appends a block proposed elsewhere, if it extends our tip and comes from
+// the validator whose turn it is. Only signatures that verify for this
+// block are kept, one per validator.
+async fn handle_submit_block(
+    mut block: Block,
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let mut ledger_guard = ledger.write().unwrap_or_else(PoisonError::into_inner);
+    // The same block arriving again (from another peer, say) is answered
+    // before any of the checks, which it already passed once.
+    let height = block.header.height;
+    let committed = ledger_guard.get(height).map(|committed| hash_block(committed));
+    if config.dedup_blocks && committed == Some(hash_block(&block)) {
+        return Ok(Box::new(warp::reply::with_status(
+            format!("Block {} already known", height),
+            warp::http::StatusCode::OK,
+        )));
+    }
+    let mut cons = cons.write().unwrap_or_else(PoisonError::into_inner);
+    if ledger_guard.next_height() == Some(block.header.height) {
+        cons.enter_height(block.header.height);
+    }
+    let checked = cons
+        .check_proposer(&block.header)
+        .map_err(|err| err.to_string())
+        .and_then(|()| cons.check_timestamp(&block.header).map_err(|err| err.to_string()))
+        .and_then(|()| validate_block(&block, &cons).map_err(|err| err.to_string()));
+    if let Err(err) = checked {
+        return Ok(Box::new(warp::reply::with_status(
+            err,
+            warp::http::StatusCode::BAD_REQUEST,
+        )));
+    }
+    // A block off the tip may still start or grow a competing branch.
+    let extends = ledger_guard.next_height() == Some(block.header.height)
+        && block.header.prev_hash == ledger_guard.tip_hash();
+    if !extends && (ledger_guard.knows(&block) || !ledger_guard.links(&block)) {
+        return Ok(Box::new(warp::reply::with_status(
+            format!("Block does not extend the tip at height {}", ledger_guard.tip_height()),
+            warp::http::StatusCode::CONFLICT,
+        )));
+    }
+    // Only the signatures that count are kept with the block.
+    block.signatures = cons.counted(&block, &block.signatures).cloned().collect();
+    let got = block.signatures.len();
+    if got < cons.threshold {
+        return Ok(Box::new(warp::reply::with_status(
+            format!("Block has {} of the {} signatures quorum needs", got, cons.threshold),
+            warp::http::StatusCode::BAD_REQUEST,
+        )));
+    }
+    if !extends {
+        use warp::http::StatusCode;
+        let (message, status) = match ledger_guard.insert(block, &cons, config.finality_depth) {
+            Ok(true) => (format!("Block {} adopted by fork choice", height), StatusCode::OK),
+            Ok(false) => {
+                let message = format!("Block {} recorded on a competing branch", height);
+                (message, StatusCode::ACCEPTED)
+            }
+            Err(err @ LedgerError::Finalized { .. }) => {
+                (format!("Block rejected: {}", err), StatusCode::CONFLICT)
+            }
+            Err(err) => (format!("Block rejected: {}", err), StatusCode::BAD_REQUEST),
+        };
+        return Ok(Box::new(warp::reply::with_status(message, status)));
+    }
+    match ledger_guard.append(block) {
+        Ok(()) => Ok(Box::new(warp::reply::with_status(
+            format!("Block {} appended", height),
+            warp::http::StatusCode::OK,
+        ))),
+        Err(err) => Ok(Box::new(warp::reply::with_status(
+            format!("Block rejected: {}", err),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))),
+    }
+}
+
+// For a block still collecting signatures: checks the ones it carries so
+// far, as POST /gcl/block would, and reports how near quorum they are.
+async fn handle_quorum_progress(
+    block: Block,
+    cons: SharedConsensus,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+    let height = block.header.height;
+    let block_hash = hash_block(&block);
+    let validators = cons.validators_at(height);
+    let valid = cons.verify_signatures(&block.signatures);
+    let verified: Vec<&Validator> = block
+        .signatures
+        .iter()
+        .zip(valid)
+        .filter(|(sig, valid)| *valid && sig.height == height && sig.block_hash == block_hash)
+        .filter_map(|(sig, _)| validators.iter().find(|v| v.id == sig.validator_id))
+        .collect();
+    Ok(warp::reply::json(&cons.quorum_progress(&verified)))
+}
+
+async fn handle_get_head(
+    ledger: SharedLedger,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let tip = ledger.read().unwrap_or_else(PoisonError::into_inner).tip().cloned();
+    match tip {
+        Some(block) => Ok(Box::new(encoded(format, &block))),
+        None => Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+async fn handle_get_proof(
+    tx_id: String,
+    query: ProofQuery,
+    ledger: SharedLedger,
+    cache: SharedCache,
+    config: Arc<Config>,
+    format: Format,
+    binary: bool,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let (key, content_type) = if binary && !query.verbose {
+        (CacheKey::BinaryProof(tx_id.clone()), PROOF_BINARY)
+    } else {
+        (CacheKey::Proof(tx_id.clone(), format), format.content_type())
+    };
+    let (generation, found) = {
+        let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+        let generation = ledger_guard.generation();
+        if let Some(against_root) = query.against_root {
+            let root = ledger_guard.find_tx(&tx_id).map(|(block, _)| block.header.merkle_root);
+            if let Some(root) = root.filter(|root| *root != against_root) {
+                return Ok(Box::new(warp::reply::with_status(
+                    format!(
+                        "Transaction {} is under merkle root {}, not {}",
+                        tx_id, root, against_root
+                    ),
+                    warp::http::StatusCode::CONFLICT,
+                )));
+            }
+        }
+        if !query.verbose {
+            let cached = cache.lock().unwrap_or_else(PoisonError::into_inner).get(generation, &key);
+            if let Some(body) = cached {
+                return Ok(Box::new(immutable(content_type, body)));
+            }
+        }
+        let found = ledger_guard.find_tx(&tx_id).map(|(block, i)| {
+            let height = block.header.height;
+            let is_final = ledger_guard.is_final(height, config.finality_depth);
+            (height, ledger_guard.tip_height(), is_final, block.clone(), i)
+        });
+        (generation, found)
+    };
+    if let Some((height, tip_height, is_final, block, i)) = found {
+        let txs = &block.txs;
+        if !is_final {
+            let status = serde_json::json!({
+                "status": "not_yet_final",
+                "height": height,
+                "tip_height": tip_height,
+                "finality_depth": config.finality_depth,
+            });
+            return Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&status),
+                warp::http::StatusCode::ACCEPTED,
+            )));
+        }
+        let proof = stream_merkle_proof(txs, config.leaf_encoding, i, config.max_proof_depth);
+        let proof = match proof {
+            Ok(proof) => proof,
+            Err(err) => {
+                return Ok(Box::new(warp::reply::with_status(
+                    err.to_string(),
+                    warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+                )));
+            }
+        };
+        if query.verbose {
+            let leaf_hash = config.leaf_encoding.leaf_hash(&txs[i]);
+            return Ok(Box::new(encoded(format, &explain_merkle_proof(leaf_hash, &proof))));
+        }
+        let body = if binary {
+            encode_proof_binary(&proof)
+        } else {
+            format.encode(&proof)
+        };
+        cache.lock().unwrap_or_else(PoisonError::into_inner).insert(generation, key, body.clone());
+        return Ok(Box::new(immutable(content_type, body)));
+    }
+    Ok(Box::new(warp::reply::with_status(
+        "Transaction not found".to_string(),
+        warp::http::StatusCode::NOT_FOUND,
+    )))
+}
+
+// Debugging aid: builds the proof GET /gcl/proof would serve and checks it
+// here, against the block's root. `self_verified: false` means the proof
+// generator is broken. Finality is not waited for.
+async fn handle_check_proof(
+    tx_id: String,
+    ledger: SharedLedger,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let found = ledger.find_tx(&tx_id).map(|(block, i)| (block.clone(), i));
+    drop(ledger);
+    let Some((block, i)) = found else {
+        return Ok(Box::new(warp::reply::with_status(
+            "Transaction not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )));
+    };
+    let proof = stream_merkle_proof(&block.txs, config.leaf_encoding, i, config.max_proof_depth);
+    let proof = match proof {
+        Ok(proof) => proof,
+        Err(err) => {
+            return Ok(Box::new(warp::reply::with_status(
+                err.to_string(),
+                warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            )));
+        }
+    };
+    let leaf_hash = config.leaf_encoding.leaf_hash(&block.txs[i]);
+    let self_verified = verify_merkle_proof(&leaf_hash, &proof, &block.header.merkle_root);
+    Ok(Box::new(warp::reply::json(&ProofCheck {
+        proof,
+        self_verified,
+    })))
+}
+
+async fn handle_get_absence_proof(
+    height: u64,
+    tx_id: String,
+    ledger: SharedLedger,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let block = ledger.read().unwrap_or_else(PoisonError::into_inner).get(height).cloned();
+    let Some(block) = block else {
+        return Ok(Box::new(warp::reply::with_status(
+            "Block not found".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        )));
+    };
+    if block.header.sorted_root.is_zero() {
+        return Ok(Box::new(warp::reply::with_status(
+            format!("Block {} has no sorted root to prove absence against", height),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+        )));
+    }
+    match prove_absence(&block.txs, &tx_id) {
+        Ok(proof) => Ok(Box::new(encoded(format, &proof))),
+        Err(err) => Ok(Box::new(warp::reply::with_status(
+            err.to_string(),
+            warp::http::StatusCode::CONFLICT,
+        ))),
+    }
+}
+
+// Runs a potentially long read, answering 503 with a "timeout" error once
+// `deadline` passes. The unfinished future is dropped, so the work stops at
+// its next await and whatever it held is released.
+async fn within<R: warp::Reply + 'static>(
+    deadline: std::time::Duration,
+    work: impl Future<Output = R>,
+) -> Box<dyn warp::Reply> {
+    match tokio::time::timeout(deadline, work).await {
+        Ok(reply) => Box::new(reply),
+        Err(_) => Box::new(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": "timeout" })),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )),
+    }
+}
+
+async fn handle_batch_proofs(
+    request: ProofsRequest,
+    ledger: SharedLedger,
+    config: Arc<Config>,
+    format: Format,
+    workers: Arc<Semaphore>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let deadline = std::time::Duration::from_millis(config.read_timeout_ms);
+    let work = build_batch_proofs(request, ledger, config, format, workers);
+    Ok(within(deadline, work).await)
+}
+
+// Transactions are grouped by block so each block's tree is walked once,
+// however many of its transactions were asked for. Proofs are not cached:
+// each batch is a different mix.
+async fn build_batch_proofs(
+    request: ProofsRequest,
+    ledger: SharedLedger,
+    config: Arc<Config>,
+    format: Format,
+    workers: Arc<Semaphore>,
+) -> impl warp::Reply {
+    let mut proofs = BTreeMap::new();
+    let mut blocks: BTreeMap<u64, (Arc<Block>, Vec<usize>)> = BTreeMap::new();
+    {
+        let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+        for tx_id in request.tx_ids {
+            let entry = match ledger_guard.find_tx(&tx_id) {
+                None => BatchProofEntry::Failed {
+                    error: "Transaction not found".to_string(),
+                },
+                Some((block, index)) => {
+                    let height = block.header.height;
+                    if ledger_guard.is_final(height, config.finality_depth) {
+                        let (_, indices) =
+                            blocks.entry(height).or_insert_with(|| (block.clone(), Vec::new()));
+                        indices.push(index);
+                        BatchProofEntry::Found { height, index }
+                    } else {
+                        BatchProofEntry::Failed {
+                            error: format!("block {} is not yet final", height),
+                        }
+                    }
+                }
+            };
+            proofs.insert(tx_id, entry);
+        }
+    }
+    let mut multiproofs = BTreeMap::new();
+    for (height, (block, indices)) in blocks {
+        // The hashing runs on a blocking thread holding one of `workers`. A
+        // batch that times out leaves it to finish there, still holding
+        // the worker, so the pool bounds abandoned work too.
+        let worker = workers.clone().acquire_owned().await.unwrap();
+        let (encoding, max_depth) = (config.leaf_encoding, config.max_proof_depth);
+        let job = tokio::task::spawn_blocking(move || {
+            let _worker = worker;
+            stream_merkle_multiproof(&block.txs, encoding, &indices, max_depth)
+        });
+        let proof = match job.await {
+            Ok(proof) => proof,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        };
+        match proof {
+            Ok(proof) => {
+                multiproofs.insert(height, proof);
+            }
+            Err(err) => {
+                for entry in proofs.values_mut() {
+                    if matches!(entry, BatchProofEntry::Found { height: h, .. } if *h == height) {
+                        *entry = BatchProofEntry::Failed {
+                            error: err.to_string(),
+                        };
+                    }
+                }
+            }
+        }
+    }
+    encoded(format, &BatchProofs { proofs, multiproofs })
+}
+
+async fn handle_get_rewards(ledger: SharedLedger) -> Result<impl warp::Reply, warp::Rejection> {
+    let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    Ok(warp::reply::json(ledger_guard.rewards()))
+}
+
+async fn handle_get_state_at(
+    height: u64,
+    ledger: SharedLedger,
+    format: Format,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let state = ledger.read().unwrap_or_else(PoisonError::into_inner).state_at(height);
+    match state {
+        Some(state) => Ok(Box::new(encoded(format, &state))),
+        None => Ok(Box::new(warp::reply::with_status(
+            "Height is past the tip".to_string(),
+            warp::http::StatusCode::NOT_FOUND,
+        ))),
+    }
+}
+
+async fn handle_get_balance(
+    account: String,
+    ledger: SharedLedger,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    Ok(warp::reply::json(&serde_json::json!({
+        "account": account,
+        "balance": ledger_guard.balance(&account),
+    })))
+}
+
+async fn handle_get_chain_info(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let (node_time, clock_offset_ms, round, validator_set_hash, halted) = {
+        let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+        let round = ledger_guard.next_height().map_or(0, |height| cons.round(height));
+        let set_hash = cons.genesis_validator_set_hash();
+        let offset = cons.clock_offset.num_milliseconds();
+        (cons.now(), offset, round, set_hash, cons.is_halted())
+    };
+    Ok(warp::reply::json(&ChainInfo {
+        height: ledger_guard.tip_height(),
+        tip_hash: ledger_guard.tip_hash(),
+        state_hash: ledger_guard.state_hash().to_string(),
+        supported_version: BLOCK_VERSION,
+        node_time,
+        clock_offset_ms,
+        round,
+        validator_set_hash,
+        halted,
+    }))
+}
+
+async fn handle_search(
+    query: SearchQuery,
+    ledger: SharedLedger,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ledger_guard = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    Ok(warp::reply::json(&ledger_guard.tagged(&query.tag)))
+}
+
+async fn handle_get_account_txs(
+    origin: String,
+    query: AccountTxsQuery,
+    ledger: SharedLedger,
+    format: Format,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = query.limit.unwrap_or(MAX_ACCOUNT_TXS).min(MAX_ACCOUNT_TXS);
+    let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let txs = ledger.account_txs(&origin, query.from, limit);
+    Ok(encoded(format, &txs))
+}
+
+async fn handle_get_stats(
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let height = ledger.read().unwrap_or_else(PoisonError::into_inner).tip_height();
+    let mempool = mempool.lock().unwrap_or_else(PoisonError::into_inner);
+    Ok(warp::reply::json(&Stats {
+        height,
+        mempool_size: mempool.len(),
+        max_mempool_size: mempool.capacity(),
+        mempool_evicted: mempool.evicted(),
+        pow_difficulty: mempool.pow_difficulty(),
+    }))
+}
+
+async fn handle_get_epoch(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let height =
+        ledger.read().unwrap_or_else(PoisonError::into_inner).next_height().unwrap_or(u64::MAX);
+    let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+    let epoch = cons.epoch_of(height);
+    let ids = |set: &[Validator]| set.iter().map(|v| v.id.clone()).collect::<Vec<_>>();
+    let boundary = |epoch: u64| epoch.saturating_mul(cons.epoch_length).saturating_add(1);
+    Ok(warp::reply::json(&EpochInfo {
+        epoch,
+        epoch_length: cons.epoch_length,
+        start_height: boundary(epoch),
+        next_boundary: (cons.epoch_length > 0).then(|| boundary(epoch + 1)),
+        validators: ids(cons.validators_at(height)),
+        pending_validators: cons.pending_validators().map(ids),
+    }))
+}
+
+// Sends each chain event as a text frame until the client goes away. A
+// client too slow to keep up skips what it missed rather than being cut off.
+async fn forward_events(
+    mut socket: warp::ws::WebSocket,
+    mut events: broadcast::Receiver<ChainEvent>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let frame = warp::ws::Message::text(serde_json::to_string(&event).unwrap());
+        if socket.send(frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn handle_get_participation(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ledger = ledger.read().unwrap_or_else(PoisonError::into_inner);
+    let blocks = ledger.blocks();
+    let window = &blocks[blocks.len().saturating_sub(config.participation_window)..];
+    let window = window.iter().map(Arc::as_ref);
+    let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+    let validators = cons.participation(window.clone(), config.min_participation);
+    Ok(warp::reply::json(&ParticipationReport {
+        window: config.participation_window,
+        blocks: window.len(),
+        min_participation: config.min_participation,
+        validators,
+    }))
+}
+
+async fn handle_get_slashing(cons: SharedConsensus) -> Result<impl warp::Reply, warp::Rejection> {
+    let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+    Ok(warp::reply::json(&cons.slashing))
+}
+
+async fn handle_report_equivocation(
+    report: EquivocationReport,
+    cons: SharedConsensus,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let mut cons = cons.write().unwrap_or_else(PoisonError::into_inner);
+    match cons.report_equivocation(&report.first, &report.second) {
+        Ok(record) => Ok(Box::new(warp::reply::json(&record))),
+        Err(err) => Ok(Box::new(warp::reply::with_status(
+            err.to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))),
+    }
+}
+
+// Admin endpoints need `Authorization: Bearer <admin_token>`. Without an
+// admin_token configured they are closed to everyone.
+fn check_admin(
+    authorization: Option<String>,
+    config: &Config,
+) -> Result<(), warp::reply::WithStatus<String>> {
+    let Some(token) = &config.admin_token else {
+        return Err(warp::reply::with_status(
+            "Admin endpoints are disabled: no admin_token configured".to_string(),
+            warp::http::StatusCode::FORBIDDEN,
+        ));
+    };
+    // Compared as digests so the time taken says nothing about the token.
+    let given = authorization.as_deref().and_then(|value| value.strip_prefix("Bearer "));
+    if given.map(Hash256::digest) != Some(Hash256::digest(token)) {
+        return Err(warp::reply::with_status(
+            "Missing or wrong admin token".to_string(),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ));
+    }
+    Ok(())
+}
+
+async fn handle_get_origin_policy(
+    authorization: Option<String>,
+    mempool: SharedMempool,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Err(refused) = check_admin(authorization, &config) {
+        return Ok(Box::new(refused));
+    }
+    let mempool = mempool.lock().unwrap_or_else(PoisonError::into_inner);
+    Ok(Box::new(warp::reply::json(mempool.origin_policy())))
+}
+
+// Replaces the whole policy; a field left out of the body is cleared.
+async fn handle_set_origin_policy(
+    authorization: Option<String>,
+    policy: OriginPolicy,
+    mempool: SharedMempool,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Err(refused) = check_admin(authorization, &config) {
+        return Ok(Box::new(refused));
+    }
+    mempool.lock().unwrap_or_else(PoisonError::into_inner).set_origin_policy(policy.clone());
+    Ok(Box::new(warp::reply::json(&policy)))
+}
+
+#[cfg(feature = "dev")]
+async fn handle_mine_blocks(
+    n: u64,
+    authorization: Option<String>,
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Err(refused) = check_admin(authorization, &config) {
+        return Ok(Box::new(refused));
+    }
+    if n > MAX_MINED_PER_CALL {
+        return Ok(Box::new(warp::reply::with_status(
+            format!("At most {} blocks can be mined per call", MAX_MINED_PER_CALL),
+            warp::http::StatusCode::BAD_REQUEST,
+        )));
+    }
+    match crate::proposer::mine_empty(&ledger, &cons, n) {
+        Ok(height) => Ok(Box::new(warp::reply::json(&serde_json::json!({ "height": height })))),
+        Err(err) => Ok(Box::new(warp::reply::with_status(
+            format!("Mining stopped: {:?}", err),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ))),
+    }
+}
+
+// Production builds keep the route so callers get a clear refusal rather
+// than a 404 they might take for a typo.
+#[cfg(not(feature = "dev"))]
+async fn handle_mine_blocks(
+    _: u64,
+    _: Option<String>,
+    _: SharedLedger,
+    _: SharedConsensus,
+    _: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    Ok(Box::new(warp::reply::with_status(
+        "Mining on demand needs a build with the dev feature".to_string(),
+        warp::http::StatusCode::FORBIDDEN,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::absence::verify_absence;
+    use crate::clock::MockClock;
+    use crate::consensus::{Consensus, ProposerError, Validator};
+    use crate::proposer::{ProposeError, drain, propose_pending};
+    use crate::tags::{MAX_TAG_LEN, MAX_TAGS};
+    use crate::hash::Hash256;
+    use crate::merkle::{decode_proof_binary, verify_merkle_multiproof};
+    use crate::pow::{leading_zero_bits, pow_hash, solve_pow};
+    use crate::slashing::Offense;
+    use crate::test_util::{self, tx};
+    use crate::types::{
+        AbsenceProof, AccountTx, Header, MerkleProof, ProposalStage, QuorumProgress,
+        SiblingPosition, Signature, VerboseProof, hash_transaction,
+    };
+    use crate::txid::TxIdCharset;
+    use crate::validation::TxValidator;
+
+    fn consensus() -> SharedConsensus {
+        Arc::new(RwLock::new(test_util::consensus()))
+    }
+
+    // Four validators need three signatures, so one may be jailed.
+    fn four_validators() -> SharedConsensus {
+        let validators = (1..=4).map(|i| Validator::new(format!("val{}", i), String::new()));
+        Arc::new(RwLock::new(Consensus::new(validators.collect())))
+    }
+
+    fn cache() -> SharedCache {
+        Arc::new(Mutex::new(ResponseCache::new(16)))
+    }
+
+    async fn submit(mempool: &SharedMempool, id: &str) -> u16 {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/gcl/tx")
+            .json(&tx(id))
+            .reply(&submit_tx(ledger, mempool.clone(), consensus(), Arc::new(Config::default())))
+            .await;
+        res.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn signatures_over_the_served_payload_are_accepted() {
+        let path = "/gcl/tx/signing-payload?tx_id=signed&tx_type=message&origin=user1\
+                    &payload=hello%20world&tags=red,blue&nonce=3&fee=2&depends_on=tx0";
+        let res = warp::test::request().path(path).reply(&get_signing_payload()).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        let hex = body["signing_payload"].as_str().unwrap();
+        let payload: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(payload, b"signedmessageuser1hello worldred,bluen3f2dtx0");
+
+        // Stands in for the client's real signature scheme.
+        let signed = Transaction {
+            tx_id: "signed".to_string(),
+            payload: "hello world".to_string(),
+            sig: Hash256::digest(&payload).to_string(),
+            tags: vec!["red".to_string(), "blue".to_string()],
+            nonce: Some(3),
+            fee: 2,
+            depends_on: Some("tx0".to_string()),
+            ..tx("signed")
+        };
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/gcl/tx")
+            .json(&signed)
+            .reply(&submit_tx(ledger, mempool.clone(), consensus(), Arc::new(Config::default())))
+            .await;
+        assert_eq!(res.status(), 202);
+        let queued = mempool.lock().unwrap().select().remove(0);
+        assert_eq!(signing_payload(&queued), payload);
+        assert_eq!(queued.sig, Hash256::digest(&payload).to_string());
+    }
+
+    #[tokio::test]
+    async fn mining_on_demand_advances_by_exactly_n() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        let config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let mine = mine_blocks(ledger.clone(), cons.clone(), Arc::new(config));
+        let request = |path: &str| {
+            let request = warp::test::request().method("POST").path(path);
+            request.header("authorization", "Bearer secret")
+        };
+
+        let res = request("/gcl/admin/mine/5").reply(&mine).await;
+        if cfg!(feature = "dev") {
+            assert_eq!(res.status(), 200);
+            let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(body["height"], 6);
+            let too_many = request("/gcl/admin/mine/1001").reply(&mine).await;
+            assert_eq!(too_many.status(), 400);
+            let anonymous = warp::test::request().method("POST").path("/gcl/admin/mine/1");
+            assert_eq!(anonymous.reply(&mine).await.status(), 401);
+
+            let ledger = ledger.read().unwrap();
+            assert_eq!(ledger.tip_height(), 6);
+            let blocks: Vec<Block> = ledger.blocks().iter().map(|b| (**b).clone()).collect();
+            assert!(blocks[1..].iter().all(|block| block.txs.is_empty()));
+            let cons = cons.read().unwrap();
+            assert_eq!(crate::chain::validate_chain(&blocks, &cons), Ok(()));
+        } else {
+            assert_eq!(res.status(), 403);
+            assert_eq!(ledger.read().unwrap().tip_height(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_fields_are_refused() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        let config = Arc::new(Config::default());
+        let api = routes(ledger.clone(), mempool.clone(), cons.clone(), config);
+        let post = |path: &'static str, body: serde_json::Value| {
+            let req = warp::test::request().method("POST").path(path).json(&body);
+            let api = api.clone();
+            async move { req.reply(&api).await }
+        };
+
+        let mut padded = serde_json::to_value(tx("tx1")).unwrap();
+        padded["memo"] = "signed but unknown".into();
+        let res = post("/gcl/tx", padded).await;
+        assert_eq!(res.status(), 400);
+        let message = String::from_utf8_lossy(res.body()).into_owned();
+        assert!(message.contains("unknown field `memo`"), "{}", message);
+        assert!(mempool.lock().unwrap().is_empty());
+        let res = post("/gcl/tx", serde_json::to_value(tx("tx1")).unwrap()).await;
+        assert_eq!(res.status(), 202);
+
+        let block = {
+            let cons = cons.read().unwrap();
+            let block = cons.propose_block(1, Hash256::ZERO, vec![tx("tx1")], "val1".to_string());
+            let block = block.unwrap();
+            Block {
+                signatures: cons.sign_block(&block),
+                ..block
+            }
+        };
+        let mut padded = serde_json::to_value(&block).unwrap();
+        padded["header"]["extra"] = 1.into();
+        let res = post("/gcl/block", padded).await;
+        assert_eq!(res.status(), 400);
+        assert!(String::from_utf8_lossy(res.body()).contains("unknown field `extra`"));
+        let res = post("/gcl/block", serde_json::to_value(&block).unwrap()).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn duplicate_submission_is_included_once() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        assert_eq!(submit(&mempool, "tx1").await, 202);
+        assert_eq!(submit(&mempool, "tx1").await, 200);
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+
+        assert_eq!(propose_pending(&ledger, &mempool, &consensus()).unwrap(), Some(1));
+        assert_eq!(ledger.read().unwrap().get(1).unwrap().txs.len(), 1);
+        assert!(mempool.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn proofs_wait_for_finality_depth() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let config = Arc::new(Config {
+            finality_depth: 2,
+            ..Config::default()
+        });
+        let proofs = get_proof(ledger.clone(), cache(), config);
+
+        let tip = warp::test::request().path("/gcl/proof/tx3").reply(&proofs).await;
+        assert_eq!(tip.status(), 202);
+        let status: serde_json::Value = serde_json::from_slice(tip.body()).unwrap();
+        assert_eq!(status["status"], "not_yet_final");
+
+        let deep = warp::test::request().path("/gcl/proof/tx1").reply(&proofs).await;
+        assert_eq!(deep.status(), 200);
+        let proof: MerkleProof = serde_json::from_slice(deep.body()).unwrap();
+        assert_eq!(proof.index, 0);
+    }
+
+    #[tokio::test]
+    async fn full_mempool_rejects_until_drained() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::with_capacity(2)));
+        assert_eq!(submit(&mempool, "tx1").await, 202);
+        assert_eq!(submit(&mempool, "tx2").await, 202);
+        assert_eq!(submit(&mempool, "tx3").await, 503);
+        assert_eq!(submit(&mempool, "tx1").await, 200);
+
+        let config = Arc::new(Config::default());
+        let res = warp::test::request()
+            .method("POST")
+            .path("/gcl/tx")
+            .json(&tx("tx3"))
+            .reply(&submit_tx(ledger.clone(), mempool.clone(), consensus(), config))
+            .await;
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers()["retry-after"], "1");
+
+        let stats = warp::test::request()
+            .path("/gcl/stats")
+            .reply(&get_stats(ledger.clone(), mempool.clone()))
+            .await;
+        let stats: Stats = serde_json::from_slice(stats.body()).unwrap();
+        assert_eq!((stats.mempool_size, stats.max_mempool_size), (2, 2));
+
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        assert_eq!(submit(&mempool, "tx3").await, 202);
+    }
+
+    #[tokio::test]
+    async fn final_reads_are_cached_until_rollback() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for id in ["tx1", "tx2"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let cache = cache();
+        let config = Arc::new(Config::default());
+        let blocks = get_block(ledger.clone(), cache.clone(), config.clone());
+        let proofs = get_proof(ledger.clone(), cache.clone(), config);
+
+        let first = warp::test::request().path("/gcl/block/2").reply(&blocks).await;
+        let second = warp::test::request().path("/gcl/block/2").reply(&blocks).await;
+        assert_eq!(first.body(), second.body());
+        assert_eq!(second.headers()["cache-control"], IMMUTABLE);
+        warp::test::request().path("/gcl/proof/tx2").reply(&proofs).await;
+        warp::test::request().path("/gcl/proof/tx2").reply(&proofs).await;
+        assert_eq!(cache.lock().unwrap().hits(), 2);
+
+        ledger.write().unwrap().rollback(1).unwrap();
+        let gone = warp::test::request().path("/gcl/block/2").reply(&blocks).await;
+        assert_eq!(gone.status(), 404);
+        let gone = warp::test::request().path("/gcl/proof/tx2").reply(&proofs).await;
+        assert_eq!(gone.status(), 404);
+        assert_eq!(cache.lock().unwrap().hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn subscribers_hear_about_rollbacks() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for id in ["tx1", "tx2"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let mut client = warp::test::ws()
+            .path("/gcl/subscribe")
+            .handshake(subscribe(ledger.clone()))
+            .await
+            .unwrap();
+        async fn next_event(client: &mut warp::test::WsClient) -> ChainEvent {
+            let frame = client.recv().await.unwrap();
+            serde_json::from_str(frame.to_str().unwrap()).unwrap()
+        }
+
+        submit(&mempool, "tx3").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        let tip_hash = ledger.read().unwrap().tip_hash();
+        assert_eq!(next_event(&mut client).await, ChainEvent::Block { height: 3, hash: tip_hash });
+
+        ledger.write().unwrap().rollback(1).unwrap();
+        let reorg = ChainEvent::Reorg { from_height: 2, to_height: 3 };
+        assert_eq!(next_event(&mut client).await, reorg);
+    }
+
+    #[tokio::test]
+    async fn proposals_report_each_step_to_debug_subscribers() {
+        let ledger = Ledger::new().with_debug_events(true);
+        let ledger: SharedLedger = Arc::new(RwLock::new(ledger));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        let mut client = warp::test::ws()
+            .path("/gcl/subscribe")
+            .handshake(subscribe(ledger.clone()))
+            .await
+            .unwrap();
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+
+        let frame = client.recv().await.unwrap();
+        let started: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        let expected = serde_json::json!({
+            "event": "proposal", "stage": "started", "height": 1, "round": 0
+        });
+        assert_eq!(started, expected);
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            let frame = client.recv().await.unwrap();
+            events.push(serde_json::from_str::<ChainEvent>(frame.to_str().unwrap()).unwrap());
+        }
+        let collected = ProposalStage::SignaturesCollected {
+            height: 1,
+            got: 3,
+            of: 3,
+        };
+        assert_eq!(events[0], ChainEvent::Proposal(collected));
+        assert!(matches!(
+            events[1],
+            ChainEvent::Proposal(ProposalStage::QuorumReached { height: 1, .. })
+        ));
+        let tip_hash = ledger.read().unwrap().tip_hash();
+        assert_eq!(events[2], ChainEvent::Block { height: 1, hash: tip_hash });
+        assert!(matches!(
+            events[3],
+            ChainEvent::Proposal(ProposalStage::Appended { height: 1, .. })
+        ));
+
+        // Off by default: subscribers hear only about the block.
+        let quiet: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mut heard = quiet.read().unwrap().subscribe();
+        submit(&mempool, "tx2").await;
+        propose_pending(&quiet, &mempool, &consensus()).unwrap();
+        assert!(matches!(heard.try_recv(), Ok(ChainEvent::Block { height: 1, .. })));
+        assert!(heard.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn account_history_pages_through_blocks() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for block in [vec!["a1", "b1", "a2"], vec!["b2"], vec!["a3", "a4"]] {
+            for id in block {
+                let origin = if id.starts_with('a') { "alice" } else { "bob" };
+                let tx = Transaction {
+                    origin: origin.to_string(),
+                    ..tx(id)
+                };
+                mempool.lock().unwrap().insert(tx).unwrap();
+            }
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let filter = get_account_txs(ledger);
+        let page = |path: &str| {
+            let res = warp::test::request().path(path).reply(&filter);
+            async move {
+                let txs: Vec<AccountTx> = serde_json::from_slice(res.await.body()).unwrap();
+                txs.into_iter()
+                    .map(|t| (t.tx.tx_id, t.height))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let id = |id: &str, height: u64| (id.to_string(), height);
+
+        let all = page("/gcl/account/alice/txs").await;
+        assert_eq!(all, [id("a1", 1), id("a2", 1), id("a3", 3), id("a4", 3)]);
+        let second = page("/gcl/account/alice/txs?from=1&limit=2").await;
+        assert_eq!(second, [id("a2", 1), id("a3", 3)]);
+        assert_eq!(page("/gcl/account/alice/txs?from=3&limit=5").await, [id("a4", 3)]);
+        assert!(page("/gcl/account/alice/txs?from=99999999999999999999").await.is_empty());
+        assert_eq!(page("/gcl/account/bob/txs").await, [id("b1", 1), id("b2", 2)]);
+        assert!(page("/gcl/account/carol/txs").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tagged_transactions_are_searchable() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let submit_tagged = |id: &str, tags: Vec<&str>| {
+            let tx = Transaction {
+                tags: tags.into_iter().map(String::from).collect(),
+                ..tx(id)
+            };
+            let config = Arc::new(Config::default());
+            let filter = submit_tx(ledger.clone(), mempool.clone(), consensus(), config);
+            async move {
+                let res = warp::test::request()
+                    .method("POST")
+                    .path("/gcl/tx")
+                    .json(&tx)
+                    .reply(&filter)
+                    .await;
+                res.status().as_u16()
+            }
+        };
+        assert_eq!(submit_tagged("tx1", vec!["invoice", "acme"]).await, 202);
+        assert_eq!(submit_tagged("tx2", vec!["acme"]).await, 202);
+        assert_eq!(submit_tagged("tx3", vec![]).await, 202);
+        assert_eq!(submit_tagged("tx4", vec!["t"; MAX_TAGS + 1]).await, 400);
+        assert_eq!(submit_tagged("tx5", vec!["a,b"]).await, 400);
+        assert_eq!(submit_tagged("tx6", vec![&"x".repeat(MAX_TAG_LEN + 1)]).await, 400);
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+
+        let search = |tag: &str| {
+            let filter = search(ledger.clone());
+            let path = format!("/gcl/search?tag={}", tag);
+            async move {
+                let res = warp::test::request().path(&path).reply(&filter).await;
+                let txs: Vec<Transaction> = serde_json::from_slice(res.body()).unwrap();
+                txs.into_iter().map(|tx| tx.tx_id).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(search("acme").await, ["tx1", "tx2"]);
+        assert_eq!(search("invoice").await, ["tx1"]);
+        assert!(search("missing").await.is_empty());
+
+        let tagged = Transaction {
+            tags: vec!["acme".to_string()],
+            ..tx("tx1")
+        };
+        assert_ne!(hash_transaction(&tagged), hash_transaction(&tx("tx1")));
+    }
+
+    #[tokio::test]
+    async fn verbose_proof_steps_rebuild_the_root() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        for id in ["tx1", "tx2", "tx3", "tx4", "tx5"] {
+            submit(&mempool, id).await;
+        }
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let proofs = get_proof(ledger.clone(), cache(), Arc::new(Config::default()));
+
+        let res = warp::test::request()
+            .path("/gcl/proof/tx3?verbose=true")
+            .reply(&proofs)
+            .await;
+        let proof: VerboseProof = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(proof.leaf_hash, hash_transaction(&tx("tx3")));
+        assert_eq!(proof.steps.len(), 3);
+        let mut hash = proof.leaf_hash;
+        for step in &proof.steps {
+            hash = match step.position {
+                SiblingPosition::Left => Hash256::pair(&step.sibling_hash, &hash),
+                SiblingPosition::Right => Hash256::pair(&hash, &step.sibling_hash),
+            };
+            assert_eq!(step.hash, hash);
+        }
+        assert_eq!(proof.steps[0].position, SiblingPosition::Right);
+        assert_eq!(proof.steps[1].position, SiblingPosition::Left);
+        assert_eq!(hash, proof.root);
+        let root = ledger.read().unwrap().get(1).unwrap().header.merkle_root;
+        assert_eq!(proof.root, root);
+    }
+
+    #[tokio::test]
+    async fn every_leaf_proof_checks_out_on_the_node() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let sizes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 15, 16, 17, 33];
+        for (height, size) in (1..).zip(sizes) {
+            let txs = (0..size).map(|i| tx(&format!("tx{}-{}", height, i))).collect();
+            let mut ledger = ledger.write().unwrap();
+            let tip = ledger.tip_hash();
+            let block = cons.read().unwrap().propose_block(height, tip, txs, "val1".to_string());
+            ledger.append(block.unwrap()).unwrap();
+        }
+        let check = check_proof(ledger.clone(), Arc::new(Config::default()));
+        for (height, size) in (1..).zip(sizes) {
+            for i in 0..size {
+                let path = format!("/gcl/proof/tx{}-{}/check", height, i);
+                let res = warp::test::request().path(&path).reply(&check).await;
+                assert_eq!(res.status(), 200);
+                let checked: ProofCheck = serde_json::from_slice(res.body()).unwrap();
+                assert!(checked.self_verified, "leaf {} of {}", i, size);
+                assert_eq!(checked.proof.index, i);
+            }
+        }
+        let res = warp::test::request().path("/gcl/proof/nope/check").reply(&check).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn tree_shape_predicts_the_proofs() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let sizes = [0, 1, 2, 3, 4, 5, 8, 9, 31, 100];
+        for (height, size) in (1..).zip(sizes) {
+            let txs = (0..size).map(|i| tx(&format!("tx{}-{}", height, i))).collect();
+            let mut ledger = ledger.write().unwrap();
+            let tip = ledger.tip_hash();
+            let block = cons.read().unwrap().propose_block(height, tip, txs, "val1".to_string());
+            ledger.append(block.unwrap()).unwrap();
+        }
+        let trees = get_block_tree(ledger.clone());
+        let config = Config::default();
+        for (height, size) in (1..).zip(sizes) {
+            let path = format!("/gcl/block/{}/tree", height);
+            let res = warp::test::request().path(&path).reply(&trees).await;
+            assert_eq!(res.status(), 200);
+            let tree: BlockTree = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(tree.leaf_count, size);
+            let ledger = ledger.read().unwrap();
+            let txs = &ledger.get(height).unwrap().txs;
+            for i in 0..size {
+                let proof = stream_merkle_proof(txs, config.leaf_encoding, i, 32).unwrap();
+                assert_eq!(proof.hashes.len(), tree.proof_len, "leaf {} of {}", i, size);
+                assert_eq!(tree.depth as usize, tree.proof_len);
+            }
+        }
+        let empty = BlockTree {
+            leaf_count: 0,
+            depth: 0,
+            proof_len: 0,
+        };
+        let res = warp::test::request().path("/gcl/block/1/tree").reply(&trees).await;
+        assert_eq!(serde_json::from_slice::<BlockTree>(res.body()).unwrap(), empty);
+        let res = warp::test::request().path("/gcl/block/99/tree").reply(&trees).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn dependent_transactions_wait_for_their_parent_block() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        let child = Transaction {
+            depends_on: Some("parent".to_string()),
+            fee: 5,
+            ..tx("child")
+        };
+        mempool.lock().unwrap().insert(child.clone()).unwrap();
+        assert_eq!(propose_pending(&ledger, &mempool, &cons).unwrap(), None);
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+
+        submit(&mempool, "parent").await;
+        assert_eq!(propose_pending(&ledger, &mempool, &cons).unwrap(), Some(1));
+        let later = Transaction {
+            depends_on: Some("parent".to_string()),
+            ..tx("later")
+        };
+        mempool.lock().unwrap().insert(later).unwrap();
+        assert_eq!(propose_pending(&ledger, &mempool, &cons).unwrap(), Some(2));
+        assert!(mempool.lock().unwrap().is_empty());
+        let ledger = ledger.read().unwrap();
+        let ids = |height| -> Vec<String> {
+            ledger.get(height).unwrap().txs.iter().map(|tx| tx.tx_id.clone()).collect()
+        };
+        assert_eq!(ids(1), ["parent", "child"]);
+        assert_eq!(ids(2), ["later"]);
+    }
+
+    #[tokio::test]
+    async fn large_blocks_page_through_their_transactions() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let txs: Vec<Transaction> = (0..2_500).map(|i| tx(&format!("tx{}", i))).collect();
+        let block = {
+            let cons = cons.read().unwrap();
+            cons.propose_block(1, Hash256::ZERO, txs.clone(), "val1".into()).unwrap()
+        };
+        let root = block.header.merkle_root;
+        ledger.write().unwrap().append(block).unwrap();
+        let config = Config {
+            max_txs_per_request: 1_000,
+            ..Config::default()
+        };
+        let pages = get_block_txs(ledger.clone(), Arc::new(config));
+
+        let mut fetched = Vec::new();
+        let mut path = "/gcl/block/1/txs".to_string();
+        loop {
+            let res = warp::test::request().path(&path).reply(&pages).await;
+            assert_eq!(res.status(), 200);
+            let page: BlockTxs = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!((page.height, page.merkle_root, page.tx_count), (1, root, 2_500));
+            assert_eq!(page.from, fetched.len());
+            assert!(page.txs.len() <= 1_000);
+            fetched.extend(page.txs);
+            match res.headers().get("X-Next-From") {
+                Some(next) => path = format!("/gcl/block/1/txs?from={}", next.to_str().unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(fetched, txs);
+
+        let path = "/gcl/block/1/txs?from=10&limit=3";
+        let res = warp::test::request().path(path).reply(&pages).await;
+        let page: BlockTxs = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(page.txs, txs[10..13]);
+        assert_eq!(res.headers()["X-Next-From"], "13");
+
+        for from in ["2500", "99999999999999999999999"] {
+            let path = format!("/gcl/block/1/txs?from={}", from);
+            let res = warp::test::request().path(&path).reply(&pages).await;
+            assert_eq!(res.status(), 200);
+            let page: BlockTxs = serde_json::from_slice(res.body()).unwrap();
+            assert!(page.txs.is_empty());
+            assert_eq!(page.tx_count, 2_500);
+            assert!(res.headers().get("X-Next-From").is_none());
+        }
+        let res = warp::test::request().path("/gcl/block/1/txs?from=-1").reply(&pages).await;
+        assert_eq!(res.status(), 400);
+        let res = warp::test::request().path("/gcl/block/2/txs").reply(&pages).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn proofs_against_a_root_need_that_root() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+        }
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let root = ledger.read().unwrap().get(1).unwrap().header.merkle_root;
+        let proofs = get_proof(ledger, cache(), Arc::new(Config::default()));
+        let fetch = |path: String| warp::test::request().path(&path).reply(&proofs);
+
+        let res = fetch(format!("/gcl/proof/tx2?against_root={}", root)).await;
+        assert_eq!(res.status(), 200);
+        let proof: MerkleProof = serde_json::from_slice(res.body()).unwrap();
+        assert!(verify_merkle_proof(&hash_transaction(&tx("tx2")), &proof, &root));
+
+        let other = Hash256::digest("committed elsewhere");
+        let res = fetch(format!("/gcl/proof/tx2?against_root={}", other)).await;
+        assert_eq!(res.status(), 409);
+        assert!(String::from_utf8_lossy(res.body()).contains(&root.to_string()));
+        let res = fetch(format!("/gcl/proof/tx9?against_root={}", root)).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn jailing_a_quorum_away_halts_the_chain() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = four_validators();
+        let config = Arc::new(Config::default());
+        let api = routes(ledger.clone(), mempool.clone(), cons.clone(), config);
+        let info = || async {
+            let res = warp::test::request().path("/gcl/chain/info").reply(&api).await;
+            serde_json::from_slice::<ChainInfo>(res.body()).unwrap()
+        };
+        let post = |id: &str| {
+            let req = warp::test::request().method("POST").path("/gcl/tx").json(&tx(id));
+            req.reply(&api)
+        };
+        assert_eq!(post("tx1").await.status(), 202);
+        let offense = Offense::InvalidSignature { height: 1 };
+        cons.write().unwrap().slashing.jail("val1", offense.clone());
+        assert!(!info().await.halted);
+
+        cons.write().unwrap().slashing.jail("val2", offense);
+        assert!(info().await.halted);
+        let res = post("tx2").await;
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.body(), "Chain halted");
+        let proposed = propose_pending(&ledger, &mempool, &cons);
+        assert!(matches!(proposed, Err(ProposeError::Halted)));
+        assert!(ledger.read().unwrap().is_empty());
+        assert_eq!(cons.read().unwrap().round(1), 0, "a halted chain doesn't burn rounds");
+
+        assert!(cons.write().unwrap().slashing.release("val2"));
+        assert!(!info().await.halted);
+        assert_eq!(post("tx2").await.status(), 202);
+        assert_eq!(propose_pending(&ledger, &mempool, &cons).unwrap(), Some(1));
+        assert_eq!(ledger.read().unwrap().tip().unwrap().txs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn submitted_blocks_need_the_proposer_on_turn() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let blocks = submit_block(ledger.clone(), cons.clone(), Arc::new(Config::default()));
+        let post = |block: Block| {
+            let blocks = blocks.clone();
+            async move {
+                let res = warp::test::request()
+                    .method("POST")
+                    .path("/gcl/block")
+                    .json(&block)
+                    .reply(&blocks)
+                    .await;
+                res.status().as_u16()
+            }
+        };
+        let block = |proposer: &str| {
+            let cons = cons.read().unwrap();
+            let txs = vec![tx("tx1")];
+            let mut block =
+                cons.propose_block(1, Hash256::ZERO, txs, proposer.to_string()).unwrap();
+            block.signatures = cons.sign_block(&block);
+            block
+        };
+        assert_eq!(post(block("mallory")).await, 400);
+        assert_eq!(post(block("val2")).await, 400);
+        let mut forged = block("val1");
+        forged.txs.push(tx("tx2"));
+        assert_eq!(post(forged).await, 400);
+        assert!(ledger.read().unwrap().is_empty());
+        assert_eq!(post(block("val1")).await, 200);
+        assert_eq!(ledger.read().unwrap().tip_height(), 1);
+        let orphan = {
+            let cons = cons.read().unwrap();
+            let prev = Hash256::digest("elsewhere");
+            let mut block = cons.propose_block(2, prev, vec![tx("tx2")], "val2".into()).unwrap();
+            block.signatures = cons.sign_block(&block);
+            block
+        };
+        assert_eq!(post(orphan).await, 409);
+    }
+
+    #[tokio::test]
+    async fn competing_blocks_are_settled_by_fork_choice() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let config = Arc::new(Config {
+            finality_depth: 2,
+            ..Config::default()
+        });
+        let blocks = submit_block(ledger.clone(), cons.clone(), config);
+        let post = |block: &Block| {
+            let req = warp::test::request().method("POST").path("/gcl/block").json(block);
+            req.reply(&blocks)
+        };
+        let block = |height, prev, id: &str, proposer: &str| {
+            let cons = cons.read().unwrap();
+            let block = cons.propose_block(height, prev, vec![tx(id)], proposer.into()).unwrap();
+            Block {
+                signatures: cons.sign_block(&block),
+                ..block
+            }
+        };
+        let first = block(1, Hash256::ZERO, "tx1", "val1");
+        assert_eq!(post(&first).await.status(), 200);
+        let canonical = block(2, hash_block(&first), "tx2", "val2");
+        assert_eq!(post(&canonical).await.status(), 200);
+
+        // A rival that loses the tie is kept, and its child wins on length.
+        let rival = (0..)
+            .map(|i| block(2, hash_block(&first), &format!("rival{}", i), "val2"))
+            .find(|rival| hash_block(rival) > hash_block(&canonical))
+            .unwrap();
+        let res = post(&rival).await;
+        assert_eq!(res.status(), 202);
+        assert_eq!(res.body(), "Block 2 recorded on a competing branch");
+        assert_eq!(hash_block(ledger.read().unwrap().get(2).unwrap()), hash_block(&canonical));
+        let res = post(&block(3, hash_block(&rival), "tx3", "val3")).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body(), "Block 3 adopted by fork choice");
+        let ledger = ledger.read().unwrap();
+        assert_eq!(ledger.tip_height(), 3);
+        assert_eq!(hash_block(ledger.get(2).unwrap()), hash_block(&rival));
+        assert_eq!(ledger.known_at(2).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn submitted_blocks_need_a_quorum() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let blocks = submit_block(ledger.clone(), cons.clone(), Arc::new(Config::default()));
+        let post = |block: &Block| {
+            let req = warp::test::request().method("POST").path("/gcl/block").json(block);
+            req.reply(&blocks)
+        };
+        let mut block = {
+            let cons = cons.read().unwrap();
+            cons.propose_block(1, Hash256::ZERO, vec![tx("tx1")], "val1".to_string()).unwrap()
+        };
+        let sigs = cons.read().unwrap().sign_block(&block);
+
+        let res = post(&block).await;
+        assert_eq!(res.status(), 400);
+        assert_eq!(res.body(), "Block has 0 of the 3 signatures quorum needs");
+        // Repeats and forgeries don't make up the missing signature.
+        block.signatures = vec![sigs[0].clone(), sigs[1].clone(), sigs[1].clone()];
+        block.signatures.push(Signature {
+            sig: "forged".to_string(),
+            ..sigs[2].clone()
+        });
+        let res = post(&block).await;
+        assert_eq!(res.status(), 400);
+        assert_eq!(res.body(), "Block has 2 of the 3 signatures quorum needs");
+        assert!(ledger.read().unwrap().is_empty());
+
+        block.signatures = sigs;
+        assert_eq!(post(&block).await.status(), 200);
+        assert_eq!(ledger.read().unwrap().tip_height(), 1);
+    }
+
+    #[tokio::test]
+    async fn resubmitted_blocks_are_a_no_op() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let block = {
+            let cons = cons.read().unwrap();
+            let mut block =
+                cons.propose_block(1, Hash256::ZERO, vec![tx("tx1")], "val1".to_string()).unwrap();
+            block.signatures = cons.sign_block(&block);
+            block
+        };
+        let post = |config: Config| {
+            let blocks = submit_block(ledger.clone(), cons.clone(), Arc::new(config));
+            let req = warp::test::request().method("POST").path("/gcl/block").json(&block);
+            async move { req.reply(&blocks).await }
+        };
+        let res = post(Config::default()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body(), "Block 1 appended");
+        let mut events = ledger.read().unwrap().subscribe();
+
+        for _ in 0..3 {
+            let res = post(Config::default()).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.body(), "Block 1 already known");
+        }
+        assert_eq!(ledger.read().unwrap().len(), 1);
+        assert!(events.try_recv().is_err());
+        // A later round would fail the proposer check, had it run.
+        cons.write().unwrap().view_change(1);
+        assert_eq!(post(Config::default()).await.status(), 200);
+
+        let off = Config {
+            dedup_blocks: false,
+            ..Config::default()
+        };
+        assert_eq!(post(off).await.status(), 400);
+        assert_eq!(ledger.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn quorum_progress_counts_signatures_as_they_arrive() {
+        let cons = consensus();
+        let progress = quorum_progress(cons.clone());
+        let mut block = {
+            let cons = cons.read().unwrap();
+            cons.propose_block(1, Hash256::ZERO, vec![tx("tx1")], "val1".to_string()).unwrap()
+        };
+        let sigs = cons.read().unwrap().sign_block(&block);
+        let check = |block: &Block| {
+            let req = warp::test::request().method("POST").path("/gcl/quorum").json(block);
+            let progress = progress.clone();
+            async move {
+                let res = req.reply(&progress).await;
+                serde_json::from_slice::<QuorumProgress>(res.body()).unwrap()
+            }
+        };
+        let at = |got, reached| QuorumProgress {
+            got,
+            needed: 3,
+            reached,
+        };
+
+        assert_eq!(check(&block).await, at(0, false));
+        block.signatures.push(sigs[0].clone());
+        // A repeat and a forgery add nothing.
+        block.signatures.push(sigs[0].clone());
+        block.signatures.push(Signature {
+            sig: "forged".to_string(),
+            ..sigs[1].clone()
+        });
+        assert_eq!(check(&block).await, at(1, false));
+        block.signatures.push(sigs[1].clone());
+        assert_eq!(check(&block).await, at(2, false));
+        block.signatures.push(sigs[2].clone());
+        assert_eq!(check(&block).await, at(3, true));
+    }
+
+    #[tokio::test]
+    async fn proposer_timeout_moves_the_height_to_round_one() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        let info = get_chain_info(ledger.clone(), cons.clone());
+        let round = || async {
+            let res = warp::test::request().path("/gcl/chain/info").reply(&info).await;
+            serde_json::from_slice::<ChainInfo>(res.body()).unwrap().round
+        };
+        assert_eq!(round().await, 0);
+        let stale = {
+            let cons = cons.read().unwrap();
+            cons.propose_block(1, Hash256::ZERO, Vec::new(), "val1".to_string()).unwrap()
+        };
+
+        // val1 never produced height 1.
+        assert_eq!(cons.write().unwrap().view_change(1), 1);
+        assert_eq!(round().await, 1);
+        assert_eq!(cons.read().unwrap().proposer_for(1).unwrap().id, "val2");
+        assert_eq!(
+            cons.read().unwrap().check_proposer(&stale.header),
+            Err(ProposerError::StaleRound { height: 1, round: 0, current: 1 })
+        );
+
+        mempool.lock().unwrap().insert(tx("tx1")).unwrap();
+        assert_eq!(propose_pending(&ledger, &mempool, &cons).unwrap(), Some(1));
+        let tip = ledger.read().unwrap().tip().unwrap().clone();
+        assert_eq!((tip.header.round, tip.header.proposer.as_str()), (1, "val2"));
+        let mut round_zero = Block::clone(&tip);
+        round_zero.header.round = 0;
+        assert_ne!(hash_block(&tip), hash_block(&round_zero));
+        assert_eq!(round().await, 0);
+
+        let mut ahead = Block::clone(&tip);
+        ahead.header.height = 2;
+        assert!(matches!(
+            cons.read().unwrap().check_proposer(&ahead.header),
+            Err(ProposerError::FutureRound { round: 1, current: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn large_reads_do_not_block_writers_while_serializing() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        for height in 1..=100 {
+            let txs = (0..300)
+                .map(|i| Transaction {
+                    payload: "x".repeat(200),
+                    ..tx(&format!("tx{}-{}", height, i))
+                })
+                .collect();
+            let mut ledger = ledger.write().unwrap();
+            let block = cons.read().unwrap().propose_block(
+                height,
+                ledger.tip_hash(),
+                txs,
+                "val1".to_string(),
+            );
+            ledger.append(block.unwrap()).unwrap();
+        }
+        let config = Config {
+            max_sync_bytes: usize::MAX,
+            ..Config::default()
+        };
+        let blocks = get_blocks(ledger.clone(), Arc::new(config));
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let writer = {
+            let (ledger, done) = (ledger.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut longest_wait = std::time::Duration::ZERO;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let started = std::time::Instant::now();
+                    drop(ledger.write().unwrap());
+                    longest_wait = longest_wait.max(started.elapsed());
+                    std::thread::sleep(std::time::Duration::from_micros(100));
+                }
+                longest_wait
+            })
+        };
+        let started = std::time::Instant::now();
+        let res = warp::test::request().path("/gcl/blocks").reply(&blocks).await;
+        let elapsed = started.elapsed();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        let longest_wait = writer.join().unwrap();
+
+        let page: Vec<Block> = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(page.len(), 100);
+        assert!(longest_wait * 2 < elapsed, "writer waited {:?} of {:?}", longest_wait, elapsed);
+    }
+
+    #[tokio::test]
+    async fn sync_pages_stay_under_the_byte_cap() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for i in 1..=7 {
+            submit(&mempool, &format!("tx{}", i)).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        // Sizes vary by a few bytes (timestamps drop zero fractions), so three of the smallest
+        // blocks plus the brackets never fit.
+        let smallest = ledger
+            .read()
+            .unwrap()
+            .blocks()
+            .iter()
+            .map(|block| serde_json::to_vec(&**block).unwrap().len())
+            .min()
+            .unwrap();
+        let config = Arc::new(Config {
+            max_sync_bytes: smallest * 3,
+            ..Config::default()
+        });
+        let blocks = get_blocks(ledger.clone(), config.clone());
+
+        let mut from = Some(1);
+        let mut synced = Vec::new();
+        while let Some(height) = from {
+            let path = format!("/gcl/blocks?from={}", height);
+            let res = warp::test::request().path(&path).reply(&blocks).await;
+            assert!(res.body().len() <= config.max_sync_bytes, "{}", res.body().len());
+            from = res.headers().get("x-next-from").map(|h| h.to_str().unwrap().parse().unwrap());
+            let page: Vec<Block> = serde_json::from_slice(res.body()).unwrap();
+            assert!(!page.is_empty() && page.len() < 3, "{}", page.len());
+            synced.extend(page.into_iter().map(|block| block.header.height));
+        }
+        assert_eq!(synced, (1..=7).collect::<Vec<_>>());
+
+        // A block bigger than the cap still goes out, alone.
+        let tiny = Arc::new(Config {
+            max_sync_bytes: 1,
+            ..Config::default()
+        });
+        let res = warp::test::request().path("/gcl/blocks").reply(&get_blocks(ledger, tiny)).await;
+        assert_eq!(serde_json::from_slice::<Vec<Block>>(res.body()).unwrap().len(), 1);
+        assert_eq!(res.headers()["x-next-from"], "2");
+    }
+
+    #[tokio::test]
+    async fn huge_pagination_params_saturate() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let config = Arc::new(Config {
+            max_blocks_per_request: 2,
+            ..Config::default()
+        });
+        let blocks = get_blocks(ledger.clone(), config);
+        let page = |path: String| {
+            let blocks = blocks.clone();
+            async move {
+                let res = warp::test::request().path(&path).reply(&blocks).await;
+                assert_eq!(res.status(), 200, "{}", path);
+                serde_json::from_slice::<Vec<Block>>(res.body()).unwrap().len()
+            }
+        };
+        assert_eq!(page(format!("/gcl/blocks?from={}", u64::MAX)).await, 0);
+        assert_eq!(page(format!("/gcl/blocks?from={}&limit={}", u64::MAX, u64::MAX)).await, 0);
+        assert_eq!(page(format!("/gcl/blocks?from={}0", u64::MAX)).await, 0);
+        assert_eq!(page("/gcl/blocks?from=4".to_string()).await, 0);
+        assert_eq!(page(format!("/gcl/blocks?limit={}", u64::MAX)).await, 2);
+        assert_eq!(page(format!("/gcl/blocks?from=2&limit={}", "9".repeat(40))).await, 2);
+        assert_eq!(page("/gcl/blocks?from=3&limit=100".to_string()).await, 1);
+
+        for path in ["/gcl/blocks?from=-1", "/gcl/blocks?limit=-5", "/gcl/blocks?from=1e3"] {
+            let res = warp::test::request().path(path).reply(&blocks).await;
+            assert_eq!(res.status(), 400, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn backdated_transactions_are_rejected() {
+        let genesis = "2024-01-01T00:00:00Z".parse().unwrap();
+        let ledger = Ledger::new().with_genesis_timestamp(Some(genesis));
+        let ledger: SharedLedger = Arc::new(RwLock::new(ledger));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(Config::default()));
+        for (id, valid_until, status) in [
+            ("tx1", "2023-06-01T00:00:00Z", 400),
+            ("tx2", "2024-06-01T00:00:00Z", 202),
+        ] {
+            let tx = Transaction {
+                valid_until: Some(valid_until.parse().unwrap()),
+                ..tx(id)
+            };
+            let res = warp::test::request()
+                .method("POST")
+                .path("/gcl/tx")
+                .json(&tx)
+                .reply(&filter)
+                .await;
+            assert_eq!(res.status(), status);
+        }
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_negotiate_json_or_msgpack() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+        }
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let routes = routes(ledger.clone(), mempool, consensus(), Arc::new(Config::default()));
+        let fetch = |path: &'static str, accept: &'static str| {
+            let routes = routes.clone();
+            async move {
+                let res = warp::test::request()
+                    .path(path)
+                    .header("accept", accept)
+                    .reply(&routes)
+                    .await;
+                (res.headers()["content-type"].to_str().unwrap().to_string(), res.into_body())
+            }
+        };
+        let block = Block::clone(ledger.read().unwrap().get(1).unwrap());
+        for path in ["/gcl/block/1", "/gcl/head"] {
+            let (kind, body) = fetch(path, "application/json").await;
+            assert_eq!(kind, "application/json");
+            assert_eq!(serde_json::from_slice::<Block>(&body).unwrap(), block);
+            let (kind, body) = fetch(path, "application/msgpack").await;
+            assert_eq!(kind, "application/msgpack");
+            assert_eq!(rmp_serde::from_slice::<Block>(&body).unwrap(), block);
+        }
+        let (_, body) = fetch("/gcl/blocks", "application/msgpack").await;
+        assert_eq!(rmp_serde::from_slice::<Vec<Block>>(&body).unwrap(), vec![block.clone()]);
+
+        let (_, json) = fetch("/gcl/proof/tx2", "*/*").await;
+        let (_, msgpack) = fetch("/gcl/proof/tx2", "application/msgpack").await;
+        let proof: MerkleProof = serde_json::from_slice(&json).unwrap();
+        assert_eq!(rmp_serde::from_slice::<MerkleProof>(&msgpack).unwrap(), proof);
+        for _ in 0..2 {
+            let (kind, binary) = fetch("/gcl/proof/tx2", PROOF_BINARY).await;
+            assert_eq!(kind, PROOF_BINARY);
+            assert_eq!(decode_proof_binary(&binary).unwrap(), proof);
+        }
+        let leaf = hash_transaction(&tx("tx2"));
+        assert!(verify_merkle_proof(&leaf, &proof, &block.header.merkle_root));
+        let header = Format::MsgPack.encode(&block.header);
+        assert_eq!(rmp_serde::from_slice::<Header>(&header).unwrap(), block.header);
+    }
+
+    #[tokio::test]
+    async fn pretty_responses_parse_to_the_same_value() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let routes = routes(ledger, mempool, consensus(), Arc::new(Config::default()));
+        let fetch = |path: String| {
+            let routes = routes.clone();
+            async move { warp::test::request().path(&path).reply(&routes).await.into_body() }
+        };
+        for path in ["/gcl/block/1", "/gcl/stats"] {
+            let compact = fetch(path.to_string()).await;
+            assert!(!compact.contains(&b'\n'), "{}", path);
+            let pretty = fetch(format!("{}?pretty=true", path)).await;
+            assert!(String::from_utf8_lossy(&pretty).contains("\n  \""), "{}", path);
+            let value = |body: &[u8]| serde_json::from_slice::<serde_json::Value>(body).unwrap();
+            assert_eq!(value(&pretty), value(&compact));
+        }
+        assert!(!fetch("/gcl/block/1?pretty=maybe".to_string()).await.contains(&b'\n'));
+    }
+
+    #[tokio::test]
+    async fn slow_reads_time_out() {
+        // Stands in for a verification that would run far past the deadline.
+        let held = Arc::new(());
+        let work = {
+            let held = held.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+                drop(held);
+                warp::reply()
+            }
+        };
+        let started = std::time::Instant::now();
+        let reply = within(std::time::Duration::from_millis(20), work).await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), 503);
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"error":"timeout"}"#);
+        assert_eq!(Arc::strong_count(&held), 1);
+
+        let quick = within(std::time::Duration::from_secs(5), async { warp::reply() }).await;
+        assert_eq!(warp::Reply::into_response(quick).status(), 200);
+    }
+
+    #[tokio::test]
+    async fn handler_panics_answer_500() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let api = routes(ledger, mempool, consensus(), Arc::new(Config::default()));
+        let boom = warp::path!("boom").map(|| -> String { panic!("handler bug") });
+        let service = catch_panics(warp::service(boom.or(api)));
+        let get = |path: &str| {
+            let request = warp::http::Request::get(path).body(Default::default()).unwrap();
+            service(request)
+        };
+
+        let response = get("/boom").await.unwrap();
+        assert_eq!(response.status(), 500);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal server error");
+        assert_eq!(get("/gcl/stats").await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn a_panic_holding_the_ledger_lock_leaves_it_usable() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let api = routes(ledger.clone(), mempool, consensus(), Arc::new(Config::default()));
+        let boom = warp::path!("boom").and(with_ledger(ledger.clone()));
+        let boom = boom.map(|ledger: SharedLedger| -> String {
+            let _guard = ledger.write().unwrap();
+            panic!("handler bug with the ledger locked")
+        });
+        let service = catch_panics(warp::service(boom.or(api)));
+        let get = |path: &str| {
+            let request = warp::http::Request::get(path).body(Default::default()).unwrap();
+            service(request)
+        };
+
+        assert_eq!(get("/boom").await.unwrap().status(), 500);
+        assert!(ledger.is_poisoned());
+        assert_eq!(get("/gcl/stats").await.unwrap().status(), 200);
+        assert_eq!(get("/gcl/blocks").await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_cut_off_at_the_timeout() {
+        let slow = warp::path!("slow").then(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            "done"
+        });
+        let quick = warp::path!("quick").map(|| "done");
+        let service = catch_panics(warp::service(slow.or(quick)));
+        let timeout = std::time::Duration::from_millis(50);
+        let bounded = with_timeout(service.clone(), timeout);
+        let get = |path: &str| warp::http::Request::get(path).body(Default::default()).unwrap();
+
+        let started = std::time::Instant::now();
+        let response = bounded(get("/slow")).await.unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= timeout && elapsed < std::time::Duration::from_secs(5));
+        assert_eq!(response.status(), 503);
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"error":"timeout"}"#);
+        assert_eq!(bounded(get("/quick")).await.unwrap().status(), 200);
+
+        let unbounded = with_timeout(service, std::time::Duration::ZERO);
+        let pending = tokio::time::timeout(timeout * 2, unbounded(get("/slow"))).await;
+        assert!(pending.is_err());
+    }
+
+    // A gzip member holding `data` in one stored block.
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 1];
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[tokio::test]
+    async fn gzip_bodies_are_inflated_within_bounds() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Arc::new(Config::default());
+        let api = routes(ledger.clone(), mempool.clone(), consensus(), config.clone());
+        let service = decompress_requests(catch_panics(warp::service(api)), config);
+        let post = |encoding: &str, body: Vec<u8>| {
+            let request = warp::http::Request::post("/rpc")
+                .header("content-type", "application/json")
+                .header("content-encoding", encoding)
+                .body(body.into())
+                .unwrap();
+            let responding = service(request);
+            async move {
+                let response = responding.await.unwrap();
+                let status = response.status();
+                let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        let batch: Vec<_> = (1..=3)
+            .map(|i| {
+                serde_json::json!({ "jsonrpc": "2.0", "method": "gcl_submitTx",
+                    "params": [tx(&format!("tx{}", i))], "id": i })
+            })
+            .collect();
+        let (status, res) = post("gzip", gzip(&serde_json::to_vec(&batch).unwrap())).await;
+        assert_eq!(status, 200);
+        assert_eq!(res[2]["result"]["tx_id"], "tx3");
+        assert_eq!(mempool.lock().unwrap().len(), 3);
+
+        // 10,000 zeros from 46 bytes is past the default ratio of 100.
+        let bomb = "1f8b08000000000000ffedc1010d000000c2a04aef9fce1c6e40010000000000000000c0bf\
+            016958180a10270000";
+        let bomb = (0..bomb.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&bomb[i..i + 2], 16).unwrap())
+            .collect();
+        let (status, res) = post("gzip", bomb).await;
+        assert_eq!(status, 413);
+        assert_eq!(res["error"], "decompressed body would exceed 4600 bytes");
+
+        let mut damaged = gzip(b"[]");
+        damaged[12] ^= 0xff;
+        let (status, res) = post("gzip", damaged).await;
+        assert_eq!(status, 400);
+        assert!(res["error"].as_str().unwrap().starts_with("malformed gzip body"));
+        let (status, _) = post("br", gzip(b"[]")).await;
+        assert_eq!(status, 415);
+        assert_eq!(mempool.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn origin_lists_gate_submissions() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Arc::new(Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        });
+        let routes = routes(ledger, mempool.clone(), consensus(), config);
+        let submit_from = |id: &str, origin: &str| {
+            let tx = Transaction {
+                origin: origin.to_string(),
+                ..tx(id)
+            };
+            warp::test::request().method("POST").path("/gcl/tx").json(&tx).reply(&routes)
+        };
+        let set_policy = |token: &str, policy: serde_json::Value| {
+            warp::test::request()
+                .method("PUT")
+                .path("/gcl/admin/origins")
+                .header("authorization", format!("Bearer {}", token))
+                .json(&policy)
+                .reply(&routes)
+        };
+
+        let denied = serde_json::json!({ "denylist": ["mallory"] });
+        assert_eq!(set_policy("wrong", denied.clone()).await.status(), 401);
+        assert_eq!(set_policy("secret", denied).await.status(), 200);
+        let res = submit_from("tx1", "mallory").await;
+        assert_eq!(res.status(), 403);
+        assert_eq!(res.body(), r#"origin "mallory" is denied"#);
+        assert_eq!(submit_from("tx2", "user1").await.status(), 202);
+
+        let allowed = serde_json::json!({
+            "allowlist": ["user1", "mallory"],
+            "denylist": ["mallory"],
+        });
+        assert_eq!(set_policy("secret", allowed).await.status(), 200);
+        assert_eq!(submit_from("tx3", "user1").await.status(), 202);
+        assert_eq!(submit_from("tx4", "user2").await.status(), 403);
+        assert_eq!(submit_from("tx5", "mallory").await.status(), 403);
+        assert_eq!(mempool.lock().unwrap().len(), 2);
+
+        let read = warp::test::request()
+            .path("/gcl/admin/origins")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        let policy: OriginPolicy = serde_json::from_slice(read.body()).unwrap();
+        assert_eq!(policy, *mempool.lock().unwrap().origin_policy());
+        assert!(policy.allowlist.unwrap().contains("user1"));
+        let closed = get_origin_policy(mempool, Arc::new(Config::default()));
+        let res = warp::test::request().path("/gcl/admin/origins").reply(&closed).await;
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn read_only_replica_rejects_writes() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Arc::new(Config {
+            read_only: true,
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        });
+        let cons = consensus();
+        let routes = routes(ledger, mempool.clone(), cons.clone(), config);
+        for path in ["/gcl/tx", "/gcl/block", "/gcl/slashing/report"] {
+            let res = warp::test::request().method("POST").path(path).json(&tx("tx1"));
+            assert_eq!(res.reply(&routes).await.status(), 405, "{}", path);
+        }
+        assert!(mempool.lock().unwrap().is_empty());
+        let policy = OriginPolicy {
+            allowlist: Some(["user1".to_string()].into()),
+            ..OriginPolicy::default()
+        };
+        let admin = warp::test::request()
+            .method("PUT")
+            .path("/gcl/admin/origins")
+            .header("authorization", "Bearer secret")
+            .json(&policy);
+        assert_eq!(admin.reply(&routes).await.status(), 405);
+        assert_eq!(*mempool.lock().unwrap().origin_policy(), OriginPolicy::default());
+        let batch = warp::test::request()
+            .method("POST")
+            .path("/gcl/proofs")
+            .json(&serde_json::json!({ "tx_ids": ["tx1"] }));
+        assert_eq!(batch.reply(&routes).await.status(), 200);
+        let block = {
+            let cons = cons.read().unwrap();
+            cons.propose_block(1, Hash256::ZERO, vec![tx("tx1")], "val1".to_string()).unwrap()
+        };
+        let quorum = warp::test::request().method("POST").path("/gcl/quorum").json(&block);
+        assert_eq!(quorum.reply(&routes).await.status(), 200);
+        let blocks = warp::test::request().path("/gcl/blocks").reply(&routes).await;
+        assert_eq!(blocks.status(), 200);
+        let missing = warp::test::request().path("/gcl/block/1").reply(&routes).await;
+        assert_eq!(missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn batched_proofs_share_one_multiproof_per_block() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+        }
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        submit(&mempool, "tx4").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/gcl/proofs")
+            .json(&serde_json::json!({ "tx_ids": ["tx1", "tx3", "tx4", "nope"] }))
+            .reply(&batch_proofs(ledger.clone(), Arc::new(Config::default())))
+            .await;
+        assert_eq!(res.status(), 200);
+        let batch: BatchProofs = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(batch.proofs["tx1"], BatchProofEntry::Found { height: 1, index: 0 });
+        assert_eq!(batch.proofs["tx3"], BatchProofEntry::Found { height: 1, index: 2 });
+        assert_eq!(batch.proofs["tx4"], BatchProofEntry::Found { height: 2, index: 0 });
+        assert!(matches!(batch.proofs["nope"], BatchProofEntry::Failed { .. }));
+
+        let ledger = ledger.read().unwrap();
+        let block = ledger.get(1).unwrap();
+        let proof = &batch.multiproofs[&1];
+        assert_eq!(proof.indices, [0, 2]);
+        // One sibling covers both: tx3 pairs with itself and tx1's parent
+        // is then tx3's sibling.
+        assert_eq!(proof.hashes.len(), 1);
+        let leaves = [0, 2].map(|i| hash_transaction(&block.txs[i]));
+        assert!(verify_merkle_multiproof(&leaves, proof, &block.header.merkle_root));
+        assert_eq!(batch.multiproofs[&2].indices, [0]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn proof_bursts_leave_the_runtime_responsive() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let cons = consensus();
+        let mut tx_ids = Vec::new();
+        for height in 1..=4 {
+            let txs: Vec<Transaction> =
+                (0..2_000).map(|i| tx(&format!("tx{}-{}", height, i))).collect();
+            tx_ids.extend(txs.iter().step_by(47).map(|tx| tx.tx_id.clone()));
+            let mut ledger = ledger.write().unwrap();
+            let tip = ledger.tip_hash();
+            let block = cons.read().unwrap().propose_block(height, tip, txs, "val1".to_string());
+            ledger.append(block.unwrap()).unwrap();
+        }
+        let config = Config {
+            proof_workers: 2,
+            ..Config::default()
+        };
+        let proofs = batch_proofs(ledger.clone(), Arc::new(config));
+        let info = get_chain_info(ledger.clone(), cons);
+        let body = serde_json::json!({ "tx_ids": tx_ids });
+
+        let burst: Vec<_> = (0..16)
+            .map(|_| {
+                let (proofs, body) = (proofs.clone(), body.clone());
+                tokio::spawn(async move {
+                    let request = warp::test::request().method("POST").path("/gcl/proofs");
+                    request.json(&body).reply(&proofs).await
+                })
+            })
+            .collect();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        for _ in 0..5 {
+            let started = std::time::Instant::now();
+            let res = warp::test::request().path("/gcl/chain/info").reply(&info).await;
+            assert_eq!(res.status(), 200);
+            let waited = started.elapsed();
+            assert!(waited < std::time::Duration::from_millis(200), "waited {:?}", waited);
+        }
+        for res in burst {
+            let res = res.await.unwrap();
+            assert_eq!(res.status(), 200);
+            let batch: BatchProofs = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(batch.multiproofs.len(), 4);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn requests_over_max_connections_get_503() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Arc::new(Config {
+            max_connections: 2,
+            ..Config::default()
+        });
+        let routes = routes(ledger.clone(), mempool, consensus(), config);
+        let head = |routes| async move {
+            warp::test::request().path("/gcl/head").reply(&routes).await.status().as_u16()
+        };
+
+        // Two reads stall behind a writer holding the ledger, using up
+        // both permits.
+        let (locked, held) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            let _guard = ledger.write().unwrap();
+            locked.send(()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(300));
+        });
+        held.recv().unwrap();
+        let in_flight = [tokio::spawn(head(routes.clone())), tokio::spawn(head(routes.clone()))];
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(head(routes.clone()).await, 503);
+
+        for request in in_flight {
+            assert_eq!(request.await.unwrap(), 404);
+        }
+        writer.join().unwrap();
+        assert_eq!(head(routes).await, 404);
+    }
+
+    #[tokio::test]
+    async fn submissions_need_work_at_the_current_difficulty() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new().with_pow_difficulty(8)));
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(Config::default()));
+        let post = |tx: Transaction| {
+            let filter = filter.clone();
+            async move {
+                let res = warp::test::request().method("POST").path("/gcl/tx").json(&tx);
+                res.reply(&filter).await.status().as_u16()
+            }
+        };
+        assert_eq!(post(tx("tx1")).await, 400);
+        let mut solved = tx("tx1");
+        solved.nonce_pow = Some(solve_pow(&solved, 8));
+        assert_eq!(post(solved.clone()).await, 202);
+
+        let found = leading_zero_bits(&pow_hash(&solved, solved.nonce_pow.unwrap()));
+        mempool.lock().unwrap().set_pow_difficulty(found + 1);
+        assert_eq!(post(solved).await, 400);
+        mempool.lock().unwrap().set_pow_difficulty(0);
+        assert_eq!(post(tx("tx3")).await, 202);
+    }
+
+    #[tokio::test]
+    async fn signers_are_the_validators_counted_for_the_block() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = four_validators();
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+
+        let signers = get_block_signers(ledger.clone(), cons.clone());
+        let fetch = |height: u64| {
+            let signers = signers.clone();
+            async move {
+                let path = format!("/gcl/block/{}/signers", height);
+                let res = warp::test::request().path(&path).reply(&signers).await;
+                assert_eq!(res.status(), 200);
+                serde_json::from_slice::<serde_json::Value>(res.body()).unwrap()
+            }
+        };
+        let first = fetch(1).await;
+        assert_eq!(first["signers"], serde_json::json!(["val1", "val2", "val3", "val4"]));
+        assert_eq!(first["quorum"], "count");
+        cons.write().unwrap().slashing.jail("val2", Offense::InvalidSignature { height: 1 });
+        submit(&mempool, "tx2").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        assert_eq!(fetch(2).await["signers"], serde_json::json!(["val1", "val3", "val4"]));
+        // val2's stored signature on block 1 no longer counts either.
+        assert_eq!(fetch(1).await["signers"], serde_json::json!(["val1", "val3", "val4"]));
+
+        let block = ledger.read().unwrap().get(2).cloned().unwrap();
+        let verified = {
+            let cons = cons.read().unwrap();
+            block.signatures.iter().all(|sig| cons.verify_signature(sig))
+        };
+        assert!(verified);
+        let missing = warp::test::request().path("/gcl/block/3/signers").reply(&signers).await;
+        assert_eq!(missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn epoch_reports_the_set_for_the_next_block() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        cons.write().unwrap().epoch_length = 2;
+        let epoch = get_epoch(ledger.clone(), cons.clone());
+        let fetch = || {
+            let epoch = epoch.clone();
+            async move {
+                let res = warp::test::request().path("/gcl/epoch").reply(&epoch).await;
+                serde_json::from_slice::<EpochInfo>(res.body()).unwrap()
+            }
+        };
+        let info = fetch().await;
+        assert_eq!((info.epoch, info.start_height, info.next_boundary), (0, 1, Some(3)));
+        assert_eq!(info.validators, ["val1", "val2", "val3"]);
+
+        let rotated = cons.read().unwrap().validators[1..].to_vec();
+        cons.write().unwrap().queue_validators(rotated).unwrap();
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        let info = fetch().await;
+        assert_eq!(info.epoch, 0);
+        assert_eq!(info.pending_validators, Some(vec!["val2".to_string(), "val3".to_string()]));
+
+        for id in ["tx2", "tx3"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+        }
+        let info = fetch().await;
+        assert_eq!((info.epoch, info.start_height, info.next_boundary), (1, 3, Some(5)));
+        assert_eq!(info.validators, ["val2", "val3"]);
+        assert_eq!(info.pending_validators, None);
+        assert_eq!(ledger.read().unwrap().get(3).unwrap().header.proposer, "val2");
+    }
+
+    #[tokio::test]
+    async fn block_at_maps_times_to_heights() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let cons = consensus();
+        let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(MockClock::new(start));
+        cons.write().unwrap().clock = clock.clone();
+        for id in ["tx1", "tx2"] {
+            submit(&mempool, id).await;
+            propose_pending(&ledger, &mempool, &cons).unwrap();
+            clock.advance(chrono::Duration::minutes(1));
+        }
+        let at = get_block_at(ledger.clone());
+        let fetch = |time: &'static str| {
+            let at = at.clone();
+            async move {
+                let path = format!("/gcl/block/at?time={}", time);
+                warp::test::request().path(&path).reply(&at).await
+            }
+        };
+        for (time, height) in [
+            ("2024-01-01T00:00:00Z", 1),
+            ("2024-01-01T00:00:30Z", 1),
+            ("2024-01-01T00:01:00Z", 2),
+            ("2024-06-01T00:00:00Z", 2),
+        ] {
+            let res = fetch(time).await;
+            assert_eq!(res.status(), 200, "{}", time);
+            let block: Block = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(block.header.height, height, "{}", time);
+        }
+        assert_eq!(fetch("2023-12-31T23:59:59Z").await.status(), 404);
+        assert_eq!(fetch("yesterday").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drain_stops_at_the_deadline() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool = Mempool::new().with_per_origin_limit(1);
+        let mempool: SharedMempool = Arc::new(Mutex::new(mempool));
+        let cons = consensus();
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+        }
+        let past = std::time::Instant::now();
+        assert_eq!(drain(&ledger, &mempool, &cons, past), 3);
+        assert_eq!(ledger.read().unwrap().tip_height(), 0);
+
+        let later = past + std::time::Duration::from_secs(60);
+        assert_eq!(drain(&ledger, &mempool, &cons, later), 0);
+        assert_eq!(ledger.read().unwrap().tip_height(), 3);
+    }
+
+    #[tokio::test]
+    async fn submissions_pass_the_rule_for_their_type() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let rules = TxValidator::default().with_fallback(|_| Err("unsupported".to_string()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new().with_tx_validator(rules)));
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(Config::default()));
+        for (id, tx_type, payload, status) in [
+            ("tx1", "transfer", r#"{"to":"user2"}"#, 400),
+            ("tx2", "deploy", r#"{"code":"contract"}"#, 202),
+            ("tx3", "deploy", "{}", 400),
+            ("tx4", "vote", "yes", 400),
+        ] {
+            let tx = Transaction {
+                tx_type: tx_type.to_string(),
+                payload: payload.to_string(),
+                ..tx(id)
+            };
+            let res = warp::test::request().method("POST").path("/gcl/tx").json(&tx);
+            assert_eq!(res.reply(&filter).await.status(), status, "{}", id);
+        }
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn omitted_ids_are_derived_from_the_content() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Arc::new(Config::default());
+        let filter = submit_tx(ledger.clone(), mempool.clone(), consensus(), config);
+        let post = |body: serde_json::Value| {
+            let res = warp::test::request().method("POST").path("/gcl/tx").json(&body);
+            res.reply(&filter)
+        };
+        let body = |payload: &str| {
+            serde_json::json!({
+                "tx_type": "message",
+                "origin": "user1",
+                "payload": payload,
+                "sig": "sig",
+            })
+        };
+        let id = |res: &warp::http::Response<warp::hyper::body::Bytes>| {
+            res.headers()["x-tx-id"].to_str().unwrap().to_string()
+        };
+
+        let first = post(body("data")).await;
+        assert_eq!(first.status(), 202);
+        let expected = derive_tx_id(&Transaction {
+            tx_id: String::new(),
+            ..tx("ignored")
+        });
+        assert_eq!(id(&first), expected);
+        assert!(String::from_utf8_lossy(first.body()).contains(&expected));
+        let retry = post(body("data")).await;
+        assert_eq!((retry.status().as_u16(), id(&retry)), (200, expected.clone()));
+        let other = post(body("other")).await;
+        assert_eq!(other.status(), 202);
+        assert_ne!(id(&other), expected);
+        let named = post(serde_json::to_value(tx(&expected)).unwrap()).await;
+        assert_eq!(named.status(), 200, "the same content under its derived id is a duplicate");
+        assert_eq!(mempool.lock().unwrap().len(), 2);
+
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let replay = post(body("data")).await;
+        assert_eq!((replay.status().as_u16(), id(&replay)), (200, expected));
+        assert!(String::from_utf8_lossy(replay.body()).contains("already committed"));
+        assert!(mempool.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tx_ids_are_bounded_and_url_safe() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Config {
+            max_tx_id_len: 16,
+            ..Config::default()
+        };
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(config));
+        let post = |tx: Transaction| {
+            let req = warp::test::request().method("POST").path("/gcl/tx").json(&tx);
+            let filter = filter.clone();
+            async move { req.reply(&filter).await }
+        };
+        for id in ["a".repeat(17), "../tx1".to_string(), "tx 1".to_string(), "tx%2F1".into()] {
+            let res = post(tx(&id)).await;
+            assert_eq!(res.status(), 400, "{}", id);
+        }
+        let res = post(tx(&"a".repeat(17))).await;
+        assert_eq!(res.body(), "tx_id is 17 bytes; at most 16 are accepted");
+        for id in ["tx1", "a.b-c_d~e", "a".repeat(16).as_str()] {
+            assert_eq!(post(tx(id)).await.status(), 202, "{}", id);
+        }
+        // Ids the node derives are its own and always pass.
+        let derived = Transaction {
+            tx_id: String::new(),
+            ..tx("unused")
+        };
+        assert_eq!(post(derived).await.status(), 202);
+        assert_eq!(mempool.lock().unwrap().len(), 4);
+
+        let base58 = Config {
+            tx_id_charset: TxIdCharset::Base58,
+            ..Config::default()
+        };
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(base58));
+        let submit = |id: &str| {
+            let req = warp::test::request().method("POST").path("/gcl/tx").json(&tx(id));
+            req.reply(&filter)
+        };
+        assert_eq!(submit("3mJr7AoUXx2Wqd").await.status(), 202);
+        assert_eq!(submit("l0O").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn stale_transactions_are_refused() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Config {
+            max_tx_age_ms: Some(60_000),
+            ..Config::default()
+        };
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(config));
+        let now = Utc::now();
+        for (id, created_at, status) in [
+            ("stale", Some(now - chrono::Duration::minutes(5)), 400),
+            ("fresh", Some(now - chrono::Duration::seconds(5)), 202),
+            ("undated", None, 202),
+            ("ahead", Some(now + chrono::Duration::minutes(5)), 400),
+        ] {
+            let tx = Transaction {
+                created_at,
+                ..tx(id)
+            };
+            let res = warp::test::request().method("POST").path("/gcl/tx").json(&tx);
+            let res = res.reply(&filter).await;
+            assert_eq!(res.status(), status, "{}", id);
+            if id == "stale" {
+                assert!(String::from_utf8_lossy(res.body()).contains("too old"));
+            }
+        }
+        assert_eq!(mempool.lock().unwrap().len(), 2);
+        let signed = Transaction {
+            created_at: Some(now),
+            ..tx("fresh")
+        };
+        assert_ne!(hash_transaction(&signed), hash_transaction(&tx("fresh")));
+    }
+
+    #[tokio::test]
+    async fn oversized_and_malformed_payloads_are_refused() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        let config = Config {
+            max_payload_bytes: 12,
+            ..Config::default()
+        };
+        let filter = submit_tx(ledger, mempool.clone(), consensus(), Arc::new(config));
+        for (id, tx_type, payload, status) in [
+            ("tx1", "message", "thirteen byte", 413),
+            ("tx2", "blob", "aGVsbG8gd29ybGQ=", 413),
+            ("tx3", "blob", "not base64!", 400),
+            ("tx4", "blob", "aGVsbG8=", 202),
+            ("tx5", "message", "twelve bytes", 202),
+        ] {
+            let tx = Transaction {
+                tx_type: tx_type.to_string(),
+                payload: payload.to_string(),
+                ..tx(id)
+            };
+            let res = warp::test::request().method("POST").path("/gcl/tx").json(&tx);
+            assert_eq!(res.reply(&filter).await.status(), status, "{}", id);
+        }
+        assert_eq!(mempool.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn absence_proofs_verify_against_the_sorted_root() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        for id in ["tx1", "tx2", "tx3"] {
+            submit(&mempool, id).await;
+        }
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let absence = get_absence_proof(ledger.clone());
+        let res = warp::test::request().path("/gcl/proof/absence/1/tx4").reply(&absence).await;
+        assert_eq!(res.status(), 200);
+        let proof: AbsenceProof = serde_json::from_slice(res.body()).unwrap();
+        let root = ledger.read().unwrap().get(1).unwrap().header.sorted_root;
+        assert!(verify_absence("tx4", &proof, &root));
+
+        let present = warp::test::request().path("/gcl/proof/absence/1/tx2").reply(&absence).await;
+        assert_eq!(present.status(), 409);
+        let missing = warp::test::request().path("/gcl/proof/absence/2/tx4").reply(&absence).await;
+        assert_eq!(missing.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn preimage_hashes_to_the_block_hash() {
+        let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+        let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+        submit(&mempool, "tx1").await;
+        propose_pending(&ledger, &mempool, &consensus()).unwrap();
+        let preimage = get_block_preimage(ledger.clone());
+        let res = warp::test::request().path("/gcl/block/1/preimage").reply(&preimage).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "application/octet-stream");
+        let block = ledger.read().unwrap().get(1).cloned().unwrap();
+        assert_eq!(Hash256::digest(res.body()), hash_block(&block));
+        let missing = warp::test::request().path("/gcl/block/2/preimage").reply(&preimage).await;
+        assert_eq!(missing.status(), 404);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::format::Format;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Block(u64, Format),
+    Proof(String, Format),
+    BinaryProof(String),
+}
+
+// Least-recently-used cache of serialized responses for final blocks and
+// their proofs. Entries are only ever invalidated by a rollback, which shows
+// up as a new ledger generation.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    capacity: usize,
+    generation: u64,
+    entries: HashMap<CacheKey, (Vec<u8>, u64)>,
+    tick: u64,
+    hits: u64,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity,
+            ..ResponseCache::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    // Drops everything cached under an older ledger generation. Generations
+    // only move forward, so a late reader can't wipe newer entries.
+    fn sync(&mut self, generation: u64) {
+        if generation > self.generation {
+            self.entries.clear();
+            self.generation = generation;
+        }
+    }
+
+    pub fn get(&mut self, generation: u64, key: &CacheKey) -> Option<Vec<u8>> {
+        self.sync(generation);
+        self.tick += 1;
+        let (body, last_used) = self.entries.get_mut(key)?;
+        *last_used = self.tick;
+        self.hits += 1;
+        Some(body.clone())
+    }
+
+    // Responses built from an older generation than the cache has seen are
+    // already stale and are not stored.
+    pub fn insert(&mut self, generation: u64, key: CacheKey, body: Vec<u8>) {
+        self.sync(generation);
+        if self.capacity == 0 || generation < self.generation {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(oldest) = oldest.map(|(key, _)| key.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key, (body, self.tick));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let mut cache = ResponseCache::new(2);
+        let block = |height| CacheKey::Block(height, Format::Json);
+        cache.insert(0, block(1), b"one".to_vec());
+        cache.insert(0, block(2), b"two".to_vec());
+        assert_eq!(cache.get(0, &block(1)).as_deref(), Some(&b"one"[..]));
+        cache.insert(0, CacheKey::Proof("tx3".to_string(), Format::Json), b"three".to_vec());
+        assert!(cache.get(0, &block(2)).is_none());
+        assert!(cache.get(0, &block(1)).is_some());
+        assert!(cache.get(0, &CacheKey::Block(1, Format::MsgPack)).is_none());
+        assert_eq!(cache.hits(), 2);
+
+        assert!(cache.get(1, &block(1)).is_none());
+        assert!(cache.is_empty());
+        cache.insert(0, block(1), b"stale".to_vec());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use crate::absence::sorted_root;
+use crate::consensus::Consensus;
+use crate::hash::Hash256;
+use crate::merkle::{MerkleError, merkle_root};
+use crate::types::{
+    BLOCK_VERSION, Block, COUNTS_VERSION, MILLIS_VERSION, body_totals, hash_block,
+};
+use chrono::SubsecRound;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum ChainError {
+    NonSequentialHeight { height: u64 },
+    BadLinkage { height: u64 },
+    BadMerkleRoot { height: u64 },
+    BadBlock { height: u64, source: BlockError },
+    BadTimestamp { height: u64 },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::NonSequentialHeight { height } => {
+                write!(f, "block at height {} is out of sequence", height)
+            }
+            ChainError::BadLinkage { height } => {
+                write!(f, "block {} does not link to its predecessor", height)
+            }
+            ChainError::BadMerkleRoot { height } => {
+                write!(f, "block {} has a merkle root that does not match its transactions", height)
+            }
+            ChainError::BadBlock { height, source } => {
+                write!(f, "block {} is invalid: {}", height, source)
+            }
+            ChainError::BadTimestamp { height } => {
+                write!(f, "block {} is timestamped before its predecessor", height)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    UnsupportedVersion { version: u16, supported: u16 },
+    SubMillisecondTimestamp,
+    MissingMerkleRoot,
+    MerkleRootMismatch { expected: Hash256, found: Hash256 },
+    SortedRootMismatch { expected: Hash256, found: Hash256 },
+    ValidatorSetMismatch { expected: Hash256, found: Hash256 },
+    TxCountMismatch { expected: u32, found: u32 },
+    TotalFeesMismatch { expected: u64, found: u64 },
+    Merkle(MerkleError),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::UnsupportedVersion { version, supported } => write!(
+                f,
+                "block version {} is newer than this node supports ({})",
+                version, supported
+            ),
+            BlockError::SubMillisecondTimestamp => {
+                write!(f, "block timestamp is finer than the millisecond its version hashes")
+            }
+            BlockError::MissingMerkleRoot => write!(f, "block has transactions but no merkle root"),
+            BlockError::MerkleRootMismatch { expected, found } => write!(
+                f,
+                "merkle root {} does not match the transactions (expected {})",
+                found, expected
+            ),
+            BlockError::SortedRootMismatch { expected, found } => write!(
+                f,
+                "sorted root {} does not match the transactions (expected {})",
+                found, expected
+            ),
+            BlockError::ValidatorSetMismatch { expected, found } => write!(
+                f,
+                "genesis validator set hash {} is not this network's {}",
+                found, expected
+            ),
+            BlockError::TxCountMismatch { expected, found } => write!(
+                f,
+                "header claims {} transactions but the block has {}",
+                found, expected
+            ),
+            BlockError::TotalFeesMismatch { expected, found } => write!(
+                f,
+                "header claims {} in fees but the transactions pay {}",
+                found, expected
+            ),
+            BlockError::Merkle(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Recomputes the merkle roots from the block's transactions, so a header
+// can't vouch for contents it doesn't commit to.
+pub fn validate_block(block: &Block, cons: &Consensus) -> Result<(), BlockError> {
+    let version = block.header.version;
+    if version > BLOCK_VERSION {
+        return Err(BlockError::UnsupportedVersion {
+            version,
+            supported: BLOCK_VERSION,
+        });
+    }
+    // The hash only covers whole milliseconds, so anything finer would be
+    // uncommitted.
+    let timestamp = block.header.timestamp;
+    if version >= MILLIS_VERSION && timestamp != timestamp.trunc_subsecs(3) {
+        return Err(BlockError::SubMillisecondTimestamp);
+    }
+    let found = block.header.merkle_root;
+    if found.is_zero() && !block.txs.is_empty() {
+        return Err(BlockError::MissingMerkleRoot);
+    }
+    let expected = merkle_root(&block.txs, cons.leaf_encoding, cons.max_merkle_leaves)
+        .map_err(BlockError::Merkle)?;
+    if expected != found {
+        return Err(BlockError::MerkleRootMismatch { expected, found });
+    }
+    // Blocks from before sorted roots carry none; there is nothing to check.
+    let found = block.header.sorted_root;
+    if !found.is_zero() {
+        let expected = sorted_root(&block.txs);
+        if expected != found {
+            return Err(BlockError::SortedRootMismatch { expected, found });
+        }
+    }
+    // Likewise for first blocks from before the validator set was hashed.
+    let found = block.header.validator_set_hash;
+    if block.header.height == 1 && !found.is_zero() {
+        let expected = cons.genesis_validator_set_hash();
+        if expected != found {
+            return Err(BlockError::ValidatorSetMismatch { expected, found });
+        }
+    }
+    // Older headers may leave the totals out, but any they do claim are
+    // hashed and so must hold.
+    let header = &block.header;
+    let (count, fees) = body_totals(&block.txs);
+    let claimed = version >= COUNTS_VERSION;
+    if (claimed || header.tx_count != 0) && header.tx_count != count {
+        return Err(BlockError::TxCountMismatch {
+            expected: count,
+            found: header.tx_count,
+        });
+    }
+    if (claimed || header.total_fees != 0) && header.total_fees != fees {
+        return Err(BlockError::TotalFeesMismatch {
+            expected: fees,
+            found: header.total_fees,
+        });
+    }
+    Ok(())
+}
+
+// Checks a whole chain, genesis first, without touching any ledger state.
+// Stops at the first broken block.
+pub fn validate_chain(blocks: &[Block], cons: &Consensus) -> Result<(), ChainError> {
+    let mut prev: Option<&Block> = None;
+    for (expected, block) in (1u64..).zip(blocks) {
+        let height = block.header.height;
+        if height != expected {
+            return Err(ChainError::NonSequentialHeight { height });
+        }
+        let prev_hash = prev.map(hash_block).unwrap_or_default();
+        if block.header.prev_hash != prev_hash {
+            return Err(ChainError::BadLinkage { height });
+        }
+        match validate_block(block, cons) {
+            Ok(()) => {}
+            Err(BlockError::MissingMerkleRoot | BlockError::MerkleRootMismatch { .. }) => {
+                return Err(ChainError::BadMerkleRoot { height });
+            }
+            Err(source) => return Err(ChainError::BadBlock { height, source }),
+        }
+        if prev.is_some_and(|p| block.header.timestamp < p.header.timestamp) {
+            return Err(ChainError::BadTimestamp { height });
+        }
+        prev = Some(block);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{consensus, tx};
+    use chrono::Duration;
+
+    fn chain(cons: &Consensus, len: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 1..=len {
+            let prev_hash = blocks.last().map(hash_block).unwrap_or_default();
+            let txs = vec![tx(&format!("tx{}", height))];
+            let block = cons.propose_block(height, prev_hash, txs, "val1".to_string()).unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn well_formed_chain_validates() {
+        let cons = consensus();
+        assert_eq!(validate_chain(&chain(&cons, 4), &cons), Ok(()));
+        assert_eq!(validate_chain(&[], &cons), Ok(()));
+    }
+
+    #[test]
+    fn each_defect_is_reported_at_its_height() {
+        let cons = consensus();
+
+        let mut blocks = chain(&cons, 3);
+        blocks.remove(1);
+        assert_eq!(
+            validate_chain(&blocks, &cons),
+            Err(ChainError::NonSequentialHeight { height: 3 })
+        );
+
+        let mut blocks = chain(&cons, 3);
+        blocks[1].header.prev_hash = Hash256::digest("bogus");
+        assert_eq!(validate_chain(&blocks, &cons), Err(ChainError::BadLinkage { height: 2 }));
+
+        let mut blocks = chain(&cons, 3);
+        blocks[2].txs.push(tx("extra"));
+        assert_eq!(validate_chain(&blocks, &cons), Err(ChainError::BadMerkleRoot { height: 3 }));
+
+        let mut blocks = chain(&cons, 3);
+        blocks[1].header.version = BLOCK_VERSION + 1;
+        let err = validate_chain(&blocks, &cons).unwrap_err();
+        assert_eq!(
+            err,
+            ChainError::BadBlock {
+                height: 2,
+                source: BlockError::UnsupportedVersion {
+                    version: BLOCK_VERSION + 1,
+                    supported: BLOCK_VERSION
+                }
+            }
+        );
+        assert!(err.to_string().contains("newer than this node supports"));
+
+        let mut blocks = chain(&cons, 2);
+        blocks[1].header.timestamp = blocks[0].header.timestamp - Duration::seconds(1);
+        assert_eq!(validate_chain(&blocks, &cons), Err(ChainError::BadTimestamp { height: 2 }));
+    }
+
+    #[test]
+    fn block_roots_are_recomputed() {
+        let cons = consensus();
+        let block = chain(&cons, 1).remove(0);
+        assert_eq!(validate_block(&block, &cons), Ok(()));
+
+        let mut forged = block.clone();
+        forged.header.merkle_root = chain(&cons, 2)[1].header.merkle_root;
+        assert!(matches!(
+            validate_block(&forged, &cons),
+            Err(BlockError::MerkleRootMismatch { .. })
+        ));
+
+        let mut unsorted = block.clone();
+        unsorted.header.sorted_root = Hash256::digest("bogus");
+        assert!(matches!(
+            validate_block(&unsorted, &cons),
+            Err(BlockError::SortedRootMismatch { .. })
+        ));
+        unsorted.header.sorted_root = Hash256::ZERO;
+        assert_eq!(validate_block(&unsorted, &cons), Ok(()));
+
+        let mut legacy = block.clone();
+        legacy.header.version = 0;
+        assert_eq!(validate_block(&legacy, &cons), Ok(()));
+        let mut future = block.clone();
+        future.header.version = BLOCK_VERSION + 1;
+        assert_eq!(
+            validate_block(&future, &cons),
+            Err(BlockError::UnsupportedVersion {
+                version: BLOCK_VERSION + 1,
+                supported: BLOCK_VERSION
+            })
+        );
+        let mut fine = block.clone();
+        fine.header.timestamp += Duration::nanoseconds(1);
+        assert_eq!(validate_block(&fine, &cons), Err(BlockError::SubMillisecondTimestamp));
+        fine.header.version = 1;
+        assert_eq!(validate_block(&fine, &cons), Ok(()));
+
+        let mut missing = block;
+        missing.header.merkle_root = Hash256::ZERO;
+        assert_eq!(validate_block(&missing, &cons), Err(BlockError::MissingMerkleRoot));
+    }
+
+    #[test]
+    fn header_totals_match_the_body() {
+        let cons = consensus();
+        let mut txs = vec![tx("a"), tx("b"), tx("c")];
+        txs[0].fee = 5;
+        txs[2].fee = 7;
+        let block = cons.propose_block(1, Hash256::ZERO, txs, "val1".to_string()).unwrap();
+        assert_eq!((block.header.tx_count, block.header.total_fees), (3, 12));
+        assert_eq!(validate_block(&block, &cons), Ok(()));
+        let empty = cons.propose_block(1, Hash256::ZERO, Vec::new(), "val1".to_string()).unwrap();
+        assert_eq!((empty.header.tx_count, empty.header.total_fees), (0, 0));
+        assert_eq!(validate_block(&empty, &cons), Ok(()));
+
+        let mut miscounted = block.clone();
+        miscounted.header.tx_count = 2;
+        assert_eq!(
+            validate_block(&miscounted, &cons),
+            Err(BlockError::TxCountMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_ne!(hash_block(&miscounted), hash_block(&block));
+        miscounted.header.tx_count = 0;
+        assert!(validate_block(&miscounted, &cons).is_err());
+        miscounted.header.version = COUNTS_VERSION - 1;
+        assert_eq!(validate_block(&miscounted, &cons), Ok(()));
+        miscounted.header.tx_count = 4;
+        assert!(validate_block(&miscounted, &cons).is_err());
+
+        let mut overpaid = block;
+        overpaid.header.total_fees = 13;
+        assert_eq!(
+            validate_block(&overpaid, &cons),
+            Err(BlockError::TotalFeesMismatch {
+                expected: 12,
+                found: 13
+            })
+        );
+    }
+}
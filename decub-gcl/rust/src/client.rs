@@ -0,0 +1,134 @@
+use crate::merkle::{LeafEncoding, verify_merkle_proof};
+use crate::types::{Block, MerkleProof, Transaction};
+use reqwest::StatusCode;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Transport(reqwest::Error),
+    NotFound(String),
+    NotYetFinal { height: u64 },
+    Status { status: u16, message: String },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(err) => write!(f, "request failed: {}", err),
+            ClientError::NotFound(message) => write!(f, "not found: {}", message),
+            ClientError::NotYetFinal { height } => write!(f, "block {} is not final yet", height),
+            ClientError::Status { status, message } => write!(f, "HTTP {}: {}", status, message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Transport(err)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitStatus {
+    Queued,
+    AlreadyPending,
+}
+
+// Typed wrapper over the GCL REST API for embedding in Rust programs.
+#[derive(Clone, Debug)]
+pub struct GclClient {
+    base_url: String,
+    http: reqwest::Client,
+    leaf_encoding: LeafEncoding,
+}
+
+impl GclClient {
+    pub fn new(base_url: &str) -> Self {
+        GclClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            leaf_encoding: LeafEncoding::default(),
+        }
+    }
+
+    // Must match the server's leaf_encoding for verify_proof to succeed.
+    pub fn with_leaf_encoding(mut self, leaf_encoding: LeafEncoding) -> Self {
+        self.leaf_encoding = leaf_encoding;
+        self
+    }
+
+    pub async fn submit_tx(&self, tx: &Transaction) -> Result<SubmitStatus, ClientError> {
+        let res = self.http.post(self.url("/gcl/tx")).json(tx).send().await?;
+        match res.status() {
+            StatusCode::ACCEPTED => Ok(SubmitStatus::Queued),
+            StatusCode::OK => Ok(SubmitStatus::AlreadyPending),
+            _ => Err(error_from(res).await),
+        }
+    }
+
+    pub async fn get_block(&self, height: u64) -> Result<Block, ClientError> {
+        let res = self.http.get(self.url(&format!("/gcl/block/{}", height))).send().await?;
+        match res.status() {
+            StatusCode::OK => Ok(res.json().await?),
+            _ => Err(error_from(res).await),
+        }
+    }
+
+    // Blocks from `from` upward, as many as the server returns per request.
+    pub async fn get_blocks(&self, from: u64) -> Result<Vec<Block>, ClientError> {
+        let res = self.http.get(self.url(&format!("/gcl/blocks?from={}", from))).send().await?;
+        match res.status() {
+            StatusCode::OK => Ok(res.json().await?),
+            _ => Err(error_from(res).await),
+        }
+    }
+
+    pub async fn get_head(&self) -> Result<Block, ClientError> {
+        let res = self.http.get(self.url("/gcl/head")).send().await?;
+        match res.status() {
+            StatusCode::OK => Ok(res.json().await?),
+            _ => Err(error_from(res).await),
+        }
+    }
+
+    pub async fn get_proof(&self, tx_id: &str) -> Result<MerkleProof, ClientError> {
+        let res = self.http.get(self.url(&format!("/gcl/proof/{}", tx_id))).send().await?;
+        match res.status() {
+            StatusCode::OK => Ok(res.json().await?),
+            StatusCode::ACCEPTED => {
+                let status: serde_json::Value = res.json().await?;
+                let height = status["height"].as_u64().unwrap_or_default();
+                Err(ClientError::NotYetFinal { height })
+            }
+            _ => Err(error_from(res).await),
+        }
+    }
+
+    // Fetches the proof for `tx` and checks it against the merkle root of the
+    // block at `height`, so the caller trusts only the block header.
+    pub async fn verify_proof(&self, tx: &Transaction, height: u64) -> Result<bool, ClientError> {
+        let proof = self.get_proof(&tx.tx_id).await?;
+        let block = self.get_block(height).await?;
+        let leaf = self.leaf_encoding.leaf_hash(tx);
+        Ok(verify_merkle_proof(&leaf, &proof, &block.header.merkle_root))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+async fn error_from(res: reqwest::Response) -> ClientError {
+    let status = res.status();
+    let message = res.text().await.unwrap_or_default();
+    if status == StatusCode::NOT_FOUND {
+        ClientError::NotFound(message)
+    } else {
+        ClientError::Status {
+            status: status.as_u16(),
+            message,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::consensus::{
+    DEFAULT_EPOCH_LENGTH, DEFAULT_MAX_FUTURE_SKEW_MS, DEFAULT_MAX_VALIDATORS, Threshold,
+};
+use crate::hash::Hash256;
+use crate::mempool::DEFAULT_DEPENDENCY_TIMEOUT_MS;
+use crate::merkle::LeafEncoding;
+use crate::store::StorageBackend;
+use crate::txid::{DEFAULT_MAX_TX_ID_LEN, TxIdCharset};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    OutOfRange { field: &'static str, value: u64 },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::OutOfRange { field, value } => {
+                write!(f, "{} of {} is out of range", field, value)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct GenesisConfig {
+    pub balances: HashMap<String, u64>,
+    pub timestamp: Option<DateTime<Utc>>,
+    // The network this node expects to join; it won't start with a
+    // validator set that hashes to anything else.
+    pub validator_set_hash: Option<Hash256>,
+}
+
+// PEM files for serving HTTPS directly, without a reverse proxy.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+// Node settings, read from the JSON file named by GCL_CONFIG. Every field
+// has a default so a partial (or absent) file is fine.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub tx_log_path: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub storage: StorageBackend,
+    pub compaction_interval_ms: u64,
+    pub read_only: bool,
+    pub tls: Option<TlsConfig>,
+    pub primary_url: Option<String>,
+    pub block_reward: u64,
+    pub finality_depth: u64,
+    pub max_proof_depth: u32,
+    pub block_interval_ms: u64,
+    pub shutdown_deadline_ms: u64,
+    pub clock_offset_ms: i64,
+    pub max_future_skew_ms: i64,
+    pub max_tx_age_ms: Option<u64>,
+    pub max_mempool_size: usize,
+    pub mempool_ttl_ms: Option<u64>,
+    pub state_checkpoint_interval: u64,
+    pub dependency_timeout_ms: i64,
+    pub max_payload_bytes: usize,
+    pub max_tx_id_len: usize,
+    pub tx_id_charset: TxIdCharset,
+    pub max_connections: usize,
+    pub max_txs_per_origin_per_block: Option<usize>,
+    pub pow_difficulty: u32,
+    pub rbf_min_fee_bump: u64,
+    pub fee_schedule: HashMap<String, u64>,
+    pub max_blocks_per_request: usize,
+    pub max_txs_per_request: usize,
+    pub max_rpc_batch: usize,
+    pub max_sync_bytes: usize,
+    pub read_timeout_ms: u64,
+    pub request_timeout_ms: u64,
+    pub keep_alive: bool,
+    pub request_compression: bool,
+    pub max_decompressed_bytes: usize,
+    pub max_decompression_ratio: usize,
+    pub proof_workers: usize,
+    pub response_cache_size: usize,
+    pub leaf_encoding: LeafEncoding,
+    pub proposer_allow_list: Option<BTreeSet<String>>,
+    pub origin_allowlist: Option<BTreeSet<String>>,
+    pub origin_denylist: BTreeSet<String>,
+    pub admin_token: Option<String>,
+    pub threshold: Option<Threshold>,
+    pub max_validators: usize,
+    pub dedup_blocks: bool,
+    pub debug_events: bool,
+    pub epoch_length: u64,
+    pub participation_window: usize,
+    pub min_participation: f64,
+    pub genesis: GenesisConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tx_log_path: None,
+            data_dir: None,
+            storage: StorageBackend::default(),
+            compaction_interval_ms: 600_000,
+            read_only: false,
+            tls: None,
+            primary_url: None,
+            block_reward: 10,
+            finality_depth: 0,
+            max_proof_depth: 24,
+            block_interval_ms: 1000,
+            shutdown_deadline_ms: 5000,
+            clock_offset_ms: 0,
+            max_future_skew_ms: DEFAULT_MAX_FUTURE_SKEW_MS,
+            max_tx_age_ms: None,
+            max_mempool_size: 10_000,
+            mempool_ttl_ms: None,
+            state_checkpoint_interval: 100,
+            dependency_timeout_ms: DEFAULT_DEPENDENCY_TIMEOUT_MS,
+            max_payload_bytes: 65_536,
+            max_tx_id_len: DEFAULT_MAX_TX_ID_LEN,
+            tx_id_charset: TxIdCharset::default(),
+            max_connections: 512,
+            max_txs_per_origin_per_block: None,
+            pow_difficulty: 0,
+            rbf_min_fee_bump: 1,
+            fee_schedule: HashMap::new(),
+            max_blocks_per_request: 100,
+            max_txs_per_request: 1_000,
+            max_rpc_batch: 100,
+            max_sync_bytes: 4 << 20,
+            read_timeout_ms: 10_000,
+            request_timeout_ms: 30_000,
+            keep_alive: true,
+            request_compression: true,
+            max_decompressed_bytes: 8 << 20,
+            max_decompression_ratio: 100,
+            proof_workers: 4,
+            response_cache_size: 1024,
+            leaf_encoding: LeafEncoding::default(),
+            proposer_allow_list: None,
+            origin_allowlist: None,
+            origin_denylist: BTreeSet::new(),
+            admin_token: None,
+            threshold: None,
+            max_validators: DEFAULT_MAX_VALIDATORS,
+            dedup_blocks: true,
+            debug_events: false,
+            epoch_length: DEFAULT_EPOCH_LENGTH,
+            participation_window: 100,
+            min_participation: 0.9,
+            genesis: GenesisConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let config: Config = match std::env::var_os("GCL_CONFIG") {
+            Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            None => Config::default(),
+        };
+        config.check()?;
+        Ok(config)
+    }
+
+    // Settings that parse but that the node can't act on.
+    pub fn check(&self) -> Result<(), ConfigError> {
+        if let Some(ms) = self.max_tx_age_ms {
+            tx_age(ms).ok_or(ConfigError::OutOfRange {
+                field: "max_tx_age_ms",
+                value: ms,
+            })?;
+        }
+        Ok(())
+    }
+
+    // `max_tx_age_ms` as a duration. One too long for chrono, which `check`
+    // refuses at load, is as good as no limit and saturates.
+    pub fn max_tx_age(&self) -> Option<TimeDelta> {
+        self.max_tx_age_ms.map(|ms| tx_age(ms).unwrap_or(TimeDelta::MAX))
+    }
+}
+
+fn tx_age(ms: u64) -> Option<TimeDelta> {
+    i64::try_from(ms).ok().and_then(TimeDelta::try_milliseconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_ages_past_what_chrono_holds_are_refused() {
+        let aged = |ms| Config {
+            max_tx_age_ms: Some(ms),
+            ..Config::default()
+        };
+        assert_eq!(aged(60_000).check(), Ok(()));
+        assert_eq!(aged(60_000).max_tx_age(), Some(TimeDelta::seconds(60)));
+        assert_eq!(Config::default().max_tx_age(), None);
+        let limit = TimeDelta::MAX.num_milliseconds() as u64;
+        assert_eq!(aged(limit).check(), Ok(()));
+        for ms in [limit + 1, i64::MAX as u64 + 1, u64::MAX] {
+            let config = aged(ms);
+            assert_eq!(
+                config.check(),
+                Err(ConfigError::OutOfRange {
+                    field: "max_tx_age_ms",
+                    value: ms
+                })
+            );
+            assert_eq!(config.max_tx_age(), Some(TimeDelta::MAX));
+        }
+    }
+}
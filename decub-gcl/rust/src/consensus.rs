@@ -1,63 +1,1377 @@
-use crate::types::{Block, Header, Transaction, hash_block};
-use chrono::Utc;
+use crate::absence::sorted_root;
+use crate::clock::{Clock, SystemClock};
+use crate::hash::Hash256;
+use crate::merkle::{LeafEncoding, MAX_MERKLE_LEAVES, MerkleError, merkle_root};
+use crate::slashing::{Offense, SlashError, SlashRecord, Slashing};
+use crate::types::{
+    BLOCK_VERSION, Block, Header, Participation, QuorumProgress, Signature, Transaction,
+    body_totals, hash_block,
+};
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
 
-#[derive(Clone, Debug)]
+pub type SharedConsensus = Arc<RwLock<Consensus>>;
+
+pub const DEFAULT_EPOCH_LENGTH: u64 = 100;
+pub const DEFAULT_MAX_FUTURE_SKEW_MS: i64 = 5_000;
+pub const DEFAULT_MAX_VALIDATORS: usize = 1_000;
+// Batches at least this big are verified across the rayon pool.
+const PARALLEL_VERIFY_MIN: usize = 64;
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
 pub struct Validator {
     pub id: String,
     pub pub_key: String,
+    #[cfg(feature = "byzantine")]
+    #[serde(skip)]
+    pub behavior: ByzantineBehavior,
+}
+
+impl Validator {
+    pub fn new(id: String, pub_key: String) -> Self {
+        Validator {
+            id,
+            pub_key,
+            #[cfg(feature = "byzantine")]
+            behavior: ByzantineBehavior::Honest,
+        }
+    }
+
+    #[cfg(feature = "byzantine")]
+    pub fn with_behavior(mut self, behavior: ByzantineBehavior) -> Self {
+        self.behavior = behavior;
+        self
+    }
+}
+
+// How a simulated validator signs, so safety tests can script faults.
+// Equivocate signs the block and a conflicting one at the same height;
+// Withhold sends nothing; SignInvalid sends a signature that won't verify.
+#[cfg(feature = "byzantine")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ByzantineBehavior {
+    #[default]
+    Honest,
+    Equivocate,
+    Withhold,
+    SignInvalid,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProposerError {
+    Unknown { proposer: String },
+    WrongTurn { height: u64, expected: String, proposer: String },
+    NotAllowed { proposer: String },
+    StaleRound { height: u64, round: u32, current: u32 },
+    FutureRound { height: u64, round: u32, current: u32 },
+}
+
+impl fmt::Display for ProposerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProposerError::Unknown { proposer } => write!(f, "unknown proposer {}", proposer),
+            ProposerError::WrongTurn {
+                height,
+                expected,
+                proposer,
+            } => write!(f, "height {} belongs to {}, not {}", height, expected, proposer),
+            ProposerError::NotAllowed { proposer } => {
+                write!(f, "proposer {} is not on the allow-list", proposer)
+            }
+            ProposerError::StaleRound {
+                height,
+                round,
+                current,
+            } => write!(
+                f,
+                "round {} at height {} is over; this node is in round {}",
+                round, height, current
+            ),
+            ProposerError::FutureRound {
+                height,
+                round,
+                current,
+            } => write!(
+                f,
+                "round {} at height {} is ahead of this node's round {}",
+                round, height, current
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TimestampError {
+    TooFarAhead { timestamp: DateTime<Utc>, now: DateTime<Utc> },
+}
+
+impl fmt::Display for TimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampError::TooFarAhead { timestamp, now } => write!(
+                f,
+                "block timestamp {} is too far ahead of this node's time {}",
+                timestamp.to_rfc3339(),
+                now.to_rfc3339()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TxAgeError {
+    Stale { created_at: DateTime<Utc>, now: DateTime<Utc> },
+    TooFarAhead { created_at: DateTime<Utc>, now: DateTime<Utc> },
+}
+
+impl fmt::Display for TxAgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxAgeError::Stale { created_at, now } => write!(
+                f,
+                "transaction created at {} is too old for this node's time {}",
+                created_at.to_rfc3339(),
+                now.to_rfc3339()
+            ),
+            TxAgeError::TooFarAhead { created_at, now } => write!(
+                f,
+                "transaction created at {} is too far ahead of this node's time {}",
+                created_at.to_rfc3339(),
+                now.to_rfc3339()
+            ),
+        }
+    }
+}
+
+// Overrides the signature count needed for a quorum: either an absolute
+// number of validators or a fraction of them, rounded up. `3` and `0.75`
+// both parse.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Threshold {
+    Absolute(usize),
+    Fraction(f64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ThresholdError {
+    FractionOutOfRange { fraction: f64 },
+    AbsoluteOutOfRange { threshold: usize, validators: usize },
+}
+
+impl fmt::Display for ThresholdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThresholdError::FractionOutOfRange { fraction } => {
+                write!(f, "threshold fraction {} must be in (0.5, 1.0]", fraction)
+            }
+            ThresholdError::AbsoluteOutOfRange {
+                threshold,
+                validators,
+            } => write!(
+                f,
+                "threshold {} must be a majority of the {} validators and no more than all of them",
+                threshold, validators
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ValidatorSetError {
+    TooMany { count: usize, max: usize },
+    Threshold(ThresholdError),
+}
+
+impl fmt::Display for ValidatorSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidatorSetError::TooMany { count, max } => {
+                write!(f, "{} validators exceed the limit of {}", count, max)
+            }
+            ValidatorSetError::Threshold(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// The validator set that became active at the start of `epoch`. It stays
+// in force for every later epoch until the next snapshot.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct ValidatorSetSnapshot {
+    pub epoch: u64,
+    pub validators: Vec<Validator>,
+    // The validators' ids, built once with the snapshot so a membership
+    // check doesn't scan the set. Snapshots are never changed afterwards.
+    #[serde(skip)]
+    members: HashSet<String>,
+}
+
+impl ValidatorSetSnapshot {
+    fn new(epoch: u64, validators: Vec<Validator>) -> Self {
+        let members = validators.iter().map(|v| v.id.clone()).collect();
+        ValidatorSetSnapshot {
+            epoch,
+            validators,
+            members,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Consensus {
+    // The active set; past sets are kept in `snapshots`.
     pub validators: Vec<Validator>,
     pub threshold: usize, // >=2/3
+    // Blocks per epoch. Validator-set changes wait for the next epoch
+    // boundary; 0 means a single epoch, so queued changes never apply.
+    pub epoch_length: u64,
+    snapshots: Vec<ValidatorSetSnapshot>,
+    pending: Option<Vec<Validator>>,
+    threshold_rule: Option<Threshold>,
+    pub leaf_encoding: LeafEncoding,
+    pub max_merkle_leaves: usize,
+    // When set, replaces the rotation check: any listed id may propose at
+    // any height. Meant for tests and local setups.
+    pub proposer_allow_list: Option<BTreeSet<String>>,
+    pub slashing: Slashing,
+    pub clock: Arc<dyn Clock>,
+    // Correction added to the clock when judging timestamps, for a host
+    // whose clock is known to be off, as an NTP offset would report.
+    pub clock_offset: Duration,
+    // How far past `now()` a received block may be stamped.
+    pub max_future_skew: Duration,
+    // Largest set that may be active or queued; see set_max_validators.
+    max_validators: usize,
+    // The height being decided and the round it has reached. Any other
+    // height is in round 0.
+    view: (u64, u32),
 }
 
 impl Consensus {
     pub fn new(validators: Vec<Validator>) -> Self {
-        let threshold = (2 * validators.len()) / 3;
+        let threshold = default_threshold(validators.len());
+        let genesis = ValidatorSetSnapshot::new(0, validators.clone());
         Consensus {
             validators,
             threshold,
+            epoch_length: DEFAULT_EPOCH_LENGTH,
+            snapshots: vec![genesis],
+            pending: None,
+            threshold_rule: None,
+            leaf_encoding: LeafEncoding::default(),
+            max_merkle_leaves: MAX_MERKLE_LEAVES,
+            proposer_allow_list: None,
+            slashing: Slashing::default(),
+            clock: Arc::new(SystemClock),
+            clock_offset: Duration::zero(),
+            max_future_skew: Duration::milliseconds(DEFAULT_MAX_FUTURE_SKEW_MS),
+            view: (0, 0),
+            max_validators: usize::MAX,
         }
     }
 
-    pub fn sign_block(&self, block: &Block) -> Vec<String> {
-        self.validators
-            .iter()
-            .map(|v| {
-                let data = format!("{}{}", v.id, hash_block(block));
-                let mut hasher = Sha256::new();
-                hasher.update(data);
-                format!("{:x}", hasher.finalize())
+    // Caps the validator set, since signing and verifying cost grows with
+    // it. Refused if the active set is already larger.
+    pub fn set_max_validators(&mut self, max: usize) -> Result<(), ValidatorSetError> {
+        check_set_size(self.validators.len(), max)?;
+        self.max_validators = max;
+        Ok(())
+    }
+
+    // Replaces the default threshold, for this set and every later one.
+    pub fn set_threshold(&mut self, threshold: Threshold) -> Result<(), ThresholdError> {
+        self.threshold = resolve_threshold(threshold, self.validators.len())?;
+        self.threshold_rule = Some(threshold);
+        Ok(())
+    }
+
+    pub fn epoch_of(&self, height: u64) -> u64 {
+        height.saturating_sub(1).checked_div(self.epoch_length).unwrap_or(0)
+    }
+
+    // Epoch in which the active set took over.
+    pub fn active_epoch(&self) -> u64 {
+        self.snapshots.last().map(|s| s.epoch).unwrap_or(0)
+    }
+
+    // Names the network: the hash of the set the chain started with, which
+    // the first block commits to.
+    pub fn genesis_validator_set_hash(&self) -> Hash256 {
+        validator_set_hash(&self.snapshots[0].validators)
+    }
+
+    // Hash of the set active now, in the same canonical form.
+    pub fn validator_set_hash(&self) -> Hash256 {
+        validator_set_hash(&self.validators)
+    }
+
+    pub fn snapshots(&self) -> &[ValidatorSetSnapshot] {
+        &self.snapshots
+    }
+
+    pub fn pending_validators(&self) -> Option<&[Validator]> {
+        self.pending.as_deref()
+    }
+
+    // Schedules a new validator set for the next epoch boundary, replacing
+    // any set already waiting. A threshold override must still be reachable
+    // by the new set.
+    pub fn queue_validators(
+        &mut self,
+        validators: Vec<Validator>,
+    ) -> Result<(), ValidatorSetError> {
+        check_set_size(validators.len(), self.max_validators)?;
+        if let Some(rule) = self.threshold_rule {
+            resolve_threshold(rule, validators.len()).map_err(ValidatorSetError::Threshold)?;
+        }
+        self.pending = Some(validators);
+        Ok(())
+    }
+
+    // Called before the block at `height` is built or checked. Crossing
+    // into a new epoch activates the queued set and snapshots it.
+    pub fn enter_height(&mut self, height: u64) {
+        let epoch = self.epoch_of(height);
+        if epoch <= self.active_epoch() {
+            return;
+        }
+        let Some(validators) = self.pending.take() else {
+            return;
+        };
+        self.threshold = match self.threshold_rule {
+            Some(rule) => resolve_threshold(rule, validators.len()).unwrap_or(validators.len()),
+            None => default_threshold(validators.len()),
+        };
+        self.validators = validators.clone();
+        self.snapshots.push(ValidatorSetSnapshot::new(epoch, validators));
+    }
+
+    // The set that was active for the block at `height`.
+    pub fn validators_at(&self, height: u64) -> &[Validator] {
+        &self.snapshot_at(height).validators
+    }
+
+    // There is always a genesis snapshot at epoch 0, so one always matches.
+    fn snapshot_at(&self, height: u64) -> &ValidatorSetSnapshot {
+        let epoch = self.epoch_of(height);
+        self.snapshots.iter().rev().find(|s| s.epoch <= epoch).unwrap_or(&self.snapshots[0])
+    }
+
+    fn is_validator_at(&self, height: u64, id: &str) -> bool {
+        self.snapshot_at(height).members.contains(id)
+    }
+
+    // This node's corrected view of the current time.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now() + self.clock_offset
+    }
+
+    pub fn check_timestamp(&self, header: &Header) -> Result<(), TimestampError> {
+        let now = self.now();
+        if header.timestamp > now + self.max_future_skew {
+            return Err(TimestampError::TooFarAhead {
+                timestamp: header.timestamp,
+                now,
+            });
+        }
+        Ok(())
+    }
+
+    // A transaction's `created_at` against this node's corrected time. It
+    // may run ahead by as much as a block timestamp may; `max_age`, when
+    // set, bounds how far behind. Transactions without one pass.
+    pub fn check_tx_age(
+        &self,
+        tx: &Transaction,
+        max_age: Option<Duration>,
+    ) -> Result<(), TxAgeError> {
+        let Some(created_at) = tx.created_at else {
+            return Ok(());
+        };
+        let now = self.now();
+        if created_at > now + self.max_future_skew {
+            return Err(TxAgeError::TooFarAhead { created_at, now });
+        }
+        // Past the earliest time chrono holds, nothing can be too old.
+        let oldest = max_age.and_then(|max_age| now.checked_sub_signed(max_age));
+        if oldest.is_some_and(|oldest| created_at < oldest) {
+            return Err(TxAgeError::Stale { created_at, now });
+        }
+        Ok(())
+    }
+
+    pub fn round(&self, height: u64) -> u32 {
+        match self.view {
+            (view_height, round) if view_height == height => round,
+            _ => 0,
+        }
+    }
+
+    // The proposer of `height` failed to produce a block in time: moves the
+    // height to its next round, and the turn to the next validator. Rounds
+    // only ever go up. Returns the new round.
+    pub fn view_change(&mut self, height: u64) -> u32 {
+        let round = self.round(height).saturating_add(1);
+        self.view = (height, round);
+        round
+    }
+
+    pub fn proposer_for(&self, height: u64) -> Option<&Validator> {
+        self.proposer_in_round(height, self.round(height))
+    }
+
+    pub fn proposer_in_round(&self, height: u64, round: u32) -> Option<&Validator> {
+        let validators = self.validators_at(height);
+        if validators.is_empty() {
+            return None;
+        }
+        let n = validators.len() as u64;
+        let turn = (height.saturating_sub(1) % n + round as u64 % n) % n;
+        validators.get(turn as usize)
+    }
+
+    // Checks the proposer of a block received from elsewhere. The block
+    // must come from the round this node is in: an earlier round has been
+    // given up on, and a later one is a view change this node hasn't made.
+    pub fn check_proposer(&self, header: &Header) -> Result<(), ProposerError> {
+        let (height, round) = (header.height, header.round);
+        let current = self.round(height);
+        if round < current {
+            return Err(ProposerError::StaleRound {
+                height,
+                round,
+                current,
+            });
+        }
+        if round > current {
+            return Err(ProposerError::FutureRound {
+                height,
+                round,
+                current,
+            });
+        }
+        self.check_proposer_in_round(header)
+    }
+
+    // The proposer check alone, for the round the block names rather than
+    // the one this node is in: how a block at a height already passed is
+    // judged.
+    pub fn check_proposer_in_round(&self, header: &Header) -> Result<(), ProposerError> {
+        let proposer = &header.proposer;
+        if let Some(allowed) = &self.proposer_allow_list {
+            if allowed.contains(proposer) {
+                return Ok(());
+            }
+            return Err(ProposerError::NotAllowed {
+                proposer: proposer.clone(),
+            });
+        }
+        if !self.is_validator_at(header.height, proposer) {
+            return Err(ProposerError::Unknown {
+                proposer: proposer.clone(),
+            });
+        }
+        match self.proposer_in_round(header.height, header.round) {
+            Some(expected) if &expected.id == proposer => Ok(()),
+            expected => Err(ProposerError::WrongTurn {
+                height: header.height,
+                expected: expected.map(|v| v.id.clone()).unwrap_or_default(),
+                proposer: proposer.clone(),
+            }),
+        }
+    }
+
+    // How often each validator signed `blocks`, counting only the blocks it
+    // was in the set for and only signatures that count toward the block's
+    // quorum. Sorted by validator id; a validator that joined partway
+    // through the window is judged on its share of the window.
+    pub fn participation<'a>(
+        &self,
+        blocks: impl IntoIterator<Item = &'a Block>,
+        min_participation: f64,
+    ) -> Vec<Participation> {
+        let mut counts: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+        for block in blocks {
+            let counted: BTreeSet<&str> = self
+                .counted(block, &block.signatures)
+                .map(|s| s.validator_id.as_str())
+                .collect();
+            for v in self.validators_at(block.header.height) {
+                let (signed, expected) = counts.entry(v.id.as_str()).or_default();
+                *expected += 1;
+                if counted.contains(v.id.as_str()) {
+                    *signed += 1;
+                }
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(id, (signed, expected))| {
+                let score = signed as f64 / expected as f64;
+                Participation {
+                    validator: id.to_string(),
+                    signed,
+                    expected,
+                    score,
+                    below_threshold: score < min_participation,
+                }
             })
             .collect()
     }
 
-    pub fn verify_quorum(&self, signatures: &[String]) -> bool {
-        signatures.len() >= self.threshold
+    pub fn sign_block(&self, block: &Block) -> Vec<Signature> {
+        let block_hash = hash_block(block);
+        let signers = by_id(self.validators_at(block.header.height)).into_iter();
+        let signers = signers.filter(|v| !self.slashing.is_jailed(&v.id));
+        #[cfg(feature = "byzantine")]
+        let signers = signers.filter(|v| v.behavior != ByzantineBehavior::Withhold);
+        let sigs: Vec<Signature> = signers
+            .map(|v| Signature {
+                validator_id: v.id.clone(),
+                height: block.header.height,
+                block_hash,
+                sig: simulated_sig(&v.id, &block_hash),
+            })
+            .collect();
+        #[cfg(feature = "byzantine")]
+        let sigs = self.misbehave(sigs, block.header.height);
+        sigs
+    }
+
+    #[cfg(feature = "byzantine")]
+    fn misbehave(&self, mut sigs: Vec<Signature>, height: u64) -> Vec<Signature> {
+        let validators = self.validators_at(height);
+        let behavior = |id: &str| validators.iter().find(|v| v.id == id).map(|v| v.behavior);
+        let mut conflicting = Vec::new();
+        for sig in sigs.iter_mut() {
+            match behavior(&sig.validator_id) {
+                Some(ByzantineBehavior::SignInvalid) => sig.sig = "invalid".to_string(),
+                Some(ByzantineBehavior::Equivocate) => {
+                    let block_hash = Hash256::digest(format!("fork of {}", sig.block_hash));
+                    conflicting.push(Signature {
+                        sig: simulated_sig(&sig.validator_id, &block_hash),
+                        block_hash,
+                        ..sig.clone()
+                    });
+                }
+                _ => {}
+            }
+        }
+        sigs.extend(conflicting);
+        sigs
+    }
+
+    pub fn verify_signature(&self, sig: &Signature) -> bool {
+        self.is_validator_at(sig.height, &sig.validator_id)
+            && sig.sig == simulated_sig(&sig.validator_id, &sig.block_hash)
+    }
+
+    // How close validators whose signatures already verified bring a block
+    // to quorum. Each counts once, and jailed ones not at all, as in
+    // `counted`.
+    pub fn quorum_progress(&self, verified: &[&Validator]) -> QuorumProgress {
+        let got = verified
+            .iter()
+            .filter(|v| !self.slashing.is_jailed(&v.id))
+            .map(|v| v.id.as_str())
+            .collect::<BTreeSet<_>>()
+            .len();
+        QuorumProgress {
+            got,
+            needed: self.threshold,
+            reached: got >= self.threshold,
+        }
+    }
+
+    // verify_signature for each of `sigs`, in order. Large batches are
+    // spread over the rayon pool.
+    pub fn verify_signatures(&self, sigs: &[Signature]) -> Vec<bool> {
+        if sigs.len() < PARALLEL_VERIFY_MIN {
+            sigs.iter().map(|sig| self.verify_signature(sig)).collect()
+        } else {
+            sigs.par_iter().map(|sig| self.verify_signature(sig)).collect()
+        }
+    }
+
+    // Too few of the active validators are free to sign for any block to
+    // reach quorum. Lifts once enough are released, or a larger set takes
+    // over at an epoch boundary.
+    pub fn is_halted(&self) -> bool {
+        let free = self.validators.iter().filter(|v| !self.slashing.is_jailed(&v.id));
+        free.count() < self.threshold
+    }
+
+    pub fn verify_quorum(&self, block: &Block, signatures: &[Signature]) -> bool {
+        self.counted(block, signatures).count() >= self.threshold
+    }
+
+    // Signatures that count toward quorum for `block`: ones for its hash and
+    // height that verify, from members of its set not jailed, one per
+    // validator in validator id order. A validator's valid signatures on one
+    // block are all the same, so neither arrival order nor duplicates change
+    // the result, and a forged repeat can't displace the real one.
+    pub fn counted<'a>(
+        &'a self,
+        block: &Block,
+        signatures: &'a [Signature],
+    ) -> impl Iterator<Item = &'a Signature> {
+        let block_hash = hash_block(block);
+        let height = block.header.height;
+        let mut by_validator: BTreeMap<&str, &Signature> = BTreeMap::new();
+        for sig in signatures {
+            if sig.block_hash != block_hash
+                || sig.height != height
+                || self.slashing.is_jailed(&sig.validator_id)
+                || !self.verify_signature(sig)
+            {
+                continue;
+            }
+            by_validator.entry(sig.validator_id.as_str()).or_insert(sig);
+        }
+        by_validator.into_values()
+    }
+
+    // Checks signatures collected for a block, jailing any validator that
+    // sent an invalid one or has already signed another block at its height.
+    pub fn record_signatures(&mut self, sigs: &[Signature]) -> Vec<SlashRecord> {
+        let mut slashed = Vec::new();
+        let verified = self.verify_signatures(sigs);
+        for (sig, valid) in sigs.iter().zip(verified) {
+            let offense = if valid {
+                self.slashing.observe(sig)
+            } else {
+                Some(Offense::InvalidSignature { height: sig.height })
+            };
+            if let Some(offense) = offense {
+                slashed.push(self.slashing.jail(&sig.validator_id, offense));
+            }
+        }
+        slashed
+    }
+
+    // Externally reported double-signing. Both signatures must verify so a
+    // report can't be forged to jail an honest validator.
+    pub fn report_equivocation(
+        &mut self,
+        first: &Signature,
+        second: &Signature,
+    ) -> Result<SlashRecord, SlashError> {
+        if !self.is_validator_at(first.height, &first.validator_id) {
+            return Err(SlashError::UnknownValidator);
+        }
+        if !self.verify_signature(first) || !self.verify_signature(second) {
+            return Err(SlashError::InvalidSignature);
+        }
+        if first.validator_id != second.validator_id
+            || first.height != second.height
+            || first.block_hash == second.block_hash
+        {
+            return Err(SlashError::NotConflicting);
+        }
+        let offense = Offense::Equivocation {
+            height: first.height,
+            first_hash: first.block_hash,
+            second_hash: second.block_hash,
+        };
+        Ok(self.slashing.jail(&first.validator_id, offense))
     }
 
     pub fn propose_block(
         &self,
         height: u64,
-        prev_hash: String,
+        prev_hash: Hash256,
         txs: Vec<Transaction>,
         proposer: String,
-    ) -> Block {
-        let merkle_root = if let Some((_, root_hash)) = crate::merkle::build_merkle_tree(&txs) {
-            root_hash
-        } else {
-            String::new()
-        };
+    ) -> Result<Block, MerkleError> {
+        let merkle_root = merkle_root(&txs, self.leaf_encoding, self.max_merkle_leaves)?;
+        let (tx_count, total_fees) = body_totals(&txs);
         let header = Header {
+            version: BLOCK_VERSION,
             height,
             prev_hash,
             merkle_root,
+            sorted_root: sorted_root(&txs),
             proposer,
-            timestamp: Utc::now(),
+            timestamp: self.clock.now().trunc_subsecs(3),
+            round: self.round(height),
+            validator_set_hash: if height == 1 {
+                self.genesis_validator_set_hash()
+            } else {
+                Hash256::ZERO
+            },
+            tx_count,
+            total_fees,
+        };
+        Ok(Block {
+            header,
+            txs,
+            signatures: Vec::new(),
+        })
+    }
+}
+
+// Every id and key in id order, each prefixed with its length so no two
+// sets share a preimage. Sorting first makes the hash depend only on who is
+// in the set, not on the order the config lists them.
+pub fn validator_set_hash(validators: &[Validator]) -> Hash256 {
+    let mut data = String::new();
+    for v in by_id(validators) {
+        data.push_str(&format!("{}:{}{}:{}", v.id.len(), v.id, v.pub_key.len(), v.pub_key));
+    }
+    Hash256::digest(data)
+}
+
+// The canonical order of a set: by id. Rotation keeps the configured order,
+// since that decides the turns.
+fn by_id(validators: &[Validator]) -> Vec<&Validator> {
+    let mut sorted: Vec<&Validator> = validators.iter().collect();
+    sorted.sort_by(|a, b| a.id.cmp(&b.id));
+    sorted
+}
+
+fn check_set_size(count: usize, max: usize) -> Result<(), ValidatorSetError> {
+    if count > max {
+        return Err(ValidatorSetError::TooMany { count, max });
+    }
+    Ok(())
+}
+
+// More than two thirds: the BFT bound, so one validator alone still needs
+// its own signature.
+fn default_threshold(validators: usize) -> usize {
+    2 * validators / 3 + 1
+}
+
+// Anything that isn't a strict majority of the validators, or that no set
+// of them could reach, is refused.
+fn resolve_threshold(threshold: Threshold, validators: usize) -> Result<usize, ThresholdError> {
+    let threshold = match threshold {
+        Threshold::Fraction(fraction) if fraction > 0.5 && fraction <= 1.0 => {
+            (fraction * validators as f64).ceil() as usize
+        }
+        Threshold::Fraction(fraction) => {
+            return Err(ThresholdError::FractionOutOfRange { fraction });
+        }
+        Threshold::Absolute(threshold) => threshold,
+    };
+    if threshold * 2 <= validators || threshold > validators {
+        return Err(ThresholdError::AbsoluteOutOfRange {
+            threshold,
+            validators,
+        });
+    }
+    Ok(threshold)
+}
+
+fn simulated_sig(validator_id: &str, block_hash: &Hash256) -> String {
+    let data = format!("{}{}", validator_id, block_hash);
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::merkle::empty_root;
+    use crate::test_util::{consensus, tx};
+
+    fn signature_from(cons: &Consensus, block: &Block, validator_id: &str) -> Signature {
+        cons.sign_block(block)
+            .into_iter()
+            .find(|s| s.validator_id == validator_id)
+            .unwrap()
+    }
+
+    fn empty_block(cons: &Consensus, prev_hash: Hash256) -> Block {
+        cons.propose_block(1, prev_hash, Vec::new(), "val1".to_string()).unwrap()
+    }
+
+    #[test]
+    fn double_signer_is_jailed_and_stops_counting() {
+        let mut cons = consensus();
+        let block = empty_block(&cons, Hash256::ZERO);
+        let rival = empty_block(&cons, Hash256::digest("fork"));
+        let all: Vec<_> =
+            ["val1", "val2", "val3"].iter().map(|id| signature_from(&cons, &block, id)).collect();
+        assert!(cons.verify_quorum(&block, &all));
+
+        assert!(cons.record_signatures(&all).is_empty());
+        let slashed = cons.record_signatures(&[signature_from(&cons, &rival, "val1")]);
+        assert_eq!(slashed.len(), 1);
+        assert_eq!(slashed[0].validator_id, "val1");
+        assert!(matches!(slashed[0].offense, Offense::Equivocation { height: 1, .. }));
+
+        assert!(cons.slashing.is_jailed("val1"));
+        assert!(!cons.verify_quorum(&block, &all));
+        assert!(cons.sign_block(&block).iter().all(|s| s.validator_id != "val1"));
+    }
+
+    #[test]
+    fn quorum_ignores_signature_order_and_repeats() {
+        let mut cons = consensus();
+        cons.set_threshold(Threshold::Absolute(3)).unwrap();
+        let block = empty_block(&cons, Hash256::ZERO);
+        let sigs = cons.sign_block(&block);
+        let counted = |sigs: &[Signature]| cons.counted(&block, sigs).cloned().collect::<Vec<_>>();
+        let expected = counted(&sigs);
+        assert_eq!(expected.len(), 3);
+
+        let mut reversed = sigs.clone();
+        reversed.reverse();
+        let mut rotated = sigs.clone();
+        rotated.rotate_left(1);
+        for order in [reversed, rotated] {
+            assert!(cons.verify_quorum(&block, &order));
+            assert_eq!(counted(&order), expected);
+        }
+
+        // Three copies of two validators' signatures are still two.
+        let repeated: Vec<_> = sigs[..2].iter().cycle().take(6).cloned().collect();
+        assert!(!cons.verify_quorum(&block, &repeated));
+        assert_eq!(counted(&repeated), expected[..2]);
+        let mut padded = repeated.clone();
+        padded.push(sigs[2].clone());
+        padded.rotate_right(3);
+        assert!(cons.verify_quorum(&block, &padded));
+        assert_eq!(counted(&padded), expected);
+
+        // A forgery, a signature on another block and one from outside the
+        // set count for nothing, and can't displace the real signature.
+        let rival = empty_block(&cons, Hash256::digest("fork"));
+        let forged = Signature {
+            sig: "0".to_string(),
+            ..sigs[2].clone()
+        };
+        let outsider = Signature {
+            validator_id: "mallory".to_string(),
+            sig: simulated_sig("mallory", &sigs[0].block_hash),
+            ..sigs[0].clone()
+        };
+        let mut noisy = vec![forged, outsider, signature_from(&cons, &rival, "val3")];
+        noisy.extend(repeated);
+        assert!(!cons.verify_quorum(&block, &noisy));
+        assert_eq!(counted(&noisy), expected[..2]);
+        noisy.push(sigs[2].clone());
+        assert!(cons.verify_quorum(&block, &noisy));
+        assert_eq!(counted(&noisy), expected);
+    }
+
+    #[cfg(feature = "byzantine")]
+    fn scripted(behaviors: &[ByzantineBehavior]) -> Consensus {
+        let validators = behaviors
+            .iter()
+            .enumerate()
+            .map(|(i, behavior)| {
+                let id = format!("val{}", i + 1);
+                Validator::new(id, String::new()).with_behavior(*behavior)
+            })
+            .collect();
+        Consensus::new(validators)
+    }
+
+    #[cfg(feature = "byzantine")]
+    #[test]
+    fn honest_majority_finalizes_and_faults_are_caught() {
+        use ByzantineBehavior::*;
+        let mut behaviors = vec![Honest; 7];
+        behaviors.extend([Equivocate, Withhold, SignInvalid]);
+        let mut cons = scripted(&behaviors);
+        assert_eq!(cons.threshold, 7);
+        let block = empty_block(&cons, Hash256::ZERO);
+        let sigs = cons.sign_block(&block);
+        assert!(sigs.iter().all(|s| s.validator_id != "val9"));
+
+        let slashed = cons.record_signatures(&sigs);
+        let offenders: Vec<_> = slashed.iter().map(|r| r.validator_id.as_str()).collect();
+        assert_eq!(offenders, ["val10", "val8"]);
+        assert!(matches!(slashed[0].offense, Offense::InvalidSignature { height: 1 }));
+        assert!(matches!(slashed[1].offense, Offense::Equivocation { height: 1, .. }));
+        assert!(!cons.slashing.is_jailed("val9"));
+
+        assert!(cons.verify_quorum(&block, &sigs));
+        let counted: Vec<_> = cons.counted(&block, &sigs).map(|s| &s.validator_id).collect();
+        assert_eq!(counted, ["val1", "val2", "val3", "val4", "val5", "val6", "val7"]);
+    }
+
+    #[cfg(feature = "byzantine")]
+    #[test]
+    fn too_many_faulty_validators_stall_the_chain() {
+        use ByzantineBehavior::*;
+        let mut cons = scripted(&[Honest, Withhold, SignInvalid]);
+        let block = empty_block(&cons, Hash256::ZERO);
+        let sigs = cons.sign_block(&block);
+        cons.record_signatures(&sigs);
+        assert!(!cons.verify_quorum(&block, &sigs));
+    }
+
+    #[test]
+    fn equivocation_reports_need_valid_conflicting_signatures() {
+        let mut cons = consensus();
+        let block = empty_block(&cons, Hash256::ZERO);
+        let rival = empty_block(&cons, Hash256::digest("fork"));
+        let first = signature_from(&cons, &block, "val2");
+        let mut forged = signature_from(&cons, &rival, "val2");
+        forged.sig = "00".to_string();
+        assert_eq!(cons.report_equivocation(&first, &forged), Err(SlashError::InvalidSignature));
+        assert_eq!(cons.report_equivocation(&first, &first), Err(SlashError::NotConflicting));
+        assert!(!cons.slashing.is_jailed("val2"));
+
+        let second = signature_from(&cons, &rival, "val2");
+        assert!(cons.report_equivocation(&first, &second).is_ok());
+        assert!(cons.slashing.is_jailed("val2"));
+    }
+
+    #[test]
+    fn validators_that_miss_blocks_lose_participation() {
+        let cons = consensus();
+        let blocks: Vec<Block> = (1..=10)
+            .map(|height| {
+                let proposed = cons.propose_block(height, Hash256::ZERO, Vec::new(), String::new());
+                let mut block = proposed.unwrap();
+                block.signatures = cons.sign_block(&block);
+                if height > 7 {
+                    block.signatures.retain(|s| s.validator_id != "val3");
+                }
+                // A signature for some other block doesn't count for this one.
+                if height > 4 {
+                    let val3 = block.signatures.iter_mut().find(|s| s.validator_id == "val3");
+                    if let Some(sig) = val3 {
+                        sig.block_hash = Hash256::digest("elsewhere");
+                    }
+                }
+                block
+            })
+            .collect();
+
+        let report = cons.participation(&blocks, 0.8);
+        let scores: Vec<(&str, u64, f64, bool)> = report
+            .iter()
+            .map(|p| (p.validator.as_str(), p.signed, p.score, p.below_threshold))
+            .collect();
+        assert_eq!(
+            scores,
+            [("val1", 10, 1.0, false), ("val2", 10, 1.0, false), ("val3", 4, 0.4, true)]
+        );
+        assert!(report.iter().all(|p| p.expected == 10));
+
+        let early = cons.participation(&blocks[..4], 0.8);
+        assert!(early.iter().all(|p| p.score == 1.0 && !p.below_threshold));
+        assert!(cons.participation(&[], 0.8).is_empty());
+    }
+
+    #[test]
+    fn genesis_commits_to_the_validator_set() {
+        let set = |ids: &[&str]| -> Vec<Validator> {
+            ids.iter().map(|id| Validator::new(id.to_string(), format!("pub-{}", id))).collect()
         };
-        Block { header, txs }
+        let three = validator_set_hash(&set(&["val1", "val2", "val3"]));
+        assert_eq!(three, validator_set_hash(&set(&["val1", "val2", "val3"])));
+        assert_ne!(three, validator_set_hash(&set(&["val1", "val2"])));
+        assert_ne!(three, validator_set_hash(&set(&["val1", "val2", "val4"])));
+        let mut rekeyed = set(&["val1", "val2", "val3"]);
+        rekeyed[0].pub_key = "other".to_string();
+        assert_ne!(three, validator_set_hash(&rekeyed));
+        // Length prefixes keep ids and keys from running together.
+        assert_ne!(
+            validator_set_hash(&[Validator::new("ab".to_string(), "c".to_string())]),
+            validator_set_hash(&[Validator::new("a".to_string(), "bc".to_string())])
+        );
+
+        let cons = consensus();
+        let genesis = empty_block(&cons, Hash256::ZERO);
+        assert_eq!(genesis.header.validator_set_hash, cons.genesis_validator_set_hash());
+        let second = cons.propose_block(2, hash_block(&genesis), Vec::new(), "val2".to_string());
+        assert!(second.unwrap().header.validator_set_hash.is_zero());
+        assert_eq!(crate::chain::validate_block(&genesis, &cons), Ok(()));
+        let mut other = consensus();
+        other.queue_validators(set(&["val1", "val2", "val3"])).unwrap();
+        other.enter_height(DEFAULT_EPOCH_LENGTH + 1);
+        assert_eq!(other.genesis_validator_set_hash(), cons.genesis_validator_set_hash());
+        let elsewhere = Consensus::new(set(&["val1", "val2", "val3"]));
+        assert!(matches!(
+            crate::chain::validate_block(&genesis, &elsewhere),
+            Err(crate::chain::BlockError::ValidatorSetMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn set_hash_and_signing_ignore_config_order() {
+        let set = |ids: &[&str]| -> Vec<Validator> {
+            ids.iter().map(|id| Validator::new(id.to_string(), format!("pub-{}", id))).collect()
+        };
+        let configured = Consensus::new(set(&["val2", "val3", "val1"]));
+        let reversed = Consensus::new(set(&["val3", "val2", "val1"]));
+        let sorted = Consensus::new(set(&["val1", "val2", "val3"]));
+        for cons in [&configured, &reversed] {
+            assert_eq!(cons.validator_set_hash(), sorted.validator_set_hash());
+            assert_eq!(cons.genesis_validator_set_hash(), sorted.genesis_validator_set_hash());
+        }
+        let smaller = Consensus::new(set(&["val1"]));
+        assert_ne!(sorted.validator_set_hash(), smaller.validator_set_hash());
+
+        let block = empty_block(&sorted, Hash256::ZERO);
+        let signers = |cons: &Consensus| -> Vec<String> {
+            cons.sign_block(&block).into_iter().map(|sig| sig.validator_id).collect()
+        };
+        assert_eq!(signers(&configured), ["val1", "val2", "val3"]);
+        assert_eq!(signers(&configured), signers(&reversed));
+        assert_eq!(configured.sign_block(&block), sorted.sign_block(&block));
+        // Rotation still follows the configured order.
+        assert_eq!(configured.proposer_for(1).unwrap().id, "val2");
+
+        let mut cons = sorted;
+        let before = cons.validator_set_hash();
+        cons.queue_validators(set(&["val4", "val1", "val2", "val3"])).unwrap();
+        cons.enter_height(DEFAULT_EPOCH_LENGTH + 1);
+        assert_ne!(cons.validator_set_hash(), before);
+        assert_eq!(cons.genesis_validator_set_hash(), before);
+    }
+
+    #[test]
+    fn empty_block_gets_the_canonical_empty_root() {
+        let block = empty_block(&consensus(), Hash256::ZERO);
+        assert_eq!(block.header.merkle_root, empty_root());
+        assert!(!block.header.merkle_root.is_zero());
+    }
+
+    #[test]
+    fn failed_tree_build_aborts_the_proposal() {
+        let mut cons = consensus();
+        cons.max_merkle_leaves = 2;
+        let txs = vec![tx("tx1"), tx("tx2"), tx("tx3")];
+        let result = cons.propose_block(1, Hash256::ZERO, txs, "val1".to_string());
+        assert_eq!(result.unwrap_err(), MerkleError::TooManyLeaves { count: 3, max: 2 });
+    }
+
+    #[test]
+    fn proposer_must_be_known_and_on_turn() {
+        let mut cons = consensus();
+        let header = |height: u64, proposer: &str| {
+            let block = cons.propose_block(height, Hash256::ZERO, Vec::new(), proposer.to_string());
+            block.unwrap().header
+        };
+        let unknown = header(2, "mallory");
+        let wrong_turn = header(2, "val1");
+        let on_turn = header(2, "val2");
+        assert_eq!(
+            cons.check_proposer(&unknown),
+            Err(ProposerError::Unknown {
+                proposer: "mallory".to_string()
+            })
+        );
+        assert_eq!(
+            cons.check_proposer(&wrong_turn),
+            Err(ProposerError::WrongTurn {
+                height: 2,
+                expected: "val2".to_string(),
+                proposer: "val1".to_string(),
+            })
+        );
+        assert_eq!(cons.check_proposer(&on_turn), Ok(()));
+
+        cons.proposer_allow_list = Some(BTreeSet::from(["val1".to_string()]));
+        assert_eq!(cons.check_proposer(&wrong_turn), Ok(()));
+        assert!(matches!(cons.check_proposer(&on_turn), Err(ProposerError::NotAllowed { .. })));
+    }
+
+    #[test]
+    fn threshold_override_replaces_the_default() {
+        let mut cons = consensus();
+        let block = empty_block(&cons, Hash256::ZERO);
+        let pair = vec![
+            signature_from(&cons, &block, "val1"),
+            signature_from(&cons, &block, "val2"),
+        ];
+        assert_eq!(cons.threshold, 3);
+        assert!(!cons.verify_quorum(&block, &pair));
+
+        cons.set_threshold(Threshold::Absolute(2)).unwrap();
+        assert!(cons.verify_quorum(&block, &pair));
+        cons.set_threshold(Threshold::Fraction(0.6)).unwrap();
+        assert_eq!(cons.threshold, 2);
+        cons.set_threshold(Threshold::Fraction(1.0)).unwrap();
+        assert_eq!(cons.threshold, 3);
+
+        let parsed: Vec<Threshold> = serde_json::from_str("[3, 0.75]").unwrap();
+        assert_eq!(parsed, [Threshold::Absolute(3), Threshold::Fraction(0.75)]);
+    }
+
+    #[test]
+    fn validator_changes_wait_for_the_epoch_boundary() {
+        let mut cons = consensus();
+        cons.epoch_length = 4;
+        let rotated: Vec<Validator> = ["val2", "val3", "val4"]
+            .iter()
+            .map(|id| Validator::new(id.to_string(), String::new()))
+            .collect();
+        cons.enter_height(2);
+        cons.queue_validators(rotated.clone()).unwrap();
+        for height in 3..=4 {
+            cons.enter_height(height);
+            assert_eq!(cons.active_epoch(), 0);
+            assert_eq!(cons.validators_at(height)[0].id, "val1");
+        }
+        assert_eq!(cons.pending_validators(), Some(rotated.as_slice()));
+
+        cons.enter_height(5);
+        assert_eq!(cons.active_epoch(), 1);
+        assert_eq!(cons.validators, rotated);
+        assert_eq!(cons.pending_validators(), None);
+        assert_eq!(cons.proposer_for(5).unwrap().id, "val3");
+        assert_eq!(cons.snapshots().len(), 2);
+        assert_eq!(cons.snapshots()[1], ValidatorSetSnapshot::new(1, rotated));
+
+        // Blocks from the first epoch are still judged by the set that made them.
+        assert_eq!(cons.proposer_for(4).unwrap().id, "val1");
+        let mut old = cons.propose_block(4, Hash256::ZERO, Vec::new(), "val1".to_string()).unwrap();
+        assert_eq!(cons.check_proposer(&old.header), Ok(()));
+        let sigs = cons.sign_block(&old);
+        assert!(sigs.iter().any(|s| s.validator_id == "val1"));
+        assert!(sigs.iter().all(|s| cons.verify_signature(s)));
+        old.header.height = 5;
+        old.header.proposer = "val1".to_string();
+        assert!(matches!(cons.check_proposer(&old.header), Err(ProposerError::Unknown { .. })));
+    }
+
+    #[test]
+    fn set_changes_refresh_the_cached_quorum() {
+        let mut cons = consensus();
+        cons.epoch_length = 2;
+        let set = |n: usize| -> Vec<Validator> {
+            (1..=n).map(|i| Validator::new(format!("val{}", i), String::new())).collect()
+        };
+        let at = |height: u64| {
+            cons.propose_block(height, Hash256::ZERO, Vec::new(), "val1".to_string()).unwrap()
+        };
+        let (first, third, fifth) = (at(1), at(3), at(5));
+        let signed_by = |id: &str, block: &Block| {
+            let block_hash = hash_block(block);
+            Signature {
+                validator_id: id.to_string(),
+                height: block.header.height,
+                block_hash,
+                sig: simulated_sig(id, &block_hash),
+            }
+        };
+        let signed = |n: usize, block: &Block| -> Vec<Signature> {
+            (1..=n).map(|i| signed_by(&format!("val{}", i), block)).collect()
+        };
+        assert_eq!(cons.threshold, 3);
+
+        cons.queue_validators(set(6)).unwrap();
+        cons.enter_height(3);
+        assert_eq!(cons.threshold, 5);
+        assert!(cons.verify_signature(&signed_by("val6", &third)));
+        assert!(!cons.verify_signature(&signed_by("val6", &first)));
+        assert!(cons.verify_quorum(&third, &signed(5, &third)));
+        assert!(!cons.verify_quorum(&third, &signed(4, &third)));
+
+        cons.queue_validators(set(3)).unwrap();
+        cons.enter_height(5);
+        assert_eq!(cons.threshold, 3);
+        assert!(!cons.verify_signature(&signed_by("val6", &fifth)));
+        assert!(cons.verify_signature(&signed_by("val6", &third)));
+        let both = [signed_by("val4", &fifth), signed_by("val3", &fifth)];
+        assert_eq!(cons.verify_signatures(&both), [false, true]);
+        assert!(cons.verify_quorum(&fifth, &signed(3, &fifth)));
+        assert!(!cons.verify_quorum(&fifth, &signed(6, &fifth)[3..]));
+
+        cons.set_threshold(Threshold::Absolute(2)).unwrap();
+        assert_eq!(cons.threshold, 2);
+    }
+
+    #[test]
+    fn queued_sets_must_fit_the_threshold_override() {
+        let mut cons = consensus();
+        cons.set_threshold(Threshold::Absolute(3)).unwrap();
+        let pair: Vec<Validator> = cons.validators[..2].to_vec();
+        assert!(matches!(
+            cons.queue_validators(pair),
+            Err(ValidatorSetError::Threshold(ThresholdError::AbsoluteOutOfRange {
+                threshold: 3,
+                validators: 2
+            }))
+        ));
+        assert_eq!(cons.pending_validators(), None);
+    }
+
+    #[test]
+    fn invalid_thresholds_are_rejected() {
+        let mut cons = consensus();
+        for fraction in [0.5, 0.2, 1.01, f64::NAN] {
+            assert!(matches!(
+                cons.set_threshold(Threshold::Fraction(fraction)),
+                Err(ThresholdError::FractionOutOfRange { .. })
+            ));
+        }
+        for threshold in [0, 1, 4] {
+            assert_eq!(
+                cons.set_threshold(Threshold::Absolute(threshold)),
+                Err(ThresholdError::AbsoluteOutOfRange {
+                    threshold,
+                    validators: 3
+                })
+            );
+        }
+        assert_eq!(cons.threshold, 3);
+    }
+
+    #[test]
+    fn default_threshold_is_more_than_two_thirds() {
+        let set = |n: usize| -> Consensus {
+            Consensus::new(
+                (1..=n).map(|i| Validator::new(format!("val{}", i), String::new())).collect(),
+            )
+        };
+        let lone = set(1);
+        assert_eq!(lone.threshold, 1);
+        let block = empty_block(&lone, Hash256::ZERO);
+        assert!(!lone.verify_quorum(&block, &[]));
+        assert!(lone.verify_quorum(&block, &lone.sign_block(&block)));
+
+        let four = set(4);
+        assert_eq!(four.threshold, 3);
+        let block = empty_block(&four, Hash256::ZERO);
+        let sigs = four.sign_block(&block);
+        assert!(four.verify_quorum(&block, &sigs[..3]));
+        assert!(!four.verify_quorum(&block, &sigs[..2]));
+        for n in 1..=10 {
+            assert!(resolve_threshold(Threshold::Absolute(set(n).threshold), n).is_ok());
+        }
+    }
+
+    #[test]
+    fn blocks_are_stamped_by_the_injected_clock() {
+        let mut cons = consensus();
+        let start: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let clock = Arc::new(MockClock::new(start));
+        cons.clock = clock.clone();
+        assert_eq!(empty_block(&cons, Hash256::ZERO).header.timestamp, start);
+        clock.advance(chrono::Duration::seconds(90));
+        let later: DateTime<Utc> = "2024-05-01T12:01:30Z".parse().unwrap();
+        assert_eq!(empty_block(&cons, Hash256::ZERO).header.timestamp, later);
+    }
+
+    #[test]
+    fn millisecond_stamps_survive_a_round_trip() {
+        let mut cons = consensus();
+        let start: DateTime<Utc> = "2024-05-01T12:00:00.123456789Z".parse().unwrap();
+        cons.clock = Arc::new(MockClock::new(start));
+        let block = empty_block(&cons, Hash256::ZERO);
+        let millis: DateTime<Utc> = "2024-05-01T12:00:00.123Z".parse().unwrap();
+        assert_eq!(block.header.timestamp, millis);
+
+        let json = serde_json::to_string(&block).unwrap();
+        let reparsed: Block = serde_json::from_str(&json).unwrap();
+        assert_eq!(hash_block(&reparsed), hash_block(&block));
+        let mut rewritten = block.clone();
+        let text = block.header.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        rewritten.header.timestamp = text.parse().unwrap();
+        assert_eq!(hash_block(&rewritten), hash_block(&block));
+        let preimage = String::from_utf8(crate::types::block_preimage(&block)).unwrap();
+        assert!(preimage.contains("2024-05-01T12:00:00.123+00:00"), "{}", preimage);
+
+        cons.clock = Arc::new(MockClock::new("2024-05-01T12:00:00Z".parse().unwrap()));
+        let whole = empty_block(&cons, Hash256::ZERO);
+        let preimage = String::from_utf8(crate::types::block_preimage(&whole)).unwrap();
+        assert!(preimage.contains("2024-05-01T12:00:00.000+00:00"), "{}", preimage);
+    }
+
+    #[test]
+    fn large_sets_verify_quickly_and_the_cap_holds() {
+        let validators: Vec<Validator> = (0..5_000)
+            .map(|i| Validator::new(format!("val{}", i), String::new()))
+            .collect();
+        let mut cons = Consensus::new(validators.clone());
+        let block = cons.propose_block(1, Hash256::ZERO, Vec::new(), "val0".to_string()).unwrap();
+        let mut sigs = cons.sign_block(&block);
+        sigs[17].sig = "forged".to_string();
+        let started = std::time::Instant::now();
+        let verified = cons.verify_signatures(&sigs);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2), "{:?}", started.elapsed());
+        assert_eq!(verified.iter().filter(|ok| !**ok).count(), 1);
+        assert!(!verified[17]);
+        assert!(sigs.iter().zip(&verified).all(|(sig, ok)| cons.verify_signature(sig) == *ok));
+
+        assert_eq!(
+            cons.set_max_validators(4_999),
+            Err(ValidatorSetError::TooMany {
+                count: 5_000,
+                max: 4_999
+            })
+        );
+        cons.set_max_validators(5_000).unwrap();
+        let more = (0..5_001)
+            .map(|i| Validator::new(format!("val{}", i), String::new()))
+            .collect();
+        assert_eq!(
+            cons.queue_validators(more),
+            Err(ValidatorSetError::TooMany {
+                count: 5_001,
+                max: 5_000
+            })
+        );
+        assert_eq!(cons.queue_validators(validators), Ok(()));
+    }
+
+    #[test]
+    fn clock_offset_moves_the_future_skew_boundary() {
+        let mut cons = consensus();
+        let start: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        cons.clock = Arc::new(MockClock::new(start));
+        let mut header = empty_block(&cons, Hash256::ZERO).header;
+        header.timestamp = start + Duration::milliseconds(5_500);
+        assert!(matches!(
+            cons.check_timestamp(&header),
+            Err(TimestampError::TooFarAhead { .. })
+        ));
+
+        cons.clock_offset = Duration::seconds(1);
+        assert_eq!(cons.now(), start + Duration::seconds(1));
+        assert_eq!(cons.check_timestamp(&header), Ok(()));
+
+        header.timestamp = start + Duration::milliseconds(4_500);
+        cons.clock_offset = Duration::zero();
+        assert_eq!(cons.check_timestamp(&header), Ok(()));
+        cons.clock_offset = Duration::seconds(-1);
+        assert!(cons.check_timestamp(&header).is_err());
+    }
+
+    #[test]
+    fn transaction_age_is_judged_by_the_corrected_clock() {
+        let mut cons = consensus();
+        let start: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        cons.clock = Arc::new(MockClock::new(start));
+        let max_age = Some(Duration::minutes(1));
+        let created = |at: DateTime<Utc>| Transaction {
+            created_at: Some(at),
+            ..tx("t1")
+        };
+
+        let old = created(start - Duration::seconds(61));
+        assert!(matches!(cons.check_tx_age(&old, max_age), Err(TxAgeError::Stale { .. })));
+        assert_eq!(cons.check_tx_age(&old, None), Ok(()));
+        assert_eq!(cons.check_tx_age(&created(start - Duration::seconds(60)), max_age), Ok(()));
+        assert_eq!(cons.check_tx_age(&tx("t1"), max_age), Ok(()));
+        // A limit reaching back before the earliest representable time
+        // refuses nothing, and doesn't panic.
+        assert_eq!(cons.check_tx_age(&old, Some(Duration::MAX)), Ok(()));
+        cons.clock_offset = Duration::seconds(-2);
+        assert_eq!(cons.check_tx_age(&old, max_age), Ok(()));
+
+        let ahead = created(start + Duration::milliseconds(4_500));
+        assert!(matches!(
+            cons.check_tx_age(&ahead, None),
+            Err(TxAgeError::TooFarAhead { .. })
+        ));
+        cons.clock_offset = Duration::zero();
+        assert_eq!(cons.check_tx_age(&ahead, max_age), Ok(()));
     }
 }
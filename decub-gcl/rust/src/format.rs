@@ -0,0 +1,166 @@
+use serde::Serialize;
+use serde_json::Value;
+
+// Wire format for read responses, picked from the Accept header. JSON
+// unless the client asks for MessagePack. `JsonStringInts` is JSON with
+// every unsigned integer written as a decimal string, for clients whose
+// JSON.parse loses precision above 2^53.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Format {
+    #[default]
+    Json,
+    JsonStringInts,
+    MsgPack,
+}
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const STRING_INTS: &str = "ints=string";
+
+impl Format {
+    pub fn from_accept(accept: Option<String>) -> Self {
+        match accept {
+            Some(accept) if accept.split(',').any(|t| t.trim().starts_with(MSGPACK)) => {
+                Format::MsgPack
+            }
+            Some(accept) if accept.split(';').any(|p| p.trim() == STRING_INTS) => {
+                Format::JsonStringInts
+            }
+            _ => Format::Json,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json | Format::JsonStringInts => "application/json",
+            Format::MsgPack => MSGPACK,
+        }
+    }
+
+    // MessagePack maps keep field names so the output decodes into the same
+    // structs as the JSON does.
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out, value);
+        out
+    }
+
+    fn encode_into<T: Serialize + ?Sized>(self, out: &mut Vec<u8>, value: &T) {
+        match self {
+            Format::Json => serde_json::to_writer(out, value).unwrap(),
+            Format::JsonStringInts => {
+                let mut value = serde_json::to_value(value).unwrap();
+                stringify_ints(&mut value);
+                serde_json::to_writer(out, &value).unwrap()
+            }
+            Format::MsgPack => rmp_serde::encode::write_named(out, value).unwrap(),
+        }
+    }
+
+    // Encodes as many of `values` as fit in about `max_bytes` as one array,
+    // each serialized once, straight into the output. The first always goes
+    // in, however large. Returns the body and how many values it holds.
+    pub fn encode_array<T: Serialize>(self, values: &[T], max_bytes: usize) -> (Vec<u8>, usize) {
+        let mut out = Vec::new();
+        let mut count = 0;
+        if self != Format::MsgPack {
+            out.push(b'[');
+        }
+        for value in values {
+            let mark = out.len();
+            if count > 0 && self != Format::MsgPack {
+                out.push(b',');
+            }
+            self.encode_into(&mut out, value);
+            // A byte for the closing bracket.
+            if count > 0 && out.len() + 1 > max_bytes {
+                out.truncate(mark);
+                break;
+            }
+            count += 1;
+        }
+        if self != Format::MsgPack {
+            out.push(b']');
+            return (out, count);
+        }
+        // MessagePack puts the length up front, so the header is written
+        // once the count is known.
+        let mut header = Vec::with_capacity(5 + out.len());
+        match count {
+            0..=15 => header.push(0x90 | count as u8),
+            16..=0xffff => {
+                header.push(0xdc);
+                header.extend_from_slice(&(count as u16).to_be_bytes());
+            }
+            _ => {
+                header.push(0xdd);
+                header.extend_from_slice(&(count as u32).to_be_bytes());
+            }
+        }
+        header.extend_from_slice(&out);
+        (header, count)
+    }
+}
+
+fn stringify_ints(value: &mut Value) {
+    match value {
+        Value::Number(n) if n.is_u64() => *value = Value::String(n.to_string()),
+        Value::Array(items) => items.iter_mut().for_each(stringify_ints),
+        Value::Object(fields) => fields.values_mut().for_each(stringify_ints),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::Hash256;
+    use crate::types::{BLOCK_VERSION, Block, Header};
+    use chrono::Utc;
+
+    #[test]
+    fn large_heights_survive_a_string_round_trip() {
+        let block = Block {
+            header: Header {
+                version: BLOCK_VERSION,
+                height: u64::MAX - 5,
+                prev_hash: Hash256::ZERO,
+                merkle_root: Hash256::ZERO,
+                sorted_root: Hash256::ZERO,
+                proposer: "val1".to_string(),
+                timestamp: Utc::now(),
+                round: 0,
+                validator_set_hash: Hash256::ZERO,
+                tx_count: 0,
+                total_fees: u64::MAX,
+            },
+            txs: Vec::new(),
+            signatures: Vec::new(),
+        };
+        let accept = Some("application/json; ints=string".to_string());
+        let format = Format::from_accept(accept);
+        assert_eq!(format, Format::JsonStringInts);
+        let body = format.encode(&block);
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["header"]["height"], "18446744073709551610");
+        assert_eq!(serde_json::from_slice::<Block>(&body).unwrap(), block);
+
+        let plain = Format::Json.encode(&block);
+        assert_eq!(serde_json::from_slice::<Block>(&plain).unwrap(), block);
+        let packed = Format::MsgPack.encode(&block);
+        assert_eq!(rmp_serde::from_slice::<Block>(&packed).unwrap(), block);
+    }
+
+    #[test]
+    fn arrays_match_whole_encodings_and_stop_at_the_byte_limit() {
+        let values: Vec<u64> = (0..20).map(|i| i * 1000).collect();
+        for format in [Format::Json, Format::JsonStringInts, Format::MsgPack] {
+            assert_eq!(format.encode_array(&values, usize::MAX), (format.encode(&values), 20));
+            assert_eq!(format.encode_array(&[0u64; 0], 0), (format.encode(&[0u64; 0]), 0));
+            let (first, count) = format.encode_array(&values[1..], 1);
+            assert_eq!((first, count), (format.encode(&values[1..2]), 1));
+            let limit = format.encode(&values[..5]).len() + 1;
+            let (body, count) = format.encode_array(&values, limit);
+            assert_eq!((body, count), (format.encode(&values[..5]), 5), "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum GzipError {
+    Malformed { reason: &'static str },
+    TooLarge { limit: usize },
+}
+
+impl fmt::Display for GzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GzipError::Malformed { reason } => write!(f, "malformed gzip body: {}", reason),
+            GzipError::TooLarge { limit } => {
+                write!(f, "decompressed body would exceed {} bytes", limit)
+            }
+        }
+    }
+}
+
+fn malformed(reason: &'static str) -> GzipError {
+    GzipError::Malformed { reason }
+}
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+// Decompresses one or more concatenated gzip members (RFC 1952), checking
+// each one's CRC and length. Output stops at `limit` bytes: anything longer
+// is refused as soon as it gets there, so a bomb is never inflated whole.
+pub fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, GzipError> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    loop {
+        pos = header_end(data, pos)?;
+        let start = out.len();
+        let mut bits = Bits::new(data, pos);
+        inflate(&mut bits, &mut out, limit)?;
+        pos = bits.byte_pos();
+        let trailer = data.get(pos..pos + 8).ok_or(malformed("truncated trailer"))?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc32fast::hash(&out[start..]) != crc {
+            return Err(malformed("crc mismatch"));
+        }
+        // ISIZE is the length modulo 2^32.
+        if (out.len() - start) as u32 != size {
+            return Err(malformed("length mismatch"));
+        }
+        pos += 8;
+        if pos == data.len() {
+            return Ok(out);
+        }
+    }
+}
+
+// Where the deflate stream of the member starting at `pos` begins.
+fn header_end(data: &[u8], mut pos: usize) -> Result<usize, GzipError> {
+    let header = data.get(pos..pos + 10).ok_or(malformed("truncated header"))?;
+    if header[..2] != [0x1f, 0x8b] {
+        return Err(malformed("not gzip"));
+    }
+    if header[2] != 8 {
+        return Err(malformed("unknown compression method"));
+    }
+    let flags = header[3];
+    if flags & 0xe0 != 0 {
+        return Err(malformed("reserved flags set"));
+    }
+    pos += 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or(malformed("truncated header"))?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let rest = data.get(pos..).unwrap_or_default();
+            let end = rest.iter().position(|&b| b == 0).ok_or(malformed("truncated header"))?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err(malformed("truncated header"));
+    }
+    Ok(pos)
+}
+
+// Deflate packs bits from the least significant end of each byte.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Bits {
+            data,
+            pos,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn take(&mut self, n: u32) -> Result<u32, GzipError> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(malformed("unexpected end of data"))?;
+            self.buf |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buf & ((1u32 << n) - 1);
+        self.buf = self.buf.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Drops the rest of a partly read byte.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.pos
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+// A canonical Huffman code as counts of codes per length and symbols in
+// code order, decoded a bit at a time.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, GzipError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        // Incomplete codes are allowed (a lone distance code is common);
+        // over-subscribed ones can't be decoded.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(malformed("over-subscribed code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u16, GzipError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(malformed("invalid code"))
+    }
+}
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order code length code lengths are sent in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// One raw deflate stream (RFC 1951), appended to `out`.
+fn inflate(bits: &mut Bits<'_>, out: &mut Vec<u8>, limit: usize) -> Result<(), GzipError> {
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => stored(bits, out, limit)?,
+            1 => {
+                let (lit, dist) = fixed_codes()?;
+                codes(bits, out, limit, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(bits)?;
+                codes(bits, out, limit, &lit, &dist)?;
+            }
+            _ => return Err(malformed("invalid block type")),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn stored(bits: &mut Bits<'_>, out: &mut Vec<u8>, limit: usize) -> Result<(), GzipError> {
+    bits.align();
+    let len = bits.take(16)? as usize;
+    let nlen = bits.take(16)? as usize;
+    if len != !nlen & 0xffff {
+        return Err(malformed("stored block length mismatch"));
+    }
+    let pos = bits.byte_pos();
+    let data = bits.data.get(pos..pos + len).ok_or(malformed("unexpected end of data"))?;
+    if out.len() + len > limit {
+        return Err(GzipError::TooLarge { limit });
+    }
+    out.extend_from_slice(data);
+    bits.pos += len;
+    Ok(())
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), GzipError> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(bits: &mut Bits<'_>) -> Result<(Huffman, Huffman), GzipError> {
+    let nlen = bits.take(5)? as usize + 257;
+    let ndist = bits.take(5)? as usize + 1;
+    let ncode = bits.take(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(malformed("too many length or distance codes"));
+    }
+    let mut clens = [0u8; 19];
+    for &index in &CLEN_ORDER[..ncode] {
+        clens[index] = bits.take(3)? as u8;
+    }
+    let clen = Huffman::new(&clens)?;
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = clen.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = index.checked_sub(1).ok_or(malformed("repeat with no length"))?;
+                (lengths[prev], 3 + bits.take(2)? as usize)
+            }
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(malformed("too many code lengths"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(malformed("no end-of-block code"));
+    }
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..])?))
+}
+
+fn codes(
+    bits: &mut Bits<'_>,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<(), GzipError> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        if symbol < 256 {
+            if out.len() >= limit {
+                return Err(GzipError::TooLarge { limit });
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LEN_BASE.len() {
+            return Err(malformed("invalid length code"));
+        }
+        let len = LEN_BASE[symbol] as usize + bits.take(LEN_EXTRA[symbol] as u32)? as usize;
+        let symbol = dist.decode(bits)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err(malformed("invalid distance code"));
+        }
+        let back = DIST_BASE[symbol] as usize + bits.take(DIST_EXTRA[symbol] as u32)? as usize;
+        if back > out.len() {
+            return Err(malformed("distance too far back"));
+        }
+        if out.len() + len > limit {
+            return Err(GzipError::TooLarge { limit });
+        }
+        // Copies may overlap what they produce, so byte by byte.
+        let start = out.len() - back;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // What python's zlib makes of a batch body, as dynamic and as fixed
+    // Huffman blocks.
+    const DYNAMIC: &str = "1f8b08000000000000ff35ce3b0a80401004d1ab2c1b1bd8eddfab88\
+        9898186b2088775798daa83a99c73cf9bab7633ff39c967fd6b94a7f1471a489b4912ed24786c81899382f0c\
+        8e8084242861094c6882139ef15cfec2339ef18c673ce319cf535edf0f2cddeaa6f2000000";
+    const FIXED: &str = "1f8b08000000000000ffab562aa988cf4c2956b2528806320d94741480942184328250c610\
+        ca04429942283308650ea12c209425543bcc18a8398650830ca12619428d32849a650835cc106a9a21d43843\
+        a8794650f38c60ee829a670435cf086a9e11d43c23a8794650f38ca0e619592ac5d602002cddeaa6f2000000";
+    // "hello" in a stored block, under a header naming the file.
+    const STORED: &str = "1f8b08080000000000ff62617463682e6a736f6e00010500faff68656c6c6f\
+        86a6103605000000";
+    // 10,000 zeros in 46 bytes.
+    const ZEROS: &str = "1f8b08000000000000ffedc1010d000000c2a04aef9fce1c6e40010000000000000000c0bf\
+        016958180a10270000";
+
+    fn batch() -> Vec<u8> {
+        let ids: Vec<String> = (0..30).map(|i| format!("\"tx{}\"", i)).collect();
+        format!("{{\"tx_ids\": [{}]}}", ids.join(", ")).into_bytes()
+    }
+
+    #[test]
+    fn every_block_type_inflates() {
+        assert_eq!(gunzip(&unhex(DYNAMIC), usize::MAX).unwrap(), batch());
+        assert_eq!(gunzip(&unhex(FIXED), usize::MAX).unwrap(), batch());
+        assert_eq!(gunzip(&unhex(STORED), usize::MAX).unwrap(), b"hello");
+        let both = [unhex(STORED), unhex(FIXED)].concat();
+        assert_eq!(gunzip(&both, usize::MAX).unwrap(), [&b"hello"[..], &batch()].concat());
+    }
+
+    #[test]
+    fn output_stops_at_the_limit() {
+        let zeros = unhex(ZEROS);
+        assert_eq!(gunzip(&zeros, 10_000).unwrap(), vec![b'0'; 10_000]);
+        assert_eq!(gunzip(&zeros, 9_999), Err(GzipError::TooLarge { limit: 9_999 }));
+        assert_eq!(gunzip(&unhex(STORED), 4), Err(GzipError::TooLarge { limit: 4 }));
+    }
+
+    #[test]
+    fn damage_is_caught() {
+        let mut flipped = unhex(FIXED);
+        let crc = flipped.len() - 8;
+        flipped[crc] ^= 1;
+        assert_eq!(gunzip(&flipped, usize::MAX), Err(malformed("crc mismatch")));
+        let fixed = unhex(FIXED);
+        assert_eq!(
+            gunzip(&fixed[..fixed.len() - 20], usize::MAX),
+            Err(malformed("unexpected end of data"))
+        );
+        assert_eq!(gunzip(b"{\"tx_ids\": []}", usize::MAX), Err(malformed("not gzip")));
+        assert_eq!(gunzip(&[], usize::MAX), Err(malformed("truncated header")));
+        let mut garbage = unhex(DYNAMIC);
+        garbage[10] |= 0b110;
+        assert_eq!(gunzip(&garbage, usize::MAX), Err(malformed("invalid block type")));
+    }
+}
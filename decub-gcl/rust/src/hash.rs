@@ -0,0 +1,132 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+// A SHA-256 digest. Written as 64 lowercase hex characters in JSON, in
+// hashed preimages and by Display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash256(pub [u8; 32]);
+
+#[derive(Debug, PartialEq)]
+pub enum ParseHashError {
+    BadLength { len: usize },
+    BadHex,
+}
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHashError::BadLength { len } => {
+                write!(f, "hash must be 64 hex characters, got {}", len)
+            }
+            ParseHashError::BadHex => write!(f, "hash contains a non-hex character"),
+        }
+    }
+}
+
+impl std::error::Error for ParseHashError {}
+
+impl Hash256 {
+    // Stands for "no block": the prev_hash of the first block.
+    pub const ZERO: Hash256 = Hash256([0; 32]);
+
+    pub fn digest(data: impl AsRef<[u8]>) -> Self {
+        Hash256(Sha256::digest(data).into())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Hash256::ZERO
+    }
+
+    // Parent of two merkle nodes. The preimage is both children as lowercase
+    // hex, as it always has been, but written into a stack buffer rather
+    // than formatted into a String.
+    pub fn pair(left: &Hash256, right: &Hash256) -> Hash256 {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut preimage = [0u8; 128];
+        for (i, byte) in left.0.iter().chain(&right.0).enumerate() {
+            preimage[2 * i] = HEX[(byte >> 4) as usize];
+            preimage[2 * i + 1] = HEX[(byte & 0xf) as usize];
+        }
+        Hash256::digest(preimage)
+    }
+}
+
+// The one way an integer enters a hashed or binary encoding: eight bytes,
+// most significant first, whatever the machine.
+pub fn write_u64_be(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 {
+            return Err(ParseHashError::BadLength { len: s.len() });
+        }
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseHashError::BadHex);
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let pair = &s[2 * i..2 * i + 2];
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| ParseHashError::BadHex)?;
+        }
+        Ok(Hash256(bytes))
+    }
+}
+
+impl Serialize for Hash256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_parses_and_round_trips() {
+        let hash = Hash256::digest("abc");
+        let hex = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hex.parse::<Hash256>(), Ok(hash));
+        assert_eq!(hex.to_uppercase().parse::<Hash256>(), Ok(hash));
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex));
+        assert_eq!(serde_json::from_str::<Hash256>(&json).unwrap(), hash);
+        let packed = rmp_serde::to_vec(&hash).unwrap();
+        assert_eq!(rmp_serde::from_slice::<Hash256>(&packed).unwrap(), hash);
+    }
+
+    #[test]
+    fn malformed_hex_is_rejected() {
+        assert_eq!("".parse::<Hash256>(), Err(ParseHashError::BadLength { len: 0 }));
+        assert_eq!("ab".repeat(33).parse::<Hash256>(), Err(ParseHashError::BadLength { len: 66 }));
+        assert_eq!("zz".repeat(32).parse::<Hash256>(), Err(ParseHashError::BadHex));
+        assert_eq!(format!("+1{}", "0".repeat(62)).parse::<Hash256>(), Err(ParseHashError::BadHex));
+        assert!(serde_json::from_str::<Hash256>("\"1234\"").is_err());
+        assert!(Hash256::ZERO.is_zero());
+        assert_eq!(Hash256::ZERO.to_string(), "0".repeat(64));
+    }
+}
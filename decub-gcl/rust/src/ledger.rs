@@ -0,0 +1,1084 @@
+use crate::chain::{ChainError, validate_block, validate_chain};
+use crate::consensus::Consensus;
+use crate::hash::Hash256;
+use crate::mmr::Mmr;
+use crate::store::BlockStore;
+use crate::transfer::{TransferError, apply_transfer, is_transfer, parse_transfer};
+use crate::types::{
+    AccountTx, Block, ChainEvent, ProposalStage, StateAt, Transaction, hash_block,
+};
+use crate::wal::Wal;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+// Events a slow subscriber can fall behind by before it starts missing some.
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug)]
+pub enum LedgerError {
+    Io(io::Error),
+    Transfer { tx_id: String, error: TransferError },
+    BrokenChain(ChainError),
+    UnknownParent { height: u64 },
+    InvalidBlock { height: u64, reason: String },
+    Finalized { fork_height: u64 },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BeforeGenesis {
+    pub valid_until: DateTime<Utc>,
+    pub genesis: DateTime<Utc>,
+}
+
+impl fmt::Display for BeforeGenesis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "valid_until {} precedes genesis {}",
+            self.valid_until.to_rfc3339(),
+            self.genesis.to_rfc3339()
+        )
+    }
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Io(err) => write!(f, "storage error: {}", err),
+            LedgerError::Transfer { tx_id, error } => write!(f, "transaction {}: {}", tx_id, error),
+            LedgerError::BrokenChain(err) => write!(f, "stored chain is broken: {}", err),
+            LedgerError::UnknownParent { height } => {
+                write!(f, "block {} does not link to any known block", height)
+            }
+            LedgerError::InvalidBlock { height, reason } => {
+                write!(f, "block {} is invalid: {}", height, reason)
+            }
+            LedgerError::Finalized { fork_height } => {
+                let dropped = fork_height + 1;
+                write!(f, "branch forks at height {} and would drop final block {}", fork_height,
+                    dropped)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for LedgerError {
+    fn from(err: io::Error) -> Self {
+        LedgerError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+struct Events(broadcast::Sender<ChainEvent>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Events(broadcast::channel(EVENT_BUFFER).0)
+    }
+}
+
+// Derived state saved after a block, for replays to start from.
+#[derive(Debug, Clone, Default)]
+struct Checkpoint {
+    height: u64,
+    balances: HashMap<String, u64>,
+    rewards: HashMap<String, u64>,
+    state_hash: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    // Shared with readers, so serving a block never copies it. This is the
+    // canonical chain, the path through `index` to the tip fork choice
+    // picked; reads by height follow it.
+    blocks: Vec<Arc<Block>>,
+    // Every known block by (height, hash), canonical or competing.
+    index: BTreeMap<(u64, Hash256), Arc<Block>>,
+    tx_log: Mmr,
+    block_reward: u64,
+    rewards: HashMap<String, u64>,
+    balances: HashMap<String, u64>,
+    genesis_balances: HashMap<String, u64>,
+    genesis_timestamp: Option<DateTime<Utc>>,
+    state_hash: String,
+    // (height, index) of every committed transaction, by tx_id (the first
+    // to commit it), by tag and by origin.
+    tx_index: HashMap<String, (u64, usize)>,
+    tag_index: HashMap<String, Vec<(u64, usize)>>,
+    origin_index: HashMap<String, Vec<(u64, usize)>>,
+    generation: u64,
+    // Every `checkpoint_interval` blocks; 0 keeps none.
+    checkpoint_interval: u64,
+    checkpoints: Vec<Checkpoint>,
+    store: Option<Box<dyn BlockStore>>,
+    wal: Option<Wal>,
+    events: Events,
+    debug_events: bool,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tx_log(tx_log: Mmr) -> Self {
+        Ledger {
+            tx_log,
+            ..Ledger::default()
+        }
+    }
+
+    pub fn with_block_reward(mut self, block_reward: u64) -> Self {
+        self.block_reward = block_reward;
+        self
+    }
+
+    pub fn with_genesis_balances(mut self, balances: HashMap<String, u64>) -> Self {
+        self.genesis_balances = balances.clone();
+        self.balances = balances;
+        self
+    }
+
+    pub fn with_debug_events(mut self, enabled: bool) -> Self {
+        self.debug_events = enabled;
+        self
+    }
+
+    pub fn with_checkpoint_interval(mut self, interval: u64) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    pub fn with_genesis_timestamp(mut self, timestamp: Option<DateTime<Utc>>) -> Self {
+        self.genesis_timestamp = timestamp;
+        self
+    }
+
+    // Loads committed blocks from the store, then finishes any block the WAL
+    // holds that the store never got. A store that doesn't outlive the
+    // process has nothing to protect, so it can go without a WAL. Call after the other builders so
+    // replay sees the configured reward and genesis balances. The recovered
+    // chain is validated before anything is replayed; a broken one (say,
+    // after editing the store by hand) is refused rather than served.
+    pub fn recover(
+        mut self,
+        store: Box<dyn BlockStore>,
+        mut wal: Option<Wal>,
+        cons: &Consensus,
+    ) -> Result<Self, LedgerError> {
+        let logged = self.tx_log.leaf_count();
+        let mut position = 0u64;
+        let mut replay = store.load()?;
+        let mut stored_tip = replay.last().map_or(0, |block| block.header.height);
+        let logged_blocks = match &wal {
+            Some(wal) => wal.entries()?,
+            None => Vec::new(),
+        };
+        for block in logged_blocks {
+            if block.header.height > stored_tip {
+                store.put(&block)?;
+                stored_tip = block.header.height;
+                replay.push(block);
+            }
+        }
+        validate_chain(&replay, cons).map_err(LedgerError::BrokenChain)?;
+        for block in replay {
+            let balances = self.next_balances(&block)?;
+            let len = block.txs.len() as u64;
+            let skip = logged.saturating_sub(position).min(len);
+            position += len;
+            self.apply(Arc::new(block), balances, skip as usize)?;
+        }
+        if let Some(wal) = &mut wal {
+            wal.checkpoint()?;
+        }
+        self.store = Some(store);
+        self.wal = wal;
+        Ok(self)
+    }
+
+    // Every append and rollback from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.0.subscribe()
+    }
+
+    // Passes a proposal step on to subscribers, if debug events are on.
+    pub fn publish_proposal(&self, stage: ProposalStage) {
+        if self.debug_events {
+            let _ = self.events.0.send(ChainEvent::Proposal(stage));
+        }
+    }
+
+    pub fn blocks(&self) -> &[Arc<Block>] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn tip(&self) -> Option<&Arc<Block>> {
+        self.blocks.last()
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.blocks.last().map_or(0, |block| block.header.height)
+    }
+
+    // None once the chain has reached u64::MAX.
+    pub fn next_height(&self) -> Option<u64> {
+        height_after(self.tip_height())
+    }
+
+    // Hash the next block must link to; empty for the first block.
+    pub fn tip_hash(&self) -> Hash256 {
+        self.blocks.last().map(|block| hash_block(block)).unwrap_or_default()
+    }
+
+    pub fn get(&self, height: u64) -> Option<&Arc<Block>> {
+        let index = usize::try_from(height.checked_sub(1)?).ok()?;
+        self.blocks.get(index)
+    }
+
+    // Every known block at `height`, canonical or not, in hash order.
+    pub fn known_at(&self, height: u64) -> Vec<&Arc<Block>> {
+        let range = (height, Hash256::ZERO)..=(height, Hash256([0xff; 32]));
+        self.index.range(range).map(|(_, block)| block).collect()
+    }
+
+    // Up to `limit` consecutive blocks starting at height `from`.
+    pub fn blocks_from(&self, from: u64, limit: usize) -> &[Arc<Block>] {
+        let start = usize::try_from(from.saturating_sub(1)).unwrap_or(usize::MAX);
+        let start = start.min(self.blocks.len());
+        let end = start.saturating_add(limit).min(self.blocks.len());
+        &self.blocks[start..end]
+    }
+
+    // Latest block stamped at or before `time`. Timestamps never decrease
+    // along a valid chain, so this is a binary search.
+    pub fn block_at(&self, time: DateTime<Utc>) -> Option<&Arc<Block>> {
+        let after = self.blocks.partition_point(|block| block.header.timestamp <= time);
+        after.checked_sub(1).map(|index| &self.blocks[index])
+    }
+
+    pub fn find_tx(&self, tx_id: &str) -> Option<(&Arc<Block>, usize)> {
+        let &(height, index) = self.tx_index.get(tx_id)?;
+        Some((self.get(height)?, index))
+    }
+
+    pub fn is_committed(&self, tx_id: &str) -> bool {
+        self.tx_index.contains_key(tx_id)
+    }
+
+    // A block is final once at least `depth` blocks have been built on it.
+    pub fn is_final(&self, height: u64, depth: u64) -> bool {
+        self.tip_height().saturating_sub(height) >= depth
+    }
+
+    // Running hash over every block hash in order, so two ledgers are equal
+    // exactly when their state hashes are. Empty for an empty ledger.
+    pub fn state_hash(&self) -> &str {
+        &self.state_hash
+    }
+
+    // Bumped by every rollback, so anything derived from the chain can tell
+    // whether it is stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn tx_log(&self) -> &Mmr {
+        &self.tx_log
+    }
+
+    pub fn rewards(&self) -> &HashMap<String, u64> {
+        &self.rewards
+    }
+
+    // Committed transactions carrying `tag`, in chain order.
+    pub fn tagged(&self, tag: &str) -> Vec<&Transaction> {
+        let Some(positions) = self.tag_index.get(tag) else {
+            return Vec::new();
+        };
+        positions
+            .iter()
+            .filter_map(|&(height, index)| self.get(height)?.txs.get(index))
+            .collect()
+    }
+
+    // The committed transactions `origin` sent, in chain order, skipping the
+    // first `from`. Unknown origins have none.
+    pub fn account_txs(&self, origin: &str, from: usize, limit: usize) -> Vec<AccountTx> {
+        let Some(positions) = self.origin_index.get(origin) else {
+            return Vec::new();
+        };
+        positions
+            .iter()
+            .skip(from)
+            .take(limit)
+            .filter_map(|&(height, index)| {
+                let tx = self.get(height)?.txs.get(index)?.clone();
+                Some(AccountTx { height, index, tx })
+            })
+            .collect()
+    }
+
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    // Nothing can have expired before the chain began, so a valid_until
+    // earlier than genesis marks a backdated transaction.
+    pub fn check_timestamp(&self, tx: &Transaction) -> Result<(), BeforeGenesis> {
+        match (tx.valid_until, self.genesis_timestamp) {
+            (Some(valid_until), Some(genesis)) if valid_until < genesis => {
+                Err(BeforeGenesis { valid_until, genesis })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Replays the blocks up to `height` from the nearest checkpoint at or
+    // below it, or from genesis. None past the tip.
+    pub fn state_at(&self, height: u64) -> Option<StateAt> {
+        if height > self.tip_height() {
+            return None;
+        }
+        let start = self.checkpoints.iter().rev().find(|c| c.height <= height);
+        let mut state = start.cloned().unwrap_or_else(|| Checkpoint {
+            balances: self.genesis_balances.clone(),
+            ..Checkpoint::default()
+        });
+        let replay = self.blocks.iter().skip_while(|b| b.header.height <= state.height);
+        for block in replay.take_while(|b| b.header.height <= height) {
+            // Committed blocks applied cleanly once, so they do again.
+            for tx in &block.txs {
+                let _ = apply_transfer(&mut state.balances, tx);
+            }
+            let earned = state.rewards.entry(block.header.proposer.clone()).or_default();
+            *earned = earned.saturating_add(self.block_reward);
+            state.state_hash = chain_state_hash(&state.state_hash, &hash_block(block));
+        }
+        Some(StateAt {
+            height,
+            state_hash: state.state_hash,
+            balances: state.balances.into_iter().collect(),
+            rewards: state.rewards.into_iter().collect(),
+        })
+    }
+
+    // Submission-time check against committed balances. Pending transfers are
+    // not counted here; the proposer re-checks them in order.
+    pub fn check_transfer(&self, tx: &Transaction) -> Result<(), TransferError> {
+        if !is_transfer(tx) {
+            return Ok(());
+        }
+        let payload = parse_transfer(tx)?;
+        let balance = self.balance(&tx.origin);
+        if balance < payload.amount {
+            return Err(TransferError::InsufficientBalance {
+                balance,
+                amount: payload.amount,
+            });
+        }
+        Ok(())
+    }
+
+    // Splits candidate transactions into those that apply cleanly, in order,
+    // on top of current balances and those that would fail.
+    pub fn check_transfers(
+        &self,
+        txs: Vec<Transaction>,
+    ) -> (Vec<Transaction>, Vec<(Transaction, TransferError)>) {
+        let mut balances = self.balances.clone();
+        let mut valid = Vec::new();
+        let mut rejected = Vec::new();
+        for tx in txs {
+            match apply_transfer(&mut balances, &tx) {
+                Ok(()) => valid.push(tx),
+                Err(err) => rejected.push((tx, err)),
+            }
+        }
+        (valid, rejected)
+    }
+
+    // Balances move only if every transfer in the block applies. With
+    // storage attached the block goes to the WAL, then the store, and only
+    // then into memory; the WAL is cleared once all three have it.
+    pub fn append(&mut self, block: Block) -> Result<(), LedgerError> {
+        let balances = self.next_balances(&block)?;
+        if let Some(wal) = &mut self.wal {
+            wal.append(&block)?;
+        }
+        if let Some(store) = &self.store {
+            store.put(&block)?;
+        }
+        let event = ChainEvent::Block {
+            height: block.header.height,
+            hash: hash_block(&block),
+        };
+        self.apply(Arc::new(block), balances, 0)?;
+        if let Some(wal) = &mut self.wal {
+            wal.checkpoint()?;
+        }
+        // No subscribers is not an error.
+        let _ = self.events.0.send(event);
+        Ok(())
+    }
+
+    // Whether the block's parent is known, canonical or not.
+    pub fn links(&self, block: &Block) -> bool {
+        let (height, prev) = (block.header.height, block.header.prev_hash);
+        match height {
+            0 => false,
+            1 => prev.is_zero(),
+            _ => self.index.contains_key(&(height - 1, prev)),
+        }
+    }
+
+    // Whether this exact block is known, canonical or not.
+    pub fn knows(&self, block: &Block) -> bool {
+        self.index.contains_key(&(block.header.height, hash_block(block)))
+    }
+
+    // Records a block that may compete with the canonical one at its height.
+    // Its parent must be known, canonical or not. Fork choice prefers the
+    // longest branch, then the lower tip hash, so every node holding the
+    // same blocks settles on the same tip. When the new block wins, the
+    // chain reorgs onto its branch; returns whether it did. Every block
+    // recorded or switched to must pass `check_branch_block` against
+    // `cons`, and a reorg may not fork at a block `finality_depth` made
+    // final. A winning branch whose transfers don't apply is refused and
+    // the block not recorded.
+    pub fn insert(
+        &mut self,
+        block: Block,
+        cons: &Consensus,
+        finality_depth: u64,
+    ) -> Result<bool, LedgerError> {
+        let height = block.header.height;
+        let hash = hash_block(&block);
+        if self.index.contains_key(&(height, hash)) {
+            return Ok(false);
+        }
+        if !self.links(&block) {
+            return Err(LedgerError::UnknownParent { height });
+        }
+        let tip = (self.tip_height(), self.tip_hash());
+        check_branch_block(&block, cons, tip.0)?;
+        let prev = block.header.prev_hash;
+        let wins = height > tip.0 || (height == tip.0 && hash < tip.1);
+        if !wins {
+            self.index.insert((height, hash), Arc::new(block));
+            return Ok(false);
+        }
+        // Walk back to where the branch leaves the canonical chain.
+        let mut branch = vec![Arc::new(block)];
+        let (mut at, mut prev) = (height - 1, prev);
+        while at > 0 && self.get(at).is_none_or(|block| hash_block(block) != prev) {
+            let Some(block) = self.index.get(&(at, prev)).cloned() else {
+                return Err(LedgerError::UnknownParent { height: at + 1 });
+            };
+            prev = block.header.prev_hash;
+            branch.push(block);
+            at -= 1;
+        }
+        branch.reverse();
+        // Blocks up to `at` survive; the first one dropped must not be final.
+        if at < tip.0 && self.is_final(at + 1, finality_depth) {
+            return Err(LedgerError::Finalized { fork_height: at });
+        }
+        // The rest were checked when recorded, but a validator jailed since
+        // no longer counts toward their quorum.
+        for block in &branch[..branch.len() - 1] {
+            check_branch_block(block, cons, tip.0)?;
+        }
+        let mut balances = self
+            .state_at(at)
+            .map_or_else(HashMap::new, |state| state.balances.into_iter().collect());
+        for tx in branch.iter().flat_map(|block| &block.txs) {
+            apply_transfer(&mut balances, tx).map_err(|error| LedgerError::Transfer {
+                tx_id: tx.tx_id.clone(),
+                error,
+            })?;
+        }
+        self.rewind(at)?;
+        for block in branch {
+            self.append(Arc::unwrap_or_clone(block))?;
+        }
+        Ok(true)
+    }
+
+    // Drops every block above `height` from memory, the index, the store and
+    // the tx log, then replays the rest from genesis to rebuild balances,
+    // rewards and the state hash. Returns the removed blocks, oldest first.
+    pub fn rollback(&mut self, height: u64) -> Result<Vec<Arc<Block>>, LedgerError> {
+        let removed = self.rewind(height)?;
+        self.index.retain(|(at, _), _| *at <= height);
+        Ok(removed)
+    }
+
+    // The canonical half of a rollback: blocks off the chain stay indexed.
+    fn rewind(&mut self, height: u64) -> Result<Vec<Arc<Block>>, LedgerError> {
+        if height >= self.tip_height() {
+            return Ok(Vec::new());
+        }
+        let keep = self.blocks.iter().take_while(|b| b.header.height <= height).count();
+        let removed = self.blocks.split_off(keep);
+        let removed_txs: u64 = removed.iter().map(|block| block.txs.len() as u64).sum();
+        self.tx_log.truncate(self.tx_log.leaf_count().saturating_sub(removed_txs))?;
+        if let Some(store) = &self.store {
+            store.truncate(height)?;
+        }
+        self.balances = self.genesis_balances.clone();
+        self.rewards.clear();
+        self.state_hash.clear();
+        self.tx_index.clear();
+        self.tag_index.clear();
+        self.origin_index.clear();
+        self.checkpoints.clear();
+        for block in std::mem::take(&mut self.blocks) {
+            let balances = self.next_balances(&block)?;
+            let skip = block.txs.len();
+            self.apply(block, balances, skip)?;
+        }
+        self.generation += 1;
+        if let (Some(first), Some(last)) = (removed.first(), removed.last()) {
+            let _ = self.events.0.send(ChainEvent::Reorg {
+                from_height: first.header.height,
+                to_height: last.header.height,
+            });
+        }
+        Ok(removed)
+    }
+
+    fn next_balances(&self, block: &Block) -> Result<HashMap<String, u64>, LedgerError> {
+        let mut balances = self.balances.clone();
+        for tx in &block.txs {
+            apply_transfer(&mut balances, tx).map_err(|error| LedgerError::Transfer {
+                tx_id: tx.tx_id.clone(),
+                error,
+            })?;
+        }
+        Ok(balances)
+    }
+
+    // `skip` leading transactions are already in the tx log (replay after a
+    // restart with a persisted log).
+    fn apply(
+        &mut self,
+        block: Arc<Block>,
+        balances: HashMap<String, u64>,
+        skip: usize,
+    ) -> Result<(), LedgerError> {
+        for tx in &block.txs[skip..] {
+            self.tx_log.append_tx(tx)?;
+        }
+        let height = block.header.height;
+        for (index, tx) in block.txs.iter().enumerate() {
+            self.tx_index.entry(tx.tx_id.clone()).or_insert((height, index));
+            for tag in &tx.tags {
+                self.tag_index.entry(tag.clone()).or_default().push((height, index));
+            }
+            self.origin_index.entry(tx.origin.clone()).or_default().push((height, index));
+        }
+        self.balances = balances;
+        let earned = self.rewards.entry(block.header.proposer.clone()).or_default();
+        *earned = earned.saturating_add(self.block_reward);
+        let hash = hash_block(&block);
+        self.state_hash = chain_state_hash(&self.state_hash, &hash);
+        if self.checkpoint_interval > 0 && height.is_multiple_of(self.checkpoint_interval) {
+            self.checkpoints.push(Checkpoint {
+                height,
+                balances: self.balances.clone(),
+                rewards: self.rewards.clone(),
+                state_hash: self.state_hash.clone(),
+            });
+        }
+        self.index.insert((height, hash), block.clone());
+        self.blocks.push(block);
+        Ok(())
+    }
+}
+
+// What a block off the canonical chain must pass before the chain may
+// switch to it: the checks POST /gcl/block makes of a block extending the
+// tip. At heights up to `tip_height` the node's round has moved on, so the
+// proposer is checked against the round the block was made in.
+fn check_branch_block(block: &Block, cons: &Consensus, tip_height: u64) -> Result<(), LedgerError> {
+    let invalid = |reason: String| LedgerError::InvalidBlock {
+        height: block.header.height,
+        reason,
+    };
+    validate_block(block, cons).map_err(|err| invalid(err.to_string()))?;
+    let proposer = if block.header.height <= tip_height {
+        cons.check_proposer_in_round(&block.header)
+    } else {
+        cons.check_proposer(&block.header)
+    };
+    proposer.map_err(|err| invalid(err.to_string()))?;
+    if !cons.verify_quorum(block, &block.signatures) {
+        return Err(invalid("quorum not reached".to_string()));
+    }
+    Ok(())
+}
+
+pub fn height_after(height: u64) -> Option<u64> {
+    height.checked_add(1)
+}
+
+// Folds a block into the running state hash: the digest of the previous
+// state hash (empty before the first block) followed by the block hash, both
+// as hex.
+fn chain_state_hash(state_hash: &str, block_hash: &Hash256) -> String {
+    Hash256::digest(format!("{}{}", state_hash, block_hash)).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::store::SledStore;
+    use crate::test_util::{consensus, tx};
+    use crate::transfer::TRANSFER;
+    use chrono::Duration;
+    use std::sync::Arc;
+
+    #[test]
+    fn empty_ledger_starts_at_height_one() {
+        let ledger = Ledger::new();
+        assert_eq!(ledger.next_height(), Some(1));
+        assert_eq!(ledger.tip_hash(), Hash256::ZERO);
+        assert!(ledger.get(0).is_none());
+        assert!(ledger.get(1).is_none());
+        assert!(ledger.get(u64::MAX).is_none());
+    }
+
+    #[test]
+    fn block_at_finds_the_block_active_at_a_time() {
+        let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(MockClock::new(start));
+        let mut cons = consensus();
+        cons.clock = clock.clone();
+        let mut ledger = Ledger::new();
+        for height in 1..=4 {
+            let prev_hash = ledger.tip_hash();
+            let block = cons.propose_block(height, prev_hash, Vec::new(), "val1".to_string());
+            ledger.append(block.unwrap()).unwrap();
+            clock.advance(Duration::seconds(10));
+        }
+        let at = |secs: i64| {
+            let block = ledger.block_at(start + Duration::seconds(secs));
+            block.map(|b| b.header.height)
+        };
+        assert_eq!(at(0), Some(1));
+        assert_eq!(at(20), Some(3));
+        assert_eq!(at(25), Some(3));
+        assert_eq!(at(29), Some(3));
+        assert_eq!(at(3600), Some(4));
+        assert_eq!(at(-1), None);
+        assert_eq!(Ledger::new().block_at(start), None);
+    }
+
+    #[test]
+    fn height_stops_at_u64_max() {
+        assert_eq!(height_after(u64::MAX - 1), Some(u64::MAX));
+        assert_eq!(height_after(u64::MAX), None);
+    }
+
+    #[test]
+    fn rewards_follow_rotating_proposer() {
+        let cons = consensus();
+        let mut ledger = Ledger::new().with_block_reward(5);
+        for height in 1..=7 {
+            let proposer = cons.proposer_for(height).unwrap().id.clone();
+            let block = cons.propose_block(height, Hash256::ZERO, Vec::new(), proposer).unwrap();
+            ledger.append(block).unwrap();
+        }
+        assert_eq!(ledger.rewards()["val1"], 15);
+        assert_eq!(ledger.rewards()["val2"], 10);
+        assert_eq!(ledger.rewards()["val3"], 10);
+    }
+
+    #[test]
+    fn state_hash_detects_divergence() {
+        let cons = consensus();
+        let mut blocks = Vec::new();
+        let mut prev_hash = Hash256::ZERO;
+        for height in 1..=4 {
+            let txs = vec![tx(&format!("tx{}", height))];
+            let block = cons.propose_block(height, prev_hash, txs, "val1".to_string()).unwrap();
+            prev_hash = hash_block(&block);
+            blocks.push(block);
+        }
+
+        let mut a = Ledger::new();
+        let mut b = Ledger::new();
+        for block in &blocks {
+            a.append(block.clone()).unwrap();
+            b.append(block.clone()).unwrap();
+        }
+        assert_eq!(a.state_hash(), b.state_hash());
+        assert_ne!(a.state_hash(), Ledger::new().state_hash());
+
+        let mut c = Ledger::new();
+        for block in &blocks[..3] {
+            c.append(block.clone()).unwrap();
+        }
+        let fork_txs = vec![tx("other")];
+        let fork_hash = hash_block(&blocks[2]);
+        let fork = cons.propose_block(4, fork_hash, fork_txs, "val1".to_string()).unwrap();
+        c.append(fork).unwrap();
+        assert_ne!(a.state_hash(), c.state_hash());
+    }
+
+    fn transfer(id: &str, from: &str, to: &str, amount: u64) -> Transaction {
+        Transaction {
+            tx_type: TRANSFER.to_string(),
+            origin: from.to_string(),
+            payload: format!(r#"{{"to":"{}","amount":{}}}"#, to, amount),
+            ..tx(id)
+        }
+    }
+
+    fn genesis() -> Ledger {
+        let balances = HashMap::from([("alice".to_string(), 100), ("bob".to_string(), 20)]);
+        Ledger::new().with_genesis_balances(balances)
+    }
+
+    fn append_txs(ledger: &mut Ledger, txs: Vec<Transaction>) -> Result<(), LedgerError> {
+        let height = ledger.next_height().unwrap();
+        let cons = consensus();
+        let block = cons.propose_block(height, ledger.tip_hash(), txs, "val1".to_string()).unwrap();
+        ledger.append(block)
+    }
+
+    #[test]
+    fn reads_share_the_committed_block() {
+        let mut ledger = Ledger::new();
+        append_txs(&mut ledger, vec![tx("t1"), tx("t2")]).unwrap();
+        let block = ledger.get(1).unwrap();
+        assert!(Arc::ptr_eq(block, ledger.tip().unwrap()));
+        assert!(Arc::ptr_eq(block, &ledger.blocks_from(1, 10)[0]));
+        assert!(Arc::ptr_eq(block, ledger.find_tx("t2").unwrap().0));
+        let at = ledger.block_at(block.header.timestamp).unwrap();
+        assert!(Arc::ptr_eq(block, at));
+
+        let held = block.clone();
+        assert_eq!(Arc::strong_count(&held), 3);
+        let removed = ledger.rollback(0).unwrap();
+        assert!(Arc::ptr_eq(&held, &removed[0]));
+    }
+
+    #[test]
+    fn transfer_moves_balance() {
+        let mut ledger = genesis();
+        append_txs(&mut ledger, vec![transfer("t1", "alice", "carol", 30)]).unwrap();
+        assert_eq!(ledger.balance("alice"), 70);
+        assert_eq!(ledger.balance("carol"), 30);
+    }
+
+    #[test]
+    fn overdraft_is_rejected_without_side_effects() {
+        let mut ledger = genesis();
+        let (valid, rejected) = ledger.check_transfers(vec![transfer("t1", "bob", "carol", 21)]);
+        assert!(valid.is_empty());
+        assert_eq!(rejected[0].1, TransferError::InsufficientBalance { balance: 20, amount: 21 });
+
+        let block = vec![transfer("t2", "alice", "carol", 5), transfer("t3", "bob", "carol", 21)];
+        assert!(matches!(
+            append_txs(&mut ledger, block),
+            Err(LedgerError::Transfer { ref tx_id, .. }) if tx_id == "t3"
+        ));
+        assert_eq!(ledger.balance("alice"), 100);
+        assert_eq!(ledger.balance("carol"), 0);
+        assert!(ledger.is_empty());
+    }
+
+    #[test]
+    fn balances_track_several_transfers() {
+        let mut ledger = genesis();
+        let (valid, rejected) = ledger.check_transfers(vec![
+            transfer("t1", "alice", "bob", 60),
+            transfer("t2", "bob", "carol", 70),
+            transfer("t3", "alice", "carol", 50),
+        ]);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0.tx_id, "t3");
+        append_txs(&mut ledger, valid).unwrap();
+        append_txs(&mut ledger, vec![transfer("t4", "carol", "alice", 10)]).unwrap();
+        assert_eq!(ledger.balance("alice"), 50);
+        assert_eq!(ledger.balance("bob"), 10);
+        assert_eq!(ledger.balance("carol"), 60);
+    }
+
+    #[test]
+    fn past_state_is_replayed_from_checkpoints() {
+        let mut ledger = genesis().with_block_reward(5).with_checkpoint_interval(2);
+        let mut plain = genesis().with_block_reward(5);
+        for (i, amount) in [5, 10, 15, 20, 25].into_iter().enumerate() {
+            let txs = vec![transfer(&format!("t{}", i), "alice", "carol", amount)];
+            append_txs(&mut ledger, txs).unwrap();
+            plain.append(Block::clone(ledger.tip().unwrap())).unwrap();
+        }
+        assert_eq!(ledger.checkpoints.len(), 2);
+
+        let now = ledger.state_at(5).unwrap();
+        assert_eq!(now.state_hash, ledger.state_hash());
+        assert_eq!(now.balances["alice"], ledger.balance("alice"));
+        assert_eq!(now.rewards["val1"], 25);
+        let past = ledger.state_at(3).unwrap();
+        assert_eq!((past.balances["alice"], past.balances["carol"]), (70, 30));
+        assert_eq!(past.balances["bob"], 20);
+        assert_eq!(past.rewards["val1"], 15);
+        assert_ne!(past.state_hash, now.state_hash);
+        let genesis_state = ledger.state_at(0).unwrap();
+        assert_eq!(genesis_state.balances["alice"], 100);
+        assert!(genesis_state.rewards.is_empty() && genesis_state.state_hash.is_empty());
+        for height in 0..=5 {
+            assert_eq!(ledger.state_at(height), plain.state_at(height));
+        }
+        assert_eq!(ledger.state_at(6), None);
+
+        ledger.rollback(3).unwrap();
+        assert_eq!(ledger.checkpoints.len(), 1);
+        assert_eq!(ledger.state_at(3), Some(past));
+        assert_eq!(ledger.state_at(4), None);
+    }
+
+    #[test]
+    fn block_in_wal_survives_crash_before_store_commit() {
+        let dir = std::env::temp_dir().join(format!("gcl-ledger-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let open = || {
+            let store = Box::new(SledStore::open(&dir.join("blocks")).unwrap());
+            let wal = Some(Wal::open(&dir.join("wal.log")).unwrap());
+            genesis().recover(store, wal, &consensus()).unwrap()
+        };
+
+        let mut ledger = open();
+        append_txs(&mut ledger, vec![transfer("t1", "alice", "carol", 30)]).unwrap();
+        let txs = vec![transfer("t2", "carol", "bob", 10)];
+        let cons = consensus();
+        let block = cons.propose_block(2, ledger.tip_hash(), txs, "val2".to_string()).unwrap();
+        drop(ledger);
+        // Crash: the block reached the WAL but never the store.
+        Wal::open(&dir.join("wal.log")).unwrap().append(&block).unwrap();
+
+        let ledger = open();
+        assert_eq!(ledger.tip_height(), 2);
+        assert_eq!(ledger.tip_hash(), hash_block(&block));
+        assert_eq!(ledger.balance("carol"), 20);
+        assert_eq!(ledger.balance("bob"), 30);
+        drop(ledger);
+
+        let ledger = open();
+        assert_eq!(ledger.tip_height(), 2);
+        assert!(Wal::open(&dir.join("wal.log")).unwrap().entries().unwrap().is_empty());
+        drop(ledger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_store_is_refused_at_startup() {
+        let dir = std::env::temp_dir().join(format!("gcl-broken-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // One handle throughout: sled's file lock can outlive a dropped handle for a moment.
+        let store = SledStore::open(&dir.join("blocks")).unwrap();
+        let wal = Some(Wal::open(&dir.join("wal.log")).unwrap());
+        let mut ledger = Ledger::new().recover(Box::new(store.clone()), wal, &consensus()).unwrap();
+        for id in ["m1", "m2", "m3"] {
+            append_txs(&mut ledger, vec![tx(id)]).unwrap();
+        }
+        let mut tampered = Block::clone(ledger.get(2).unwrap());
+        drop(ledger);
+        tampered.header.prev_hash = Hash256::ZERO;
+        store.put(&tampered).unwrap();
+
+        let store = Box::new(store);
+        let wal = Some(Wal::open(&dir.join("wal.log")).unwrap());
+        let err = Ledger::new().recover(store, wal, &consensus()).unwrap_err();
+        assert!(matches!(err, LedgerError::BrokenChain(ChainError::BadLinkage { height: 2 })));
+        let diagnostic = "stored chain is broken: block 2 does not link to its predecessor";
+        assert_eq!(err.to_string(), diagnostic);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_earlier_state() {
+        let mut ledger = genesis().with_block_reward(5);
+        append_txs(&mut ledger, vec![transfer("t1", "alice", "carol", 30)]).unwrap();
+        let state_hash = ledger.state_hash().to_string();
+        let tx_root = ledger.tx_log().root();
+        append_txs(&mut ledger, vec![transfer("t2", "carol", "bob", 10), tx("m1")]).unwrap();
+
+        let removed = ledger.rollback(1).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(ledger.tip_height(), 1);
+        assert_eq!(ledger.state_hash(), state_hash);
+        assert_eq!(ledger.tx_log().root(), tx_root);
+        assert!(ledger.is_committed("t1") && !ledger.is_committed("t2"));
+        assert!(ledger.find_tx("m1").is_none());
+        assert_eq!((ledger.balance("carol"), ledger.balance("bob")), (30, 20));
+        assert_eq!(ledger.rewards()["val1"], 5);
+        assert_eq!(ledger.generation(), 1);
+        assert!(ledger.rollback(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn competing_blocks_share_the_index_and_fork_choice_picks_one() {
+        let mut ledger = genesis();
+        append_txs(&mut ledger, vec![tx("t1")]).unwrap();
+        let cons = consensus();
+        let signed = |block: Block| Block {
+            signatures: cons.sign_block(&block),
+            ..block
+        };
+        let rival = |txs| cons.propose_block(2, ledger.tip_hash(), txs, "val2".to_string());
+        let a = signed(rival(vec![transfer("a", "alice", "carol", 30)]).unwrap());
+        let b = signed(rival(vec![transfer("b", "alice", "dave", 40)]).unwrap());
+        let (winner, loser) = if hash_block(&a) < hash_block(&b) { (a, b) } else { (b, a) };
+
+        assert!(ledger.insert(loser.clone(), &cons, 2).unwrap());
+        assert!(ledger.insert(winner.clone(), &cons, 2).unwrap());
+        assert!(!ledger.insert(loser.clone(), &cons, 2).unwrap());
+        let known: Vec<Hash256> = ledger.known_at(2).iter().map(|b| hash_block(b)).collect();
+        assert_eq!(known.len(), 2);
+        assert!(known.contains(&hash_block(&winner)) && known.contains(&hash_block(&loser)));
+        assert_eq!(hash_block(ledger.get(2).unwrap()), hash_block(&winner));
+        let spent = |block: &Block| if block.txs[0].tx_id == "a" { 30 } else { 40 };
+        assert_eq!(ledger.balance("alice"), 100 - spent(&winner));
+
+        // A longer branch wins whatever its hashes, but only with a quorum.
+        let next = cons.propose_block(3, hash_block(&loser), vec![tx("t3")], "val3".to_string());
+        let next = next.unwrap();
+        assert!(matches!(
+            ledger.insert(next.clone(), &cons, 2),
+            Err(LedgerError::InvalidBlock { height: 3, .. })
+        ));
+        assert_eq!(hash_block(ledger.get(2).unwrap()), hash_block(&winner));
+        assert!(ledger.insert(signed(next), &cons, 2).unwrap());
+        assert_eq!(ledger.tip_height(), 3);
+        assert_eq!(hash_block(ledger.get(2).unwrap()), hash_block(&loser));
+        assert_eq!(ledger.balance("alice"), 100 - spent(&loser));
+        assert_eq!(ledger.known_at(2).len(), 2);
+
+        let orphan = cons.propose_block(5, Hash256::digest("nowhere"), Vec::new(), "val1".into());
+        assert!(matches!(
+            ledger.insert(orphan.unwrap(), &cons, 2),
+            Err(LedgerError::UnknownParent { height: 5 })
+        ));
+        ledger.rollback(1).unwrap();
+        assert!(ledger.known_at(2).is_empty());
+    }
+
+    #[test]
+    fn branch_blocks_are_judged_by_the_round_they_name() {
+        let mut ledger = genesis();
+        append_txs(&mut ledger, vec![tx("t1")]).unwrap();
+        let fork = ledger.tip_hash();
+        append_txs(&mut ledger, vec![tx("t2")]).unwrap();
+        let cons = consensus();
+        // Height 2 went to a view change elsewhere: its round 1 is val3's.
+        let mut later = consensus();
+        later.view_change(2);
+        let signed = |proposer: &str| {
+            let block = later.propose_block(2, fork, vec![tx("r2")], proposer.into()).unwrap();
+            Block {
+                signatures: cons.sign_block(&block),
+                ..block
+            }
+        };
+        assert!(matches!(
+            ledger.insert(signed("val2"), &cons, 1),
+            Err(LedgerError::InvalidBlock { height: 2, .. })
+        ));
+        assert!(ledger.insert(signed("val3"), &cons, 1).is_ok());
+        assert_eq!(ledger.known_at(2).len(), 2);
+    }
+
+    #[test]
+    fn reorgs_may_not_drop_a_final_block() {
+        let mut ledger = genesis();
+        append_txs(&mut ledger, vec![tx("t1")]).unwrap();
+        let fork = ledger.tip_hash();
+        append_txs(&mut ledger, vec![tx("t2")]).unwrap();
+        let cons = consensus();
+        let signed = |height, prev, id: String, proposer: &str| {
+            let block = cons.propose_block(height, prev, vec![tx(&id)], proposer.into());
+            let block = block.unwrap();
+            Block {
+                signatures: cons.sign_block(&block),
+                ..block
+            }
+        };
+        // A rival to the block at `height` that loses the tie, so only its
+        // child reorgs.
+        let rival = |ledger: &Ledger, height, prev, id: &str, proposer: &str| {
+            (0..)
+                .map(|i| signed(height, prev, format!("{}{}", id, i), proposer))
+                .find(|block| hash_block(block) > ledger.tip_hash())
+                .unwrap()
+        };
+        let r2 = rival(&ledger, 2, fork, "r", "val2");
+        assert!(!ledger.insert(r2.clone(), &cons, 1).unwrap());
+        let r3 = signed(3, hash_block(&r2), "r".to_string(), "val3");
+
+        // At depth 0 the tip is already final, so dropping it is refused.
+        assert!(matches!(
+            ledger.insert(r3.clone(), &cons, 0),
+            Err(LedgerError::Finalized { fork_height: 1 })
+        ));
+        assert_eq!(ledger.tip_height(), 2);
+        assert!(ledger.find_tx("t2").is_some());
+        // At depth 1 block 2 isn't final yet, so the branch may replace it.
+        assert!(ledger.insert(r3, &cons, 1).unwrap());
+        assert_eq!(ledger.tip_height(), 3);
+        assert!(ledger.find_tx("t2").is_none());
+
+        // Now r2 has a block on top: final at depth 1, not at depth 2.
+        let b2 = signed(2, fork, "b2".to_string(), "val2");
+        assert!(!ledger.insert(b2.clone(), &cons, 1).unwrap());
+        let b3 = rival(&ledger, 3, hash_block(&b2), "b", "val3");
+        assert!(!ledger.insert(b3.clone(), &cons, 1).unwrap());
+        let b4 = signed(4, hash_block(&b3), "b4".to_string(), "val1");
+        assert!(matches!(
+            ledger.insert(b4.clone(), &cons, 1),
+            Err(LedgerError::Finalized { fork_height: 1 })
+        ));
+        assert!(ledger.find_tx("r").is_some());
+        assert!(ledger.insert(b4, &cons, 2).unwrap());
+        assert_eq!(ledger.tip_height(), 4);
+        assert!(ledger.find_tx("b2").is_some());
+    }
+
+    #[test]
+    fn transactions_may_not_predate_genesis() {
+        let genesis: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let ledger = Ledger::new().with_genesis_timestamp(Some(genesis));
+        let at = |time: &str| Transaction {
+            valid_until: Some(time.parse().unwrap()),
+            ..tx("t1")
+        };
+        assert_eq!(
+            ledger.check_timestamp(&at("2023-12-31T23:59:59Z")),
+            Err(BeforeGenesis {
+                valid_until: "2023-12-31T23:59:59Z".parse().unwrap(),
+                genesis,
+            })
+        );
+        assert_eq!(ledger.check_timestamp(&at("2024-01-01T00:00:01Z")), Ok(()));
+        assert_eq!(ledger.check_timestamp(&tx("t2")), Ok(()));
+        assert_eq!(Ledger::new().check_timestamp(&at("1970-01-01T00:00:00Z")), Ok(()));
+    }
+}
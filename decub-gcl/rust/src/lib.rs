@@ -0,0 +1,33 @@
+pub mod absence;
+pub mod api;
+pub mod cache;
+pub mod chain;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod consensus;
+pub mod format;
+pub mod gzip;
+pub mod hash;
+pub mod ledger;
+pub mod mempool;
+pub mod merkle;
+pub mod mmr;
+pub mod origins;
+pub mod pow;
+pub mod proposer;
+#[cfg(feature = "client")]
+pub mod replica;
+pub mod rpc;
+pub mod slashing;
+pub mod store;
+pub mod tags;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod transfer;
+pub mod tls;
+pub mod txid;
+pub mod types;
+pub mod validation;
+pub mod wal;
@@ -1,32 +1,121 @@
-mod types;
-mod merkle;
-mod consensus;
-mod api;
+use decub_gcl::api::{self, SharedLedger, SharedMempool};
+use decub_gcl::config::Config;
+use decub_gcl::consensus::{Consensus, SharedConsensus, Validator};
+use decub_gcl::ledger::Ledger;
+use decub_gcl::mempool::Mempool;
+use decub_gcl::mmr::Mmr;
+use decub_gcl::origins::OriginPolicy;
+use decub_gcl::proposer;
+use decub_gcl::store::{self, BlockStore, MemoryStore, SledStore, StorageBackend};
+use decub_gcl::tls;
+use decub_gcl::wal::Wal;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use warp::hyper::service::{make_service_fn, service_fn};
 
-use api::{submit_tx, get_block, get_proof, Ledger};
-use consensus::{Consensus, Validator};
-use std::sync::Arc;
-use warp::Filter;
+const PENDING_FILE: &str = "pending.json";
 
 #[tokio::main]
 async fn main() {
+    let mut config = Config::load().expect("failed to load config");
+    if std::env::args().any(|arg| arg == "--read-only") {
+        config.read_only = true;
+    }
+    let config = Arc::new(config);
+
     // Initialize consensus with mock validators
     let validators = vec![
-        Validator {
-            id: "val1".to_string(),
-            pub_key: "pub1".to_string(),
-        },
-        Validator {
-            id: "val2".to_string(),
-            pub_key: "pub2".to_string(),
-        },
-        Validator {
-            id: "val3".to_string(),
-            pub_key: "pub3".to_string(),
-        },
+        Validator::new("val1".to_string(), "pub1".to_string()),
+        Validator::new("val2".to_string(), "pub2".to_string()),
+        Validator::new("val3".to_string(), "pub3".to_string()),
     ];
-    let cons = Arc::new(Consensus::new(validators));
-    let ledger: Ledger = Arc::new(std::sync::RwLock::new(Vec::new()));
+    let mut cons = Consensus::new(validators);
+    cons.leaf_encoding = config.leaf_encoding;
+    cons.proposer_allow_list = config.proposer_allow_list.clone();
+    cons.epoch_length = config.epoch_length;
+    cons.clock_offset = chrono::Duration::milliseconds(config.clock_offset_ms);
+    cons.max_future_skew = chrono::Duration::milliseconds(config.max_future_skew_ms);
+    if let Some(threshold) = config.threshold {
+        if let Err(err) = cons.set_threshold(threshold) {
+            eprintln!("Refusing to start: {}", err);
+            std::process::exit(1);
+        }
+    }
+    if let Err(err) = cons.set_max_validators(config.max_validators) {
+        eprintln!("Refusing to start: {}", err);
+        std::process::exit(1);
+    }
+    let set_hash = cons.genesis_validator_set_hash();
+    if config.genesis.validator_set_hash.is_some_and(|expected| expected != set_hash) {
+        eprintln!("Refusing to start: validator set hash {} is not the configured one", set_hash);
+        std::process::exit(1);
+    }
+    let cons: SharedConsensus = Arc::new(RwLock::new(cons));
+    let tx_log = match &config.tx_log_path {
+        Some(path) => Mmr::open(path).expect("failed to open transaction log"),
+        None => Mmr::new(),
+    };
+    let ledger = Ledger::with_tx_log(tx_log)
+        .with_block_reward(config.block_reward)
+        .with_checkpoint_interval(config.state_checkpoint_interval)
+        .with_debug_events(config.debug_events)
+        .with_genesis_balances(config.genesis.balances.clone())
+        .with_genesis_timestamp(config.genesis.timestamp);
+    if let Some(dir) = &config.data_dir {
+        std::fs::create_dir_all(dir).expect("failed to create data directory");
+    }
+    // Without a data_dir there is nowhere to put sled, so blocks stay in memory.
+    let backend = (&config.data_dir, config.storage);
+    let (store, wal): (Arc<dyn BlockStore>, Option<Wal>) = match backend {
+        (Some(dir), StorageBackend::Sled) => {
+            let store = SledStore::open(&dir.join("blocks")).expect("failed to open block store");
+            let wal = Wal::open(&dir.join("wal.log")).expect("failed to open write-ahead log");
+            (Arc::new(store), Some(wal))
+        }
+        _ => (Arc::new(MemoryStore::default()), None),
+    };
+    if config.compaction_interval_ms > 0 {
+        let interval = Duration::from_millis(config.compaction_interval_ms);
+        tokio::spawn(store::compact_every(store.clone(), interval));
+    }
+    let ledger = ledger.recover(Box::new(store), wal, &cons.read().unwrap()).unwrap_or_else(|err| {
+        eprintln!("Refusing to start: {}", err);
+        std::process::exit(1);
+    });
+    let ledger: SharedLedger = Arc::new(RwLock::new(ledger));
+    let per_origin_limit = config.max_txs_per_origin_per_block.unwrap_or(usize::MAX);
+    let mut mempool = Mempool::with_capacity(config.max_mempool_size)
+        .with_per_origin_limit(per_origin_limit)
+        .with_pow_difficulty(config.pow_difficulty)
+        .with_min_fee_bump(config.rbf_min_fee_bump)
+        .with_fee_schedule(config.fee_schedule.clone())
+        .with_dependency_timeout(chrono::Duration::milliseconds(config.dependency_timeout_ms))
+        .with_origin_policy(OriginPolicy {
+            allowlist: config.origin_allowlist.clone(),
+            denylist: config.origin_denylist.clone(),
+        });
+    if let Some(ttl) = config.mempool_ttl_ms {
+        mempool = mempool.with_ttl(chrono::Duration::milliseconds(ttl as i64));
+    }
+    if let Some(dir) = &config.data_dir {
+        let restored = mempool.restore(&dir.join(PENDING_FILE));
+        let restored = restored.expect("failed to restore pending transactions");
+        if restored > 0 {
+            println!("Restored {} pending transactions", restored);
+        }
+    }
+    let mempool: SharedMempool = Arc::new(Mutex::new(mempool));
+
+    let interval = Duration::from_millis(config.block_interval_ms);
+    if !config.read_only {
+        tokio::spawn(proposer::run(ledger.clone(), mempool.clone(), cons.clone(), interval));
+    }
+    if let Some(primary) = &config.primary_url {
+        follow_primary(ledger.clone(), cons.clone(), primary, interval, config.finality_depth);
+    }
 
     // Sample block JSON (as comment)
     // {
@@ -34,24 +123,98 @@ async fn main() {
     //     "height": 1,
     //     "prev_hash": "",
     //     "merkle_root": "hash...",
-    //     "proposer": "validator1",
+    //     "proposer": "val1",
     //     "timestamp": "2023-01-01T00:00:00Z"
     //   },
     //   "txs": [
     //     {
     //       "tx_id": "tx1",
-    //       "type": "transfer",
+    //       "tx_type": "transfer",
     //       "origin": "user1",
-    //       "payload": "data",
+    //       "payload": "{\"to\":\"user2\",\"amount\":10}",
     //       "sig": "sig1"
     //     }
     //   ]
     // }
 
-    let routes = submit_tx(ledger.clone(), cons.clone())
-        .or(get_block(ledger.clone()))
-        .or(get_proof(ledger.clone()));
+    let routes = api::routes(ledger.clone(), mempool.clone(), cons.clone(), config.clone());
+    let service = api::catch_panics(warp::service(routes));
+    let service = api::decompress_requests(service, config.clone());
+    let service = api::with_timeout(service, Duration::from_millis(config.request_timeout_ms));
+
+    // A handler panic is answered with a 500 by `catch_panics`; the hook is
+    // what leaves a trace of it.
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("{}\n{}", info, std::backtrace::Backtrace::force_capture());
+    }));
+
+    let shutdown = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let served = match &config.tls {
+        Some(tls_config) => {
+            let acceptor = tls::acceptor(tls_config).unwrap_or_else(|err| {
+                eprintln!("Refusing to start: cannot load TLS certificate: {}", err);
+                std::process::exit(1);
+            });
+            let listener = TcpListener::bind(addr).await.expect("failed to bind");
+            println!("Starting GCL server on :8080 (HTTPS)");
+            tls::serve_tls(listener, acceptor, service, config.clone(), shutdown).await
+        }
+        None => {
+            let make_service = make_service_fn(move |_| {
+                let service = service.clone();
+                async move { Ok::<_, Infallible>(service_fn(service)) }
+            });
+            println!("Starting GCL server on :8080");
+            api::server_options(warp::hyper::Server::bind(&addr), &config)
+                .serve(make_service)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+    };
+    if let Err(err) = served {
+        eprintln!("Server error: {}", err);
+    }
+
+    // Whatever can't be proposed before the deadline is saved for the next
+    // start instead of holding up the exit.
+    let deadline = Instant::now() + Duration::from_millis(config.shutdown_deadline_ms);
+    let remaining = if config.read_only {
+        mempool.lock().unwrap_or_else(PoisonError::into_inner).len()
+    } else {
+        proposer::drain(&ledger, &mempool, &cons, deadline)
+    };
+    if remaining == 0 {
+        return;
+    }
+    match &config.data_dir {
+        Some(dir) => {
+            let mempool = mempool.lock().unwrap_or_else(PoisonError::into_inner);
+            match mempool.persist(&dir.join(PENDING_FILE)) {
+                Ok(()) => println!("Saved {} pending transactions", remaining),
+                Err(err) => eprintln!("Failed to save {} pending transactions: {}", remaining, err),
+            }
+        }
+        None => eprintln!("Dropping {} pending transactions: no data_dir", remaining),
+    }
+}
+
+#[cfg(feature = "client")]
+fn follow_primary(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    primary: &str,
+    interval: Duration,
+    finality_depth: u64,
+) {
+    let client = decub_gcl::client::GclClient::new(primary);
+    tokio::spawn(decub_gcl::replica::follow(ledger, cons, client, interval, finality_depth));
+}
 
-    println!("Starting GCL server on :8080");
-    warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+#[cfg(not(feature = "client"))]
+fn follow_primary(_: SharedLedger, _: SharedConsensus, _: &str, _: Duration, _: u64) {
+    eprintln!("Refusing to start: primary_url needs a build with the client feature");
+    std::process::exit(1);
 }
@@ -0,0 +1,584 @@
+use crate::clock::{Clock, SystemClock};
+use crate::hash::Hash256;
+use crate::origins::OriginPolicy;
+use crate::types::{Transaction, hash_transaction};
+use crate::validation::TxValidator;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+pub const DEFAULT_DEPENDENCY_TIMEOUT_MS: i64 = 60_000;
+
+// Transactions waiting for the proposer, in arrival order. Entries are keyed
+// by hash_transaction so a client retrying a submission can't queue it twice.
+#[derive(Debug)]
+pub struct Mempool {
+    // Each with the time it was queued.
+    pending: VecDeque<(Hash256, Transaction, DateTime<Utc>)>,
+    hashes: HashSet<Hash256>,
+    // Pending transactions that carry a nonce, by (origin, nonce).
+    by_nonce: HashMap<(String, u64), Hash256>,
+    capacity: usize,
+    // Least fee each tx_type pays; types not listed may pay nothing.
+    fee_schedule: HashMap<String, u64>,
+    min_fee_bump: u64,
+    per_origin_limit: usize,
+    pow_difficulty: u32,
+    origin_policy: OriginPolicy,
+    tx_validator: TxValidator,
+    // How long a transaction may wait for a block before it is dropped.
+    ttl: Option<Duration>,
+    // How long a transaction may wait on a dependency nobody has sent.
+    dependency_timeout: Duration,
+    evicted: u64,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MempoolError {
+    Full,
+    Underpriced { tx_type: String, fee: u64, required: u64 },
+    ReplacementUnderpriced { fee: u64, required: u64 },
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Full => write!(f, "mempool full"),
+            MempoolError::Underpriced { tx_type, fee, required } => write!(
+                f,
+                "fee {} is too low; {} transactions pay at least {}",
+                fee, tx_type, required
+            ),
+            MempoolError::ReplacementUnderpriced { fee, required } => write!(
+                f,
+                "replacement fee {} is too low; at least {} is needed",
+                fee, required
+            ),
+        }
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::with_capacity(usize::MAX)
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Mempool {
+            pending: VecDeque::new(),
+            hashes: HashSet::new(),
+            by_nonce: HashMap::new(),
+            capacity,
+            fee_schedule: HashMap::new(),
+            min_fee_bump: 1,
+            per_origin_limit: usize::MAX,
+            pow_difficulty: 0,
+            origin_policy: OriginPolicy::default(),
+            tx_validator: TxValidator::default(),
+            ttl: None,
+            dependency_timeout: Duration::milliseconds(DEFAULT_DEPENDENCY_TIMEOUT_MS),
+            evicted: 0,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Caps how many of one origin's transactions go into a single block.
+    pub fn with_per_origin_limit(mut self, limit: usize) -> Self {
+        self.per_origin_limit = limit;
+        self
+    }
+
+    // Leading zero bits a submission's nonce_pow must produce; 0 turns the
+    // check off. Can be changed while the node runs.
+    pub fn with_pow_difficulty(mut self, difficulty: u32) -> Self {
+        self.pow_difficulty = difficulty;
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: HashMap<String, u64>) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    // How much more a replacement must pay than the transaction it
+    // replaces. Never less than 1, so an equal fee can't bump anything.
+    pub fn with_min_fee_bump(mut self, bump: u64) -> Self {
+        self.min_fee_bump = bump.max(1);
+        self
+    }
+
+    // Origins allowed to submit. Can be changed while the node runs.
+    pub fn with_origin_policy(mut self, origin_policy: OriginPolicy) -> Self {
+        self.origin_policy = origin_policy;
+        self
+    }
+
+    // Per-type rules submissions must pass before they are queued.
+    pub fn with_tx_validator(mut self, tx_validator: TxValidator) -> Self {
+        self.tx_validator = tx_validator;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_dependency_timeout(mut self, timeout: Duration) -> Self {
+        self.dependency_timeout = timeout;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn tx_validator(&self) -> &TxValidator {
+        &self.tx_validator
+    }
+
+    pub fn pow_difficulty(&self) -> u32 {
+        self.pow_difficulty
+    }
+
+    pub fn set_pow_difficulty(&mut self, difficulty: u32) {
+        self.pow_difficulty = difficulty;
+    }
+
+    pub fn origin_policy(&self) -> &OriginPolicy {
+        &self.origin_policy
+    }
+
+    pub fn set_origin_policy(&mut self, origin_policy: OriginPolicy) {
+        self.origin_policy = origin_policy;
+    }
+
+    // Transactions dropped for outliving the TTL since the node started.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn contains(&self, tx_hash: &Hash256) -> bool {
+        self.hashes.contains(tx_hash)
+    }
+
+    // Returns Ok(false) without queueing if the transaction is already
+    // pending. A full mempool still answers for transactions it holds.
+    //
+    // A transaction with the same origin and nonce as a pending one replaces
+    // it, keeping its place in line, if it pays at least `min_fee_bump` more.
+    // Replacing never needs free space.
+    pub fn insert(&mut self, tx: Transaction) -> Result<bool, MempoolError> {
+        let tx_hash = hash_transaction(&tx);
+        if self.hashes.contains(&tx_hash) {
+            return Ok(false);
+        }
+        let required = self.fee_schedule.get(&tx.tx_type).copied().unwrap_or(0);
+        if tx.fee < required {
+            return Err(MempoolError::Underpriced {
+                tx_type: tx.tx_type,
+                fee: tx.fee,
+                required,
+            });
+        }
+        let key = tx.nonce.map(|nonce| (tx.origin.clone(), nonce));
+        if let Some(old_hash) = key.as_ref().and_then(|key| self.by_nonce.get(key)) {
+            let old_hash = *old_hash;
+            let slot = self.pending.iter_mut().find(|(h, ..)| *h == old_hash).unwrap();
+            let required = slot.1.fee.saturating_add(self.min_fee_bump);
+            if tx.fee < required {
+                return Err(MempoolError::ReplacementUnderpriced {
+                    fee: tx.fee,
+                    required,
+                });
+            }
+            *slot = (tx_hash, tx, self.clock.now());
+            self.hashes.remove(&old_hash);
+            self.hashes.insert(tx_hash);
+            self.by_nonce.insert(key.unwrap(), tx_hash);
+            return Ok(true);
+        }
+        if self.is_full() {
+            return Err(MempoolError::Full);
+        }
+        if let Some(key) = key {
+            self.by_nonce.insert(key, tx_hash);
+        }
+        self.hashes.insert(tx_hash);
+        self.pending.push_back((tx_hash, tx, self.clock.now()));
+        Ok(true)
+    }
+
+    // Transactions for the next block. They stay pending until `remove` is
+    // called, so a failed proposal loses nothing.
+    //
+    // Blocks are ordered by fee (highest first), then nonce (none first,
+    // then lowest), then tx_id, never by arrival, so two nodes holding the
+    // same transactions propose the same block. An origin over its
+    // per-block limit keeps its first transactions in that order; the rest
+    // wait for later blocks.
+    pub fn select(&self) -> Vec<Transaction> {
+        let mut ordered: Vec<&Transaction> = self.pending.iter().map(|(_, tx, _)| tx).collect();
+        ordered.sort_by(|a, b| {
+            b.fee
+                .cmp(&a.fee)
+                .then(a.nonce.cmp(&b.nonce))
+                .then_with(|| a.tx_id.cmp(&b.tx_id))
+        });
+        let mut taken: HashMap<&str, usize> = HashMap::new();
+        ordered
+            .into_iter()
+            .filter(|tx| {
+                let count = taken.entry(tx.origin.as_str()).or_default();
+                *count += 1;
+                *count <= self.per_origin_limit
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Splits what `select` chose into the transactions the next block can
+    // hold, in block order, and those whose dependency will never come.
+    // A transaction with `depends_on` goes in once the tx it names is
+    // `committed` or already placed in this block, so it may be moved
+    // behind it: of those free to go, the earliest in `txs` goes first.
+    // The rest wait: for as long as the dependency is pending, and for the
+    // dependency timeout while it is unknown. Once that passes they come
+    // back as orphans, for the caller to drop.
+    pub fn order_dependencies(
+        &self,
+        txs: Vec<Transaction>,
+        committed: impl Fn(&str) -> bool,
+    ) -> (Vec<Transaction>, Vec<Transaction>) {
+        // Positions in `txs` waiting on each tx_id, and those free to go.
+        let mut children: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut free = BTreeSet::new();
+        for (i, tx) in txs.iter().enumerate() {
+            match &tx.depends_on {
+                Some(parent) if !committed(parent) => {
+                    children.entry(parent.as_str()).or_default().push(i);
+                }
+                _ => {
+                    free.insert(i);
+                }
+            }
+        }
+        let mut order = Vec::with_capacity(txs.len());
+        let mut placed = vec![false; txs.len()];
+        while let Some(i) = free.pop_first() {
+            placed[i] = true;
+            order.push(i);
+            free.extend(children.remove(txs[i].tx_id.as_str()).unwrap_or_default());
+        }
+        let pending: HashSet<&str> =
+            self.pending.iter().map(|(_, tx, _)| tx.tx_id.as_str()).collect();
+        let queued: HashMap<Hash256, DateTime<Utc>> =
+            self.pending.iter().map(|(hash, _, queued)| (*hash, *queued)).collect();
+        let cutoff = self.clock.now() - self.dependency_timeout;
+        let orphaned: Vec<usize> = (0..txs.len())
+            .filter(|&i| {
+                let parent = txs[i].depends_on.as_deref().unwrap_or_default();
+                let queued = queued.get(&hash_transaction(&txs[i]));
+                !placed[i] && !pending.contains(parent) && queued.is_some_and(|at| *at <= cutoff)
+            })
+            .collect();
+
+        let mut txs: Vec<Option<Transaction>> = txs.into_iter().map(Some).collect();
+        let mut take = |indices: Vec<usize>| -> Vec<Transaction> {
+            indices.into_iter().filter_map(|i| txs[i].take()).collect()
+        };
+        (take(order), take(orphaned))
+    }
+
+    // Saves every pending transaction, in order, so a shutdown that ran out
+    // of time to propose them loses nothing. The file is replaced whole.
+    pub fn persist(&self, path: &Path) -> io::Result<()> {
+        let txs: Vec<&Transaction> = self.pending.iter().map(|(_, tx, _)| tx).collect();
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(&txs).map_err(io::Error::from)?)?;
+        file.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    // Queues what `persist` saved and deletes the file. Returns how many
+    // transactions were queued; a missing file queues none.
+    pub fn restore(&mut self, path: &Path) -> io::Result<usize> {
+        let txs: Vec<Transaction> = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::from)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut restored = 0;
+        for tx in txs {
+            if self.insert(tx) == Ok(true) {
+                restored += 1;
+            }
+        }
+        fs::remove_file(path)?;
+        Ok(restored)
+    }
+
+    // A committed transaction uses up its nonce, so whatever is pending
+    // under the same origin and nonce goes too.
+    pub fn remove(&mut self, txs: &[Transaction]) {
+        for tx in txs {
+            self.hashes.remove(&hash_transaction(tx));
+            if let Some(nonce) = tx.nonce {
+                if let Some(tx_hash) = self.by_nonce.remove(&(tx.origin.clone(), nonce)) {
+                    self.hashes.remove(&tx_hash);
+                }
+            }
+        }
+        let hashes = &self.hashes;
+        self.pending.retain(|(tx_hash, ..)| hashes.contains(tx_hash));
+    }
+
+    // Drops every transaction queued longer than the TTL ago, so one that
+    // never makes it into a block (underpriced, say) doesn't wait forever.
+    // Returns how many were dropped.
+    pub fn evict_expired(&mut self) -> usize {
+        let Some(ttl) = self.ttl else {
+            return 0;
+        };
+        let cutoff = self.clock.now() - ttl;
+        let expired: Vec<Transaction> = self
+            .pending
+            .iter()
+            .filter(|(_, _, queued)| *queued <= cutoff)
+            .map(|(_, tx, _)| tx.clone())
+            .collect();
+        self.remove(&expired);
+        self.evicted += expired.len() as u64;
+        expired.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn tx(id: &str, origin: &str) -> Transaction {
+        Transaction {
+            origin: origin.to_string(),
+            ..test_util::tx(id)
+        }
+    }
+
+    fn bid(id: &str, nonce: u64, fee: u64) -> Transaction {
+        Transaction {
+            nonce: Some(nonce),
+            fee,
+            ..tx(id, "user1")
+        }
+    }
+
+    fn ids(txs: &[Transaction]) -> Vec<&str> {
+        txs.iter().map(|tx| tx.tx_id.as_str()).collect()
+    }
+
+    #[test]
+    fn arrival_order_does_not_change_the_block() {
+        use crate::merkle::{LeafEncoding, MAX_MERKLE_LEAVES, merkle_root};
+        let set = vec![
+            tx("plain-b", "user2"),
+            bid("low", 3, 1),
+            tx("plain-a", "user3"),
+            bid("high", 7, 9),
+            bid("tied-late", 2, 5),
+            bid("tied-early", 1, 5),
+        ];
+        let mut forward = Mempool::new();
+        let mut backward = Mempool::new();
+        for tx in &set {
+            forward.insert(tx.clone()).unwrap();
+        }
+        for tx in set.iter().rev() {
+            backward.insert(tx.clone()).unwrap();
+        }
+
+        let block = forward.select();
+        assert_eq!(ids(&block), ["high", "tied-early", "tied-late", "low", "plain-a", "plain-b"]);
+        assert_eq!(backward.select(), block);
+        let root = |txs: &[Transaction]| {
+            merkle_root(txs, LeafEncoding::CanonicalTx, MAX_MERKLE_LEAVES).unwrap()
+        };
+        assert_eq!(root(&backward.select()), root(&block));
+    }
+
+    #[test]
+    fn one_origin_cannot_fill_a_block() {
+        let mut mempool = Mempool::new().with_per_origin_limit(2);
+        for i in 1..=5 {
+            mempool.insert(tx(&format!("flood{}", i), "spammer")).unwrap();
+        }
+        mempool.insert(tx("other", "user2")).unwrap();
+
+        let mut blocks = Vec::new();
+        while !mempool.is_empty() {
+            let txs = mempool.select();
+            mempool.remove(&txs);
+            blocks.push(txs);
+        }
+        assert_eq!(ids(&blocks[0]), ["flood1", "flood2", "other"]);
+        assert_eq!(ids(&blocks[1]), ["flood3", "flood4"]);
+        assert_eq!(ids(&blocks[2]), ["flood5"]);
+    }
+
+    #[test]
+    fn pending_transactions_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("gcl-pending-{}.json", std::process::id()));
+        let mut mempool = Mempool::new();
+        for i in 0..20_000 {
+            mempool.insert(tx(&format!("tx{}", i), &format!("user{}", i % 7))).unwrap();
+        }
+        mempool.persist(&path).unwrap();
+
+        let mut restarted = Mempool::new();
+        assert_eq!(restarted.restore(&path).unwrap(), 20_000);
+        assert_eq!(restarted.select(), mempool.select());
+        assert!(!path.exists());
+        assert_eq!(restarted.restore(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn dependents_follow_their_parents_or_time_out() {
+        use crate::clock::MockClock;
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let mut mempool = Mempool::new()
+            .with_dependency_timeout(Duration::seconds(30))
+            .with_clock(clock.clone());
+        let child = |id: &str, parent: &str, fee: u64| Transaction {
+            depends_on: Some(parent.to_string()),
+            fee,
+            ..tx(id, "user2")
+        };
+        for tx in [
+            child("grandchild", "child", 9),
+            child("child", "parent", 5),
+            tx("parent", "user1"),
+            child("of-old", "committed", 1),
+            child("orphan", "ghost", 3),
+        ] {
+            mempool.insert(tx).unwrap();
+        }
+        let committed = |id: &str| id == "committed";
+        let (ready, orphaned) = mempool.order_dependencies(mempool.select(), committed);
+        assert_eq!(ids(&ready), ["of-old", "parent", "child", "grandchild"]);
+        assert!(orphaned.is_empty());
+
+        // A parent left pending holds its children back however long it
+        // takes, while one nobody has seen only gets the timeout.
+        clock.advance(Duration::seconds(31));
+        let without_parent: Vec<Transaction> =
+            mempool.select().into_iter().filter(|tx| tx.tx_id != "parent").collect();
+        let (ready, orphaned) = mempool.order_dependencies(without_parent, committed);
+        assert_eq!(ids(&ready), ["of-old"]);
+        assert_eq!(ids(&orphaned), ["orphan"]);
+    }
+
+    #[test]
+    fn transactions_past_the_ttl_are_evicted() {
+        use crate::clock::MockClock;
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        let mut mempool = Mempool::new().with_ttl(Duration::minutes(60)).with_clock(clock.clone());
+        mempool.insert(bid("stale", 1, 0)).unwrap();
+        clock.advance(Duration::minutes(30));
+        mempool.insert(tx("fresh", "user2")).unwrap();
+        assert_eq!(mempool.evict_expired(), 0);
+
+        clock.advance(Duration::minutes(31));
+        assert_eq!(mempool.evict_expired(), 1);
+        assert_eq!(ids(&mempool.select()), ["fresh"]);
+        assert!(!mempool.contains(&hash_transaction(&bid("stale", 1, 0))));
+        assert_eq!(mempool.evicted(), 1);
+        // Its nonce is free again.
+        assert_eq!(mempool.insert(bid("retry", 1, 0)), Ok(true));
+
+        let mut forever = Mempool::new().with_clock(clock.clone());
+        forever.insert(tx("old", "user1")).unwrap();
+        clock.advance(Duration::days(365));
+        assert_eq!(forever.evict_expired(), 0);
+    }
+
+    #[test]
+    fn each_tx_type_pays_its_scheduled_fee() {
+        let schedule = HashMap::from([("transfer".to_string(), 5), ("deploy".to_string(), 50)]);
+        let mut mempool = Mempool::new().with_fee_schedule(schedule);
+        let priced = |tx_type: &str, fee: u64| Transaction {
+            tx_type: tx_type.to_string(),
+            fee,
+            ..tx(&format!("{}-{}", tx_type, fee), "user1")
+        };
+
+        assert_eq!(mempool.insert(priced("transfer", 5)), Ok(true));
+        let err = mempool.insert(priced("deploy", 5)).unwrap_err();
+        assert_eq!(
+            err,
+            MempoolError::Underpriced {
+                tx_type: "deploy".to_string(),
+                fee: 5,
+                required: 50
+            }
+        );
+        assert_eq!(err.to_string(), "fee 5 is too low; deploy transactions pay at least 50");
+        assert!(mempool.insert(priced("transfer", 4)).is_err());
+        assert_eq!(mempool.insert(priced("deploy", 50)), Ok(true));
+        assert_eq!(mempool.insert(priced("message", 0)), Ok(true));
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn higher_fees_replace_pending_transactions() {
+        let mut mempool = Mempool::new().with_min_fee_bump(5);
+        mempool.insert(bid("first", 1, 10)).unwrap();
+        mempool.insert(tx("other", "user2")).unwrap();
+        assert_eq!(
+            mempool.insert(bid("cheap", 1, 14)),
+            Err(MempoolError::ReplacementUnderpriced { fee: 14, required: 15 })
+        );
+        assert_eq!(
+            mempool.insert(bid("same", 1, 10)),
+            Err(MempoolError::ReplacementUnderpriced { fee: 10, required: 15 })
+        );
+        assert_eq!(mempool.insert(bid("bumped", 1, 15)), Ok(true));
+        assert_eq!(mempool.insert(bid("next", 2, 0)), Ok(true));
+        assert_eq!(mempool.len(), 3);
+
+        let txs = mempool.select();
+        assert_eq!(ids(&txs), ["bumped", "other", "next"]);
+        assert!(!mempool.contains(&hash_transaction(&bid("first", 1, 10))));
+        mempool.remove(&txs);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.insert(bid("first", 1, 10)), Ok(true));
+    }
+}
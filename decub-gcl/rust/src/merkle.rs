@@ -1,74 +1,749 @@
-use crate::types::{MerkleNode, MerkleProof, Transaction, hash_transaction};
-use sha2::{Digest, Sha256};
+use crate::types::{
+    MerkleMultiproof, MerkleProof, MerkleTree, ProofStep, SiblingPosition, Transaction,
+    VerboseProof, hash_transaction,
+};
+use crate::hash::{Hash256, write_u64_be};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-pub fn build_merkle_tree(txs: &[Transaction]) -> Option<(MerkleNode, String)> {
-    if txs.is_empty() {
-        return None;
+pub const MAX_MERKLE_LEAVES: usize = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum MerkleError {
+    TooManyLeaves { count: usize, max: usize },
+    IndexOutOfRange { index: usize, leaves: usize },
+    TooDeep { depth: u32, max: u32 },
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MerkleError::TooManyLeaves { count, max } => {
+                write!(f, "{} transactions exceed the {} leaf limit", count, max)
+            }
+            MerkleError::IndexOutOfRange { index, leaves } => {
+                write!(f, "leaf {} is out of range for {} leaves", index, leaves)
+            }
+            MerkleError::TooDeep { depth, max } => {
+                write!(f, "proof depth {} exceeds the limit of {}", depth, max)
+            }
+        }
     }
+}
 
-    let mut nodes: Vec<MerkleNode> = txs
-        .iter()
-        .map(|tx| MerkleNode {
-            hash: hash_transaction(tx),
-            left: None,
-            right: None,
-        })
-        .collect();
+// How a transaction becomes a leaf. CanonicalTx commits to every field, so
+// a proof also proves the payload and signature. TxId commits to the id
+// alone, which matches verifiers that only know ids but leaves the tx body
+// unauthenticated by the root.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeafEncoding {
+    #[default]
+    CanonicalTx,
+    TxId,
+}
+
+impl LeafEncoding {
+    pub fn leaf_hash(self, tx: &Transaction) -> Hash256 {
+        match self {
+            LeafEncoding::CanonicalTx => hash_transaction(tx),
+            LeafEncoding::TxId => Hash256::digest(&tx.tx_id),
+        }
+    }
+}
+
+// Root committed to by a block with no transactions: the hash of no data,
+// so an empty block is told apart from one whose tree failed to build.
+pub fn empty_root() -> Hash256 {
+    Hash256::digest([])
+}
+
+// Header root for a block's transactions.
+pub fn merkle_root(
+    txs: &[Transaction],
+    encoding: LeafEncoding,
+    max_leaves: usize,
+) -> Result<Hash256, MerkleError> {
+    if txs.len() > max_leaves {
+        return Err(MerkleError::TooManyLeaves {
+            count: txs.len(),
+            max: max_leaves,
+        });
+    }
+    Ok(root_digest(txs, encoding))
+}
+
+// Root of the canonical tree over `txs`, or `empty_root` for none.
+pub fn root_of(txs: &[Transaction]) -> String {
+    root_of_with(txs, LeafEncoding::default())
+}
 
+// Same root as `build_merkle_tree_with`, but each level overwrites the one
+// below in place, so only the leaves are ever held.
+pub fn root_of_with(txs: &[Transaction], encoding: LeafEncoding) -> String {
+    root_digest(txs, encoding).to_string()
+}
+
+fn root_digest(txs: &[Transaction], encoding: LeafEncoding) -> Hash256 {
+    let mut nodes: Vec<Hash256> = txs.iter().map(|tx| encoding.leaf_hash(tx)).collect();
+    if nodes.is_empty() {
+        return empty_root();
+    }
     while nodes.len() > 1 {
-        let mut new_nodes = Vec::new();
-        for chunk in nodes.chunks(2) {
-            let left = &chunk[0];
-            let right = if chunk.len() == 2 { &chunk[1] } else { &chunk[0] };
-            let combined = format!("{}{}", left.hash, right.hash);
-            let mut hasher = Sha256::new();
-            hasher.update(combined);
-            let hash = format!("{:x}", hasher.finalize());
-            new_nodes.push(MerkleNode {
-                hash,
-                left: Some(Box::new(left.clone())),
-                right: Some(Box::new(right.clone())),
-            });
-        }
-        nodes = new_nodes;
-    }
-
-    let root = nodes.into_iter().next().unwrap();
-    let root_hash = root.hash.clone();
-    Some((root, root_hash))
-}
-
-pub fn generate_merkle_proof(root: &MerkleNode, index: usize) -> MerkleProof {
-    let mut proof = MerkleProof {
-        hashes: Vec::new(),
-        index,
-    };
-    let mut current = root;
+        let parents = nodes.len().div_ceil(2);
+        for i in 0..parents {
+            let left = nodes[2 * i];
+            nodes[i] = Hash256::pair(&left, nodes.get(2 * i + 1).unwrap_or(&left));
+        }
+        nodes.truncate(parents);
+    }
+    nodes[0]
+}
+
+pub fn build_merkle_tree(txs: &[Transaction]) -> Option<(MerkleTree, Hash256)> {
+    build_merkle_tree_with(txs, LeafEncoding::default())
+}
+
+pub fn build_merkle_tree_with(
+    txs: &[Transaction],
+    encoding: LeafEncoding,
+) -> Option<(MerkleTree, Hash256)> {
+    tree_from_leaves(txs.iter().map(|tx| encoding.leaf_hash(tx)).collect())
+}
+
+// Each level is hashed from the one below, so the tree holds under 2n
+// hashes.
+pub fn tree_from_leaves(leaves: Vec<Hash256>) -> Option<(MerkleTree, Hash256)> {
+    if leaves.is_empty() {
+        return None;
+    }
+
+    let mut levels = vec![leaves];
+    while let Some(nodes) = levels.last().filter(|nodes| nodes.len() > 1) {
+        let parents = nodes
+            .chunks(2)
+            .map(|chunk| Hash256::pair(&chunk[0], chunk.get(1).unwrap_or(&chunk[0])))
+            .collect();
+        levels.push(parents);
+    }
+
+    let root_hash = *levels.last()?.first()?;
+    Some((MerkleTree { levels }, root_hash))
+}
+
+// Proof hashes are ordered leaf to root; bit i of index says whether the
+// node at level i is a right child.
+pub fn generate_merkle_proof(tree: &MerkleTree, index: usize) -> MerkleProof {
+    let depth = tree.levels.len().saturating_sub(1);
+    let mut hashes = Vec::with_capacity(depth);
     let mut idx = index;
-    while current.left.is_some() || current.right.is_some() {
-        if idx % 2 == 0 {
-            if let Some(right) = &current.right {
-                proof.hashes.push(right.hash.clone());
-            }
+    for level in &tree.levels[..depth] {
+        let Some(sibling) = level.get(idx ^ 1).or_else(|| level.get(idx)) else {
+            break;
+        };
+        hashes.push(*sibling);
+        idx /= 2;
+    }
+    MerkleProof { hashes, index }
+}
+
+// Levels above the leaves, i.e. the length of every proof in the tree.
+pub fn merkle_depth(leaves: usize) -> u32 {
+    leaves.max(1).next_power_of_two().trailing_zeros()
+}
+
+// Same proof as generate_merkle_proof, without building the tree: each
+// sibling subtree is hashed on demand, depth-first, so memory stays
+// O(log n) and the time is one pass over the leaves. Blocks deeper than
+// `max_depth` are refused before any hashing.
+pub fn stream_merkle_proof(
+    txs: &[Transaction],
+    encoding: LeafEncoding,
+    index: usize,
+    max_depth: u32,
+) -> Result<MerkleProof, MerkleError> {
+    if index >= txs.len() {
+        return Err(MerkleError::IndexOutOfRange {
+            index,
+            leaves: txs.len(),
+        });
+    }
+    let depth = merkle_depth(txs.len());
+    if depth > max_depth {
+        return Err(MerkleError::TooDeep {
+            depth,
+            max: max_depth,
+        });
+    }
+    let mut hashes = Vec::with_capacity(depth as usize);
+    let mut current = encoding.leaf_hash(&txs[index]);
+    let mut idx = index;
+    let mut width = txs.len();
+    let mut level = 0;
+    while width > 1 {
+        let sibling_idx = idx ^ 1;
+        // An odd last node is paired with itself.
+        let sibling = if sibling_idx < width {
+            subtree_hash(txs, encoding, level, sibling_idx)
+        } else {
+            current
+        };
+        current = if idx & 1 == 0 {
+            Hash256::pair(&current, &sibling)
+        } else {
+            Hash256::pair(&sibling, &current)
+        };
+        hashes.push(sibling);
+        idx /= 2;
+        width = width.div_ceil(2);
+        level += 1;
+    }
+    Ok(MerkleProof { hashes, index })
+}
+
+// Hash of node `idx` at `level` of the tree build_merkle_tree would build.
+fn subtree_hash(txs: &[Transaction], encoding: LeafEncoding, level: u32, idx: usize) -> Hash256 {
+    if level == 0 {
+        return encoding.leaf_hash(&txs[idx]);
+    }
+    let below = txs.len().div_ceil(1 << (level - 1));
+    let left = subtree_hash(txs, encoding, level - 1, 2 * idx);
+    let right = if 2 * idx + 1 < below {
+        subtree_hash(txs, encoding, level - 1, 2 * idx + 1)
+    } else {
+        left
+    };
+    Hash256::pair(&left, &right)
+}
+
+// A block with one transaction has a single-leaf tree whose root is the leaf
+// itself, so its proof has no hashes and checks only that the leaf equals the
+// root. Such a proof must have index 0; any other index names no leaf.
+pub fn verify_merkle_proof(leaf_hash: &Hash256, proof: &MerkleProof, root: &Hash256) -> bool {
+    if proof.hashes.is_empty() {
+        return proof.index == 0 && leaf_hash == root;
+    }
+    let mut hash = *leaf_hash;
+    let mut idx = proof.index;
+    for sibling in &proof.hashes {
+        hash = if idx & 1 == 0 {
+            Hash256::pair(&hash, sibling)
+        } else {
+            Hash256::pair(sibling, &hash)
+        };
+        idx /= 2;
+    }
+    hash == *root
+}
+
+// Walks a proof the way verify_merkle_proof does, recording every level.
+pub fn explain_merkle_proof(leaf_hash: Hash256, proof: &MerkleProof) -> VerboseProof {
+    let mut hash = leaf_hash;
+    let mut idx = proof.index;
+    let mut steps = Vec::with_capacity(proof.hashes.len());
+    for &sibling in &proof.hashes {
+        let position = if idx & 1 == 0 {
+            hash = Hash256::pair(&hash, &sibling);
+            SiblingPosition::Right
         } else {
-            if let Some(left) = &current.left {
-                proof.hashes.push(left.hash.clone());
+            hash = Hash256::pair(&sibling, &hash);
+            SiblingPosition::Left
+        };
+        steps.push(ProofStep {
+            sibling_hash: sibling,
+            position,
+            hash,
+        });
+        idx /= 2;
+    }
+    VerboseProof {
+        index: proof.index,
+        leaf_hash,
+        steps,
+        root: hash,
+    }
+}
+
+pub const PROOF_BINARY: &str = "application/vnd.gcl.proof";
+
+#[derive(Debug, PartialEq)]
+pub enum ProofDecodeError {
+    Empty,
+    TooDeep { levels: usize },
+    BadLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for ProofDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofDecodeError::Empty => write!(f, "binary proof is empty"),
+            ProofDecodeError::TooDeep { levels } => {
+                write!(f, "binary proof has {} levels, more than a bitmap can hold", levels)
+            }
+            ProofDecodeError::BadLength { expected, found } => {
+                write!(f, "binary proof should be {} bytes, got {}", expected, found)
             }
         }
-        if idx % 2 == 0 {
-            if let Some(left) = &current.left {
-                current = left;
-            } else {
-                break;
+    }
+}
+
+// Compact form of a MerkleProof: one byte for the number of levels, an
+// 8-byte big-endian bitmap whose bit i is set when the sibling at level i
+// sits on the left (which is the leaf index itself), then each sibling as
+// 32 raw bytes, leaf to root.
+pub fn encode_proof_binary(proof: &MerkleProof) -> Vec<u8> {
+    let mut out = Vec::with_capacity(9 + 32 * proof.hashes.len());
+    out.push(proof.hashes.len() as u8);
+    write_u64_be(&mut out, proof.index as u64);
+    for sibling in &proof.hashes {
+        out.extend_from_slice(&sibling.0);
+    }
+    out
+}
+
+pub fn decode_proof_binary(bytes: &[u8]) -> Result<MerkleProof, ProofDecodeError> {
+    let (&levels, rest) = bytes.split_first().ok_or(ProofDecodeError::Empty)?;
+    let levels = levels as usize;
+    if levels > 64 {
+        return Err(ProofDecodeError::TooDeep { levels });
+    }
+    let expected = 9 + 32 * levels;
+    if bytes.len() != expected {
+        return Err(ProofDecodeError::BadLength {
+            expected,
+            found: bytes.len(),
+        });
+    }
+    let (bitmap, hashes) = rest.split_at(8);
+    let index = u64::from_be_bytes(bitmap.try_into().unwrap()) as usize;
+    let hashes = hashes
+        .chunks(32)
+        .map(|raw| Hash256(raw.try_into().unwrap()))
+        .collect();
+    Ok(MerkleProof { hashes, index })
+}
+
+// Like stream_merkle_proof for several leaves at once. A sibling that is
+// itself proven, or sits on a path already being proven, is left out, so
+// leaves close together share most of their hashes.
+pub fn stream_merkle_multiproof(
+    txs: &[Transaction],
+    encoding: LeafEncoding,
+    indices: &[usize],
+    max_depth: u32,
+) -> Result<MerkleMultiproof, MerkleError> {
+    let mut known = indices.to_vec();
+    known.sort_unstable();
+    known.dedup();
+    if let Some(&index) = known.iter().find(|&&index| index >= txs.len()) {
+        return Err(MerkleError::IndexOutOfRange {
+            index,
+            leaves: txs.len(),
+        });
+    }
+    let depth = merkle_depth(txs.len());
+    if depth > max_depth {
+        return Err(MerkleError::TooDeep {
+            depth,
+            max: max_depth,
+        });
+    }
+    let indices = known.clone();
+    let mut hashes = Vec::new();
+    let mut width = txs.len();
+    let mut level = 0;
+    while width > 1 {
+        let mut parents: Vec<usize> = Vec::with_capacity(known.len());
+        for &idx in &known {
+            let sibling = idx ^ 1;
+            if sibling < width && known.binary_search(&sibling).is_err() {
+                hashes.push(subtree_hash(txs, encoding, level, sibling));
             }
-        } else {
-            if let Some(right) = &current.right {
-                current = right;
+            if parents.last() != Some(&(idx / 2)) {
+                parents.push(idx / 2);
+            }
+        }
+        known = parents;
+        width = width.div_ceil(2);
+        level += 1;
+    }
+    Ok(MerkleMultiproof {
+        indices,
+        leaves: txs.len(),
+        hashes,
+    })
+}
+
+// `leaf_hashes` lines up with `proof.indices`.
+pub fn verify_merkle_multiproof(
+    leaf_hashes: &[Hash256],
+    proof: &MerkleMultiproof,
+    root_hash: &Hash256,
+) -> bool {
+    let ascending = proof.indices.windows(2).all(|pair| pair[0] < pair[1]);
+    let in_range = proof.indices.last().is_some_and(|&last| last < proof.leaves);
+    if !ascending || !in_range || leaf_hashes.len() != proof.indices.len() {
+        return false;
+    }
+    let mut known: Vec<(usize, Hash256)> =
+        proof.indices.iter().copied().zip(leaf_hashes.iter().copied()).collect();
+    let mut siblings = proof.hashes.iter();
+    let mut width = proof.leaves;
+    while width > 1 {
+        let mut parents = Vec::with_capacity(known.len());
+        let mut i = 0;
+        while i < known.len() {
+            let (idx, hash) = known[i];
+            let sibling = if idx ^ 1 >= width {
+                hash
+            } else if known.get(i + 1).is_some_and(|next| next.0 == idx ^ 1) {
+                i += 1;
+                known[i].1
+            } else {
+                match siblings.next() {
+                    Some(&sibling) => sibling,
+                    None => return false,
+                }
+            };
+            let parent = if idx & 1 == 0 {
+                Hash256::pair(&hash, &sibling)
             } else {
+                Hash256::pair(&sibling, &hash)
+            };
+            parents.push((idx / 2, parent));
+            i += 1;
+        }
+        known = parents;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && known.len() == 1 && known[0].1 == *root_hash
+}
+
+// Append-only accumulator producing the same root as build_merkle_tree over
+// the same leaves. levels[i] holds every completed node at level i, so the
+// frontier of peaks is the trailing unpaired entry of each level.
+#[derive(Clone, Debug, Default)]
+pub struct MerkleAccumulator {
+    levels: Vec<Vec<Hash256>>,
+    len: usize,
+}
+
+impl MerkleAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, leaf: Hash256) {
+        let mut hash = leaf;
+        let mut level = 0;
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level].push(hash);
+            let nodes = &self.levels[level];
+            if nodes.len() % 2 == 1 {
                 break;
             }
+            hash = Hash256::pair(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            level += 1;
         }
-        idx /= 2;
+        self.len += 1;
+    }
+
+    pub fn append_tx(&mut self, tx: &Transaction) {
+        self.append(hash_transaction(tx));
+    }
+
+    pub fn root(&self) -> Option<Hash256> {
+        if self.len == 0 {
+            return None;
+        }
+        self.partials().pop().flatten()
+    }
+
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len {
+            return None;
+        }
+        let partials = self.partials();
+        let mut hashes = Vec::new();
+        let mut idx = index;
+        let mut count = self.len;
+        let mut level = 0;
+        while count > 1 {
+            let sibling = if idx ^ 1 < count { idx ^ 1 } else { idx };
+            hashes.push(self.node(level, sibling, &partials));
+            idx /= 2;
+            count = count.div_ceil(2);
+            level += 1;
+        }
+        Some(MerkleProof { hashes, index })
+    }
+
+    fn node(&self, level: usize, idx: usize, partials: &[Option<Hash256>]) -> Hash256 {
+        match self.levels.get(level).and_then(|nodes| nodes.get(idx)) {
+            Some(&hash) => hash,
+            None => partials[level].expect("partial node at level"),
+        }
+    }
+
+    // The rightmost, not yet completed node of every level, as the full tree
+    // would compute it by pairing odd nodes with themselves. The last entry
+    // is the root.
+    fn partials(&self) -> Vec<Option<Hash256>> {
+        let mut partials = Vec::new();
+        let mut carry: Option<Hash256> = None;
+        let mut count = self.len;
+        let mut level = 0;
+        loop {
+            if count == 1 {
+                let root = carry.or_else(|| self.levels[level].last().cloned());
+                partials.push(root);
+                return partials;
+            }
+            let complete = self.len >> level;
+            partials.push(carry);
+            carry = match (complete % 2 == 1, carry) {
+                (true, Some(c)) => Some(Hash256::pair(&self.levels[level][complete - 1], &c)),
+                (true, None) => {
+                    let last = &self.levels[level][complete - 1];
+                    Some(Hash256::pair(last, last))
+                }
+                (false, Some(c)) => Some(Hash256::pair(&c, &c)),
+                (false, None) => None,
+            };
+            count = count.div_ceil(2);
+            level += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tx;
+
+    fn txs(n: usize) -> Vec<Transaction> {
+        (0..n)
+            .map(|i| Transaction {
+                tx_type: "transfer".to_string(),
+                payload: format!("data{}", i),
+                ..tx(&format!("tx{}", i))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accumulator_root_matches_full_rebuild() {
+        let all = txs(70);
+        let mut acc = MerkleAccumulator::new();
+        assert_eq!(acc.root(), None);
+        for n in 1..=all.len() {
+            acc.append_tx(&all[n - 1]);
+            let (_, expected) = build_merkle_tree(&all[..n]).unwrap();
+            assert_eq!(acc.root(), Some(expected), "size {}", n);
+        }
+    }
+
+    #[test]
+    fn root_of_matches_the_built_tree() {
+        assert_eq!(root_of(&[]), empty_root().to_string());
+        for n in [1, 2, 3, 4, 5, 7, 8, 9, 16, 33, 100] {
+            let all = txs(n);
+            let (_, expected) = build_merkle_tree(&all).unwrap();
+            assert_eq!(root_of(&all), expected.to_string(), "size {}", n);
+            let (_, by_id) = build_merkle_tree_with(&all, LeafEncoding::TxId).unwrap();
+            assert_eq!(root_of_with(&all, LeafEncoding::TxId), by_id.to_string(), "size {}", n);
+        }
+    }
+
+    #[test]
+    fn accumulator_proofs_match_tree_and_verify() {
+        for n in [1, 2, 3, 5, 8, 13, 33] {
+            let set = txs(n);
+            let mut acc = MerkleAccumulator::new();
+            set.iter().for_each(|tx| acc.append_tx(tx));
+            let (tree, root) = build_merkle_tree(&set).unwrap();
+            for (i, tx) in set.iter().enumerate() {
+                let proof = acc.prove(i).unwrap();
+                assert_eq!(proof.hashes, generate_merkle_proof(&tree, i).hashes);
+                assert!(verify_merkle_proof(&hash_transaction(tx), &proof, &root));
+            }
+            assert!(acc.prove(n).is_none());
+        }
+    }
+
+    #[test]
+    fn streamed_proofs_match_tree_proofs() {
+        for n in [1, 2, 3, 4, 5, 7, 8, 9, 16, 17, 31, 100] {
+            let set = txs(n);
+            let (tree, root) = build_merkle_tree(&set).unwrap();
+            for i in 0..n {
+                let streamed = stream_merkle_proof(&set, LeafEncoding::CanonicalTx, i, 32).unwrap();
+                let built = generate_merkle_proof(&tree, i);
+                assert_eq!(streamed.hashes, built.hashes, "n={} i={}", n, i);
+                let leaf = hash_transaction(&set[i]);
+                assert!(verify_merkle_proof(&leaf, &streamed, &root));
+            }
+            assert_eq!(streamed_len(&set), merkle_depth(n) as usize);
+            assert_eq!(
+                stream_merkle_proof(&set, LeafEncoding::CanonicalTx, n, 32).unwrap_err(),
+                MerkleError::IndexOutOfRange { index: n, leaves: n }
+            );
+        }
+    }
+
+    #[test]
+    fn single_tx_blocks_prove_with_no_hashes() {
+        let set = txs(1);
+        let (tree, root) = build_merkle_tree(&set).unwrap();
+        let leaf = hash_transaction(&set[0]);
+        assert_eq!(root, leaf);
+        let proof = generate_merkle_proof(&tree, 0);
+        assert!(proof.hashes.is_empty());
+        assert_eq!(proof, stream_merkle_proof(&set, LeafEncoding::CanonicalTx, 0, 32).unwrap());
+        assert!(verify_merkle_proof(&leaf, &proof, &root));
+
+        let other = hash_transaction(&txs(2)[1]);
+        assert!(!verify_merkle_proof(&other, &proof, &root));
+        let misplaced = MerkleProof { index: 1, ..proof };
+        assert!(!verify_merkle_proof(&leaf, &misplaced, &root));
+    }
+
+    #[test]
+    fn large_trees_hold_each_hash_once() {
+        let n = 20_001;
+        let set = txs(n);
+        let (tree, root) = build_merkle_tree(&set).unwrap();
+        assert_eq!(tree.levels.len() as u32, merkle_depth(n) + 1);
+        let stored: usize = tree.levels.iter().map(Vec::len).sum();
+        assert!(stored < 2 * n + tree.levels.len(), "{} hashes for {} leaves", stored, n);
+        for i in [0, n / 2, n - 1] {
+            let proof = generate_merkle_proof(&tree, i);
+            let streamed = stream_merkle_proof(&set, LeafEncoding::CanonicalTx, i, 32).unwrap();
+            assert_eq!(proof, streamed);
+            assert!(verify_merkle_proof(&hash_transaction(&set[i]), &proof, &root));
+        }
+    }
+
+    #[test]
+    fn proofs_verify_on_bytes_and_parse_either_hex_case() {
+        let set = txs(13);
+        let (tree, root) = build_merkle_tree(&set).unwrap();
+        for (i, tx) in set.iter().enumerate() {
+            let proof = generate_merkle_proof(&tree, i);
+            let leaf = hash_transaction(tx);
+            // Hashing the joined hex of each pair lands on the same root as
+            // the byte walk.
+            let mut hash = leaf.to_string();
+            for (level, sibling) in proof.hashes.iter().enumerate() {
+                let pair = if (i >> level) & 1 == 0 {
+                    format!("{}{}", hash, sibling)
+                } else {
+                    format!("{}{}", sibling, hash)
+                };
+                hash = Hash256::digest(pair).to_string();
+            }
+            assert_eq!(hash, root.to_string());
+            assert!(verify_merkle_proof(&leaf, &proof, &root));
+
+            let json = serde_json::to_string(&proof).unwrap();
+            assert_eq!(json, json.to_lowercase());
+            let upper: Vec<String> =
+                proof.hashes.iter().map(|h| h.to_string().to_uppercase()).collect();
+            let upper = serde_json::json!({ "hashes": upper, "index": i });
+            assert_eq!(serde_json::from_value::<MerkleProof>(upper).unwrap(), proof);
+        }
+    }
+
+    fn streamed_len(set: &[Transaction]) -> usize {
+        stream_merkle_proof(set, LeafEncoding::CanonicalTx, 0, 32).unwrap().hashes.len()
+    }
+
+    #[test]
+    fn proofs_deeper_than_the_cap_are_refused() {
+        let set = txs(9);
+        assert_eq!(merkle_depth(9), 4);
+        assert!(stream_merkle_proof(&set, LeafEncoding::CanonicalTx, 8, 4).is_ok());
+        assert_eq!(
+            stream_merkle_proof(&set, LeafEncoding::CanonicalTx, 8, 3).unwrap_err(),
+            MerkleError::TooDeep { depth: 4, max: 3 }
+        );
+    }
+
+    #[test]
+    fn binary_proofs_decode_to_the_same_proof() {
+        let set = txs(11);
+        let root = merkle_root(&set, LeafEncoding::CanonicalTx, MAX_MERKLE_LEAVES).unwrap();
+        for i in [0, 5, 10] {
+            let proof = stream_merkle_proof(&set, LeafEncoding::CanonicalTx, i, 32).unwrap();
+            let bytes = encode_proof_binary(&proof);
+            assert_eq!(bytes.len(), 9 + 32 * proof.hashes.len());
+            assert!(bytes.len() * 2 < serde_json::to_vec(&proof).unwrap().len());
+            let decoded = decode_proof_binary(&bytes).unwrap();
+            assert_eq!(decoded, proof);
+            let leaf = hash_transaction(&set[i]);
+            assert!(verify_merkle_proof(&leaf, &decoded, &root));
+        }
+
+        assert_eq!(decode_proof_binary(&[]), Err(ProofDecodeError::Empty));
+        assert_eq!(
+            decode_proof_binary(&[2, 0, 0, 0, 0, 0, 0, 0, 1]),
+            Err(ProofDecodeError::BadLength { expected: 73, found: 9 })
+        );
+        assert_eq!(decode_proof_binary(&[65]), Err(ProofDecodeError::TooDeep { levels: 65 }));
+    }
+
+    #[test]
+    fn multiproofs_verify_and_share_hashes() {
+        for n in [1, 2, 3, 5, 8, 13, 33] {
+            let set = txs(n);
+            let root = build_merkle_tree(&set).unwrap().1;
+            let picks = [vec![0], vec![0, n - 1], (0..n).step_by(3).collect(), (0..n).collect()];
+            for picked in picks {
+                let encoding = LeafEncoding::CanonicalTx;
+                let proof = stream_merkle_multiproof(&set, encoding, &picked, 32).unwrap();
+                let mut leaves: Vec<Hash256> =
+                    proof.indices.iter().map(|&i| hash_transaction(&set[i])).collect();
+                assert!(verify_merkle_multiproof(&leaves, &proof, &root), "n={} {:?}", n, picked);
+                let single: usize = proof.indices.iter().map(|&i| streamed_len_at(&set, i)).sum();
+                assert!(proof.hashes.len() <= single);
+                leaves[0] = hash_transaction(&txs(n + 1)[n]);
+                assert!(!verify_merkle_multiproof(&leaves, &proof, &root));
+            }
+        }
+        let set = txs(4);
+        let both = stream_merkle_multiproof(&set, LeafEncoding::CanonicalTx, &[2, 3], 32).unwrap();
+        assert_eq!(both.hashes.len(), 1);
+    }
+
+    fn streamed_len_at(set: &[Transaction], index: usize) -> usize {
+        stream_merkle_proof(set, LeafEncoding::CanonicalTx, index, 32).unwrap().hashes.len()
+    }
+
+    #[test]
+    fn leaf_encodings_give_stable_distinct_roots() {
+        let set = txs(6);
+        let canonical = build_merkle_tree_with(&set, LeafEncoding::CanonicalTx).unwrap().1;
+        let by_id = build_merkle_tree_with(&set, LeafEncoding::TxId).unwrap().1;
+        assert_eq!(canonical, build_merkle_tree(&set).unwrap().1);
+        assert_eq!(by_id, build_merkle_tree_with(&set, LeafEncoding::TxId).unwrap().1);
+        assert_ne!(canonical, by_id);
+
+        let mut edited = set.clone();
+        edited[2].payload = "changed".to_string();
+        let edited_canonical = build_merkle_tree_with(&edited, LeafEncoding::CanonicalTx).unwrap();
+        let edited_by_id = build_merkle_tree_with(&edited, LeafEncoding::TxId).unwrap();
+        assert_ne!(edited_canonical.1, canonical);
+        assert_eq!(edited_by_id.1, by_id);
     }
-    proof
 }
@@ -0,0 +1,242 @@
+use crate::hash::Hash256;
+use crate::types::{MmrProof, Transaction, hash_transaction};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+// Merkle mountain range over every transaction in chain order. The optional
+// log on disk records leaves as they are appended; replaying it on open
+// rebuilds the same peaks, so roots and proofs survive a restart.
+#[derive(Debug, Default)]
+pub struct Mmr {
+    nodes: Vec<Hash256>,
+    leaves: u64,
+    log: Option<File>,
+}
+
+impl Mmr {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut mmr = Mmr::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let leaf = line?.parse().map_err(|err| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("bad log line: {}", err))
+                })?;
+                mmr.push_leaf(leaf);
+            }
+        }
+        mmr.log = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        Ok(mmr)
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.leaves
+    }
+
+    pub fn append(&mut self, leaf: Hash256) -> io::Result<u64> {
+        if let Some(log) = &mut self.log {
+            writeln!(log, "{}", leaf)?;
+        }
+        Ok(self.push_leaf(leaf))
+    }
+
+    pub fn append_tx(&mut self, tx: &Transaction) -> io::Result<u64> {
+        self.append(hash_transaction(tx))
+    }
+
+    // Forgets every leaf from `leaf_count` on, in memory and in the log.
+    pub fn truncate(&mut self, leaf_count: u64) -> io::Result<()> {
+        if leaf_count >= self.leaves {
+            return Ok(());
+        }
+        // Every leaf is logged as a line of 64 hex characters.
+        if let Some(log) = &mut self.log {
+            log.set_len(leaf_count * 65)?;
+        }
+        self.nodes.truncate(node_count(leaf_count));
+        self.leaves = leaf_count;
+        Ok(())
+    }
+
+    fn push_leaf(&mut self, leaf: Hash256) -> u64 {
+        let position = self.leaves;
+        self.nodes.push(leaf);
+        let mut height = 0;
+        while (position >> height) & 1 == 1 {
+            let right = self.nodes.len() - 1;
+            let left = right + 1 - (1 << (height + 1));
+            let parent = Hash256::pair(&self.nodes[left], &self.nodes[right]);
+            self.nodes.push(parent);
+            height += 1;
+        }
+        self.leaves += 1;
+        position
+    }
+
+    // Peak positions from the tallest (leftmost) mountain to the smallest.
+    fn peaks(&self) -> Vec<(usize, u32)> {
+        let mut peaks = Vec::new();
+        let mut offset = 0;
+        for height in (0..64).rev() {
+            if (self.leaves >> height) & 1 == 1 {
+                let size = (1usize << (height + 1)) - 1;
+                peaks.push((offset + size - 1, height));
+                offset += size;
+            }
+        }
+        peaks
+    }
+
+    fn peak_hashes(&self) -> Vec<Hash256> {
+        self.peaks().iter().map(|(p, _)| self.nodes[*p]).collect()
+    }
+
+    pub fn root(&self) -> Option<Hash256> {
+        bag_peaks(&self.peak_hashes())
+    }
+
+    // `position` is the transaction's index in the chain-wide log.
+    pub fn prove(&self, position: u64) -> Option<MmrProof> {
+        if position >= self.leaves {
+            return None;
+        }
+        let peaks = self.peaks();
+        let mut first_leaf = 0;
+        for (peak, height) in &peaks {
+            let span = 1u64 << height;
+            if position < first_leaf + span {
+                let local = position - first_leaf;
+                let mut siblings = Vec::with_capacity(*height as usize);
+                let mut node = *peak;
+                for level in (0..*height).rev() {
+                    let right = node - 1;
+                    let left = node - (1 << (level + 1));
+                    if (local >> level) & 1 == 0 {
+                        siblings.push(self.nodes[right]);
+                        node = left;
+                    } else {
+                        siblings.push(self.nodes[left]);
+                        node = right;
+                    }
+                }
+                siblings.reverse();
+                return Some(MmrProof {
+                    position,
+                    leaf_count: self.leaves,
+                    siblings,
+                    peaks: self.peak_hashes(),
+                });
+            }
+            first_leaf += span;
+        }
+        None
+    }
+
+    pub fn verify(root: &Hash256, leaf_hash: &Hash256, proof: &MmrProof) -> bool {
+        let mut first_leaf = 0;
+        let mut peak_index = 0;
+        for height in (0..64).rev() {
+            let span = 1u64 << height;
+            if (proof.leaf_count >> height) & 1 == 0 {
+                continue;
+            }
+            if proof.position < first_leaf + span {
+                if proof.siblings.len() != height as usize {
+                    return false;
+                }
+                let local = proof.position - first_leaf;
+                let mut hash = *leaf_hash;
+                for (level, sibling) in proof.siblings.iter().enumerate() {
+                    hash = if (local >> level) & 1 == 0 {
+                        Hash256::pair(&hash, sibling)
+                    } else {
+                        Hash256::pair(sibling, &hash)
+                    };
+                }
+                return proof.peaks.get(peak_index) == Some(&hash)
+                    && bag_peaks(&proof.peaks).as_ref() == Some(root);
+            }
+            first_leaf += span;
+            peak_index += 1;
+        }
+        false
+    }
+}
+
+// Nodes stored for the first `leaves` leaves, which is also the index the
+// next leaf is pushed at.
+fn node_count(leaves: u64) -> usize {
+    (2 * leaves - u64::from(leaves.count_ones())) as usize
+}
+
+// Peaks are bagged right to left into a single root.
+fn bag_peaks(peaks: &[Hash256]) -> Option<Hash256> {
+    let mut iter = peaks.iter().rev();
+    let mut root = *iter.next()?;
+    for peak in iter {
+        root = Hash256::pair(peak, &root);
+    }
+    Some(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn tx(i: u64) -> Transaction {
+        Transaction {
+            tx_type: "transfer".to_string(),
+            payload: format!("data{}", i),
+            ..test_util::tx(&format!("tx{}", i))
+        }
+    }
+
+    #[test]
+    fn early_transaction_proves_against_current_root() {
+        let mut mmr = Mmr::new();
+        for i in 0..1000 {
+            mmr.append_tx(&tx(i)).unwrap();
+        }
+        let root = mmr.root().unwrap();
+        for position in [0, 1, 2, 511, 512, 999] {
+            let proof = mmr.prove(position).unwrap();
+            assert!(Mmr::verify(&root, &hash_transaction(&tx(position)), &proof));
+            assert!(!Mmr::verify(&root, &hash_transaction(&tx(position + 1)), &proof));
+        }
+        assert!(mmr.prove(1000).is_none());
+    }
+
+    #[test]
+    fn reopened_log_restores_root_and_proofs() {
+        let path = std::env::temp_dir().join(format!("gcl-mmr-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let root = {
+            let mut mmr = Mmr::open(&path).unwrap();
+            for i in 0..37 {
+                mmr.append_tx(&tx(i)).unwrap();
+            }
+            mmr.root().unwrap()
+        };
+        let mut mmr = Mmr::open(&path).unwrap();
+        assert_eq!(mmr.leaf_count(), 37);
+        assert_eq!(mmr.root().unwrap(), root);
+        mmr.append_tx(&tx(37)).unwrap();
+        let proof = mmr.prove(3).unwrap();
+        assert!(Mmr::verify(&mmr.root().unwrap(), &hash_transaction(&tx(3)), &proof));
+
+        mmr.truncate(20).unwrap();
+        let mut fresh = Mmr::new();
+        for i in 0..20 {
+            fresh.append_tx(&tx(i)).unwrap();
+        }
+        assert_eq!(mmr.root(), fresh.root());
+        drop(mmr);
+        assert_eq!(Mmr::open(&path).unwrap().root(), fresh.root());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+// Which origins may submit transactions. The denylist always wins; with an
+// allowlist set, origins missing from it are turned away too.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OriginPolicy {
+    #[serde(default)]
+    pub allowlist: Option<BTreeSet<String>>,
+    #[serde(default)]
+    pub denylist: BTreeSet<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OriginError {
+    Denied { origin: String },
+    NotAllowed { origin: String },
+}
+
+impl fmt::Display for OriginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginError::Denied { origin } => write!(f, "origin {:?} is denied", origin),
+            OriginError::NotAllowed { origin } => {
+                write!(f, "origin {:?} is not on the allowlist", origin)
+            }
+        }
+    }
+}
+
+impl OriginPolicy {
+    pub fn check(&self, origin: &str) -> Result<(), OriginError> {
+        if self.denylist.contains(origin) {
+            return Err(OriginError::Denied {
+                origin: origin.to_string(),
+            });
+        }
+        if self.allowlist.as_ref().is_some_and(|allowed| !allowed.contains(origin)) {
+            return Err(OriginError::NotAllowed {
+                origin: origin.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
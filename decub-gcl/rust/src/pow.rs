@@ -0,0 +1,94 @@
+use crate::hash::Hash256;
+use crate::types::{Transaction, hash_transaction};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum PowError {
+    Missing { difficulty: u32 },
+    Insufficient { difficulty: u32, found: u32 },
+}
+
+impl fmt::Display for PowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowError::Missing { difficulty } => {
+                write!(f, "nonce_pow required: {} leading zero bits", difficulty)
+            }
+            PowError::Insufficient { difficulty, found } => {
+                write!(f, "nonce_pow gives {} leading zero bits, {} required", found, difficulty)
+            }
+        }
+    }
+}
+
+// The work hash is sha256 over the transaction hash in hex followed by the
+// nonce in decimal. The nonce is left out of hash_transaction, so solving
+// doesn't change a transaction's identity.
+pub fn pow_hash(tx: &Transaction, nonce: u64) -> Hash256 {
+    Hash256::digest(format!("{}{}", hash_transaction(tx), nonce))
+}
+
+pub fn leading_zero_bits(hash: &Hash256) -> u32 {
+    let mut bits = 0;
+    for byte in hash.0 {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+pub fn check_pow(tx: &Transaction, difficulty: u32) -> Result<(), PowError> {
+    if difficulty == 0 {
+        return Ok(());
+    }
+    let nonce = tx.nonce_pow.ok_or(PowError::Missing { difficulty })?;
+    let found = leading_zero_bits(&pow_hash(tx, nonce));
+    if found < difficulty {
+        return Err(PowError::Insufficient { difficulty, found });
+    }
+    Ok(())
+}
+
+// Smallest nonce meeting `difficulty`, for clients and tests.
+pub fn solve_pow(tx: &Transaction, difficulty: u32) -> u64 {
+    (0..).find(|&nonce| leading_zero_bits(&pow_hash(tx, nonce)) >= difficulty).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tx;
+
+    #[test]
+    fn solved_nonce_meets_its_difficulty_only() {
+        let mut solved = tx("tx1");
+        assert_eq!(check_pow(&solved, 0), Ok(()));
+        assert_eq!(check_pow(&solved, 8), Err(PowError::Missing { difficulty: 8 }));
+
+        solved.nonce_pow = Some(solve_pow(&solved, 8));
+        assert_eq!(check_pow(&solved, 8), Ok(()));
+        let found = leading_zero_bits(&pow_hash(&solved, solved.nonce_pow.unwrap()));
+        assert!(found >= 8);
+        let harder = found + 1;
+        assert_eq!(
+            check_pow(&solved, harder),
+            Err(PowError::Insufficient {
+                difficulty: harder,
+                found
+            })
+        );
+        assert_eq!(hash_transaction(&solved), hash_transaction(&tx("tx1")));
+    }
+
+    #[test]
+    fn zero_bits_are_counted_across_bytes() {
+        let mut bytes = [0xff; 32];
+        assert_eq!(leading_zero_bits(&Hash256(bytes)), 0);
+        bytes[0] = 0;
+        bytes[1] = 0x1f;
+        assert_eq!(leading_zero_bits(&Hash256(bytes)), 11);
+        assert_eq!(leading_zero_bits(&Hash256::ZERO), 256);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::api::{SharedLedger, SharedMempool};
+use crate::consensus::{Consensus, SharedConsensus};
+use crate::ledger::{Ledger, LedgerError};
+use crate::merkle::MerkleError;
+use crate::types::{ProposalStage, Transaction};
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum ProposeError {
+    HeightOverflow,
+    NoProposer,
+    Halted,
+    QuorumFailed { height: u64 },
+    MerkleRoot(MerkleError),
+    Append(LedgerError),
+}
+
+// Builds the next block from the mempool. Returns the new height, or None
+// when nothing is pending. Transactions leave the mempool only once their
+// block is appended.
+pub fn propose_pending(
+    ledger: &SharedLedger,
+    mempool: &SharedMempool,
+    cons: &SharedConsensus,
+) -> Result<Option<u64>, ProposeError> {
+    let mut ledger_guard = ledger.write().unwrap_or_else(PoisonError::into_inner);
+    let mut cons = cons.write().unwrap_or_else(PoisonError::into_inner);
+    let mut mempool_guard = mempool.lock().unwrap_or_else(PoisonError::into_inner);
+    let evicted = mempool_guard.evict_expired();
+    if evicted > 0 {
+        eprintln!("Evicted {} transactions past the mempool TTL", evicted);
+    }
+    let (txs, rejected) = ledger_guard.check_transfers(mempool_guard.select());
+    for (tx, err) in &rejected {
+        eprintln!("Dropping transaction {}: {}", tx.tx_id, err);
+    }
+    let rejected: Vec<_> = rejected.into_iter().map(|(tx, _)| tx).collect();
+    mempool_guard.remove(&rejected);
+    let (txs, orphaned) =
+        mempool_guard.order_dependencies(txs, |tx_id| ledger_guard.is_committed(tx_id));
+    for tx in &orphaned {
+        let parent = tx.depends_on.as_deref().unwrap_or_default();
+        eprintln!("Dropping transaction {}: dependency {} never arrived", tx.tx_id, parent);
+    }
+    mempool_guard.remove(&orphaned);
+    if txs.is_empty() {
+        return Ok(None);
+    }
+    let height = append_next(&mut ledger_guard, &mut cons, txs)?;
+    mempool_guard.remove(&ledger_guard.tip().unwrap().txs);
+    Ok(Some(height))
+}
+
+// Proposes `n` empty blocks back to back, without waiting for the block
+// interval, so tests can reach a height quickly. Returns the new tip.
+#[cfg(feature = "dev")]
+pub fn mine_empty(
+    ledger: &SharedLedger,
+    cons: &SharedConsensus,
+    n: u64,
+) -> Result<u64, ProposeError> {
+    let mut ledger_guard = ledger.write().unwrap_or_else(PoisonError::into_inner);
+    let mut cons = cons.write().unwrap_or_else(PoisonError::into_inner);
+    for _ in 0..n {
+        append_next(&mut ledger_guard, &mut cons, Vec::new())?;
+    }
+    Ok(ledger_guard.tip_height())
+}
+
+// Builds, signs and appends the next block over `txs`, reporting each step.
+fn append_next(
+    ledger_guard: &mut Ledger,
+    cons: &mut Consensus,
+    txs: Vec<Transaction>,
+) -> Result<u64, ProposeError> {
+    let started = Instant::now();
+    let since_start = || started.elapsed().as_millis() as u64;
+    let height = ledger_guard.next_height().ok_or(ProposeError::HeightOverflow)?;
+    cons.enter_height(height);
+    // Without enough free validators a round could only fail, and moving
+    // on to the next one would churn through them for nothing.
+    if cons.is_halted() {
+        return Err(ProposeError::Halted);
+    }
+    let round = cons.round(height);
+    report(ledger_guard, ProposalStage::Started { height, round });
+    let proposer = cons.proposer_for(height).ok_or(ProposeError::NoProposer)?;
+    let prev_hash = ledger_guard.tip_hash();
+    let mut block = cons
+        .propose_block(height, prev_hash, txs, proposer.id.clone())
+        .map_err(ProposeError::MerkleRoot)?;
+    let sigs = cons.sign_block(&block);
+    for record in cons.record_signatures(&sigs) {
+        eprintln!("Slashed {}: {:?}", record.validator_id, record.offense);
+    }
+    let got = cons.counted(&block, &sigs).count();
+    let of = cons.validators_at(height).len();
+    report(ledger_guard, ProposalStage::SignaturesCollected { height, got, of });
+    // A proposer that can't gather a quorum has failed its round; the next
+    // tick tries the height again under the next validator.
+    if !cons.verify_quorum(&block, &sigs) {
+        cons.view_change(height);
+        report(ledger_guard, ProposalStage::QuorumFailed { height, elapsed_ms: since_start() });
+        return Err(ProposeError::QuorumFailed { height });
+    }
+    report(ledger_guard, ProposalStage::QuorumReached { height, elapsed_ms: since_start() });
+    block.signatures = cons.counted(&block, &sigs).cloned().collect();
+    ledger_guard.append(block).map_err(ProposeError::Append)?;
+    report(ledger_guard, ProposalStage::Appended { height, elapsed_ms: since_start() });
+    Ok(height)
+}
+
+// Emits a proposal step as a tracing event, for whatever subscriber the
+// embedding program installed, and to chain subscribers when enabled.
+fn report(ledger: &Ledger, stage: ProposalStage) {
+    match &stage {
+        ProposalStage::Started { height, round } => {
+            tracing::info!(height, round, "proposal started")
+        }
+        ProposalStage::SignaturesCollected { height, got, of } => {
+            tracing::info!(height, got, of, "signatures collected ({}/{})", got, of)
+        }
+        ProposalStage::QuorumReached { height, elapsed_ms } => {
+            tracing::info!(height, elapsed_ms, "quorum reached")
+        }
+        ProposalStage::QuorumFailed { height, elapsed_ms } => {
+            tracing::warn!(height, elapsed_ms, "quorum failed")
+        }
+        ProposalStage::Appended { height, elapsed_ms } => {
+            tracing::info!(height, elapsed_ms, "block appended")
+        }
+    }
+    ledger.publish_proposal(stage);
+}
+
+// Proposes blocks until the mempool is empty or `deadline` passes, so a
+// huge backlog can't hold up shutdown. Returns how many transactions are
+// still pending.
+pub fn drain(
+    ledger: &SharedLedger,
+    mempool: &SharedMempool,
+    cons: &SharedConsensus,
+    deadline: Instant,
+) -> usize {
+    while Instant::now() < deadline {
+        match propose_pending(ledger, mempool, cons) {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("Block proposal failed during shutdown: {:?}", err);
+                break;
+            }
+        }
+    }
+    mempool.lock().unwrap_or_else(PoisonError::into_inner).len()
+}
+
+pub async fn run(
+    ledger: SharedLedger,
+    mempool: SharedMempool,
+    cons: SharedConsensus,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut halted = false;
+    loop {
+        ticker.tick().await;
+        // Reported once each way rather than as a failure every tick.
+        let now_halted = cons.read().unwrap_or_else(PoisonError::into_inner).is_halted();
+        if now_halted != halted {
+            if now_halted {
+                eprintln!("Chain halted: too many validators are jailed for a quorum");
+            } else {
+                println!("Chain resumed");
+            }
+            halted = now_halted;
+        }
+        if halted {
+            continue;
+        }
+        if let Err(err) = propose_pending(&ledger, &mempool, &cons) {
+            eprintln!("Block proposal failed: {:?}", err);
+        }
+    }
+}
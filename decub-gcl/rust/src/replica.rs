@@ -0,0 +1,102 @@
+use crate::api::SharedLedger;
+use crate::client::{ClientError, GclClient};
+use crate::consensus::SharedConsensus;
+use crate::ledger::LedgerError;
+use std::fmt;
+use std::sync::PoisonError;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ReplicaError {
+    Client(ClientError),
+    Diverged { height: u64 },
+    Append(LedgerError),
+}
+
+impl fmt::Display for ReplicaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplicaError::Client(err) => write!(f, "primary unreachable: {}", err),
+            ReplicaError::Diverged { height } => {
+                write!(f, "primary block {} does not extend the local tip", height)
+            }
+            ReplicaError::Append(err) => write!(f, "could not apply primary block: {}", err),
+        }
+    }
+}
+
+impl From<ClientError> for ReplicaError {
+    fn from(err: ClientError) -> Self {
+        ReplicaError::Client(err)
+    }
+}
+
+impl From<LedgerError> for ReplicaError {
+    fn from(err: LedgerError) -> Self {
+        ReplicaError::Append(err)
+    }
+}
+
+// Appends every block the primary has above the local tip. Returns how many
+// were applied. When the primary has reorged, its blocks no longer link to
+// the local tip; the pull then steps back a height at a time and hands the
+// primary's branch to fork choice, down to the first block `finality_depth`
+// keeps final.
+pub async fn pull(
+    ledger: &SharedLedger,
+    cons: &SharedConsensus,
+    primary: &GclClient,
+    finality_depth: u64,
+) -> Result<usize, ReplicaError> {
+    let mut applied = 0;
+    let mut from = ledger.read().unwrap_or_else(PoisonError::into_inner).next_height();
+    while let Some(height) = from {
+        let blocks = primary.get_blocks(height).await?;
+        if blocks.is_empty() {
+            return Ok(applied);
+        }
+        let mut ledger_guard = ledger.write().unwrap_or_else(PoisonError::into_inner);
+        let cons = cons.read().unwrap_or_else(PoisonError::into_inner);
+        for block in blocks {
+            let height = block.header.height;
+            // Carry on after this block, even one already known, so a pull
+            // that stepped back doesn't fetch it again.
+            from = height.checked_add(1);
+            if ledger_guard.knows(&block) {
+                continue;
+            }
+            if Some(height) == ledger_guard.next_height()
+                && block.header.prev_hash == ledger_guard.tip_hash()
+            {
+                ledger_guard.append(block)?;
+            } else if ledger_guard.links(&block) {
+                ledger_guard.insert(block, &cons, finality_depth)?;
+            } else {
+                let back = height.saturating_sub(1);
+                if back == 0 || ledger_guard.is_final(back, finality_depth) {
+                    return Err(ReplicaError::Diverged { height });
+                }
+                from = Some(back);
+                break;
+            }
+            applied += 1;
+        }
+    }
+    Ok(applied)
+}
+
+pub async fn follow(
+    ledger: SharedLedger,
+    cons: SharedConsensus,
+    primary: GclClient,
+    interval: Duration,
+    finality_depth: u64,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(err) = pull(&ledger, &cons, &primary, finality_depth).await {
+            eprintln!("Replication failed: {}", err);
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::txid::{TxIdCharset, check_tx_id};
+use serde_json::{Value, json};
+use warp::Filter;
+use warp::filters::BoxedFilter;
+use warp::http::{Method, Request, StatusCode};
+use warp::hyper::Body;
+use warp::hyper::service::Service;
+use warp::reply::Response;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+// The REST call a method maps to answered with an error status.
+pub const SERVER_ERROR: i64 = -32000;
+
+// The REST routes, each reply already turned into a response.
+pub type RestApi = BoxedFilter<(Response,)>;
+
+// JSON-RPC 2.0 over `POST /rpc`. Every method is answered by sending the
+// matching REST request through `api`, so both interfaces share their checks
+// and limits. A batch runs within the one request's permit and timeout, so
+// it may hold at most `max_batch` calls.
+pub fn json_rpc(
+    api: RestApi,
+    max_batch: usize,
+) -> impl Filter<Extract = (Response,), Error = warp::Rejection> + Clone {
+    warp::path!("rpc")
+        .and(warp::post())
+        .and(warp::body::bytes())
+        .and_then(move |body: warp::hyper::body::Bytes| handle_rpc(body, api.clone(), max_batch))
+}
+
+// A batch is answered as an array, in order, leaving out notifications. A
+// request or batch made only of notifications gets an empty 204.
+async fn handle_rpc(
+    body: warp::hyper::body::Bytes,
+    api: RestApi,
+    max_batch: usize,
+) -> Result<Response, warp::Rejection> {
+    let answer = match serde_json::from_slice::<Value>(&body) {
+        Err(_) => Some(error(Value::Null, PARSE_ERROR, "Parse error", None)),
+        Ok(Value::Array(calls)) if calls.is_empty() => {
+            Some(error(Value::Null, INVALID_REQUEST, "Invalid Request", None))
+        }
+        Ok(Value::Array(calls)) if calls.len() > max_batch => {
+            let data = json!({ "max_batch": max_batch });
+            Some(error(Value::Null, INVALID_REQUEST, "Batch too large", Some(data)))
+        }
+        Ok(Value::Array(calls)) => {
+            let mut answers = Vec::new();
+            for call in calls {
+                answers.extend(answer(call, &api).await);
+            }
+            (!answers.is_empty()).then_some(Value::Array(answers))
+        }
+        Ok(call) => answer(call, &api).await,
+    };
+    Ok(match answer {
+        Some(answer) => warp::Reply::into_response(warp::reply::json(&answer)),
+        None => warp::Reply::into_response(StatusCode::NO_CONTENT),
+    })
+}
+
+// None for a notification, which has no id to answer.
+async fn answer(call: Value, api: &RestApi) -> Option<Value> {
+    let Some(call) = call.as_object() else {
+        return Some(error(Value::Null, INVALID_REQUEST, "Invalid Request", None));
+    };
+    let id = call.get("id").cloned();
+    let version = call.get("jsonrpc").and_then(Value::as_str);
+    let (Some("2.0"), Some(method)) = (version, call.get("method").and_then(Value::as_str)) else {
+        let id = id.unwrap_or(Value::Null);
+        return Some(error(id, INVALID_REQUEST, "Invalid Request", None));
+    };
+    let params = call.get("params").cloned().unwrap_or(Value::Null);
+    let outcome = dispatch(method, params, api).await;
+    let id = id?;
+    Some(match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err((code, message, data)) => error(id, code, &message, data),
+    })
+}
+
+type RpcError = (i64, String, Option<Value>);
+
+async fn dispatch(method: &str, params: Value, api: &RestApi) -> Result<Value, RpcError> {
+    let invalid = |message: &str| (INVALID_PARAMS, message.to_string(), None);
+    let (method, path, body) = match method {
+        "gcl_submitTx" => match first_param(params, "tx") {
+            Some(tx @ Value::Object(_)) => (Method::POST, "/gcl/tx".to_string(), Some(tx)),
+            _ => return Err(invalid("expected a transaction object")),
+        },
+        "gcl_getBlock" => match first_param(params, "height").as_ref().and_then(Value::as_u64) {
+            Some(height) => (Method::GET, format!("/gcl/block/{}", height), None),
+            None => return Err(invalid("expected a block height")),
+        },
+        "gcl_getHead" => (Method::GET, "/gcl/head".to_string(), None),
+        "gcl_getProof" => {
+            let tx_id = first_param(params, "tx_id");
+            // Path segments are not percent-decoded, so only ids that need
+            // no escaping can be looked up.
+            match tx_id.as_ref().and_then(Value::as_str) {
+                Some(tx_id) if check_tx_id(tx_id, usize::MAX, TxIdCharset::UrlSafe).is_ok() => {
+                    (Method::GET, format!("/gcl/proof/{}", tx_id), None)
+                }
+                _ => return Err(invalid("expected a URL-safe tx_id")),
+            }
+        }
+        _ => return Err((METHOD_NOT_FOUND, "Method not found".to_string(), None)),
+    };
+    let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header("content-type", "application/json")
+        .body(body)
+        .unwrap();
+    // warp won't route a request from inside another's filter, so the inner
+    // one runs as a task of its own.
+    let api = api.clone();
+    let inner = tokio::spawn(async move { warp::service(api).call(request).await });
+    let Ok(Ok(response)) = inner.await else {
+        return Err((INTERNAL_ERROR, "Internal error".to_string(), None));
+    };
+    let status = response.status();
+    let tx_id = response.headers().get("x-tx-id").and_then(|id| id.to_str().ok());
+    let tx_id = tx_id.map(str::to_string);
+    let bytes = warp::hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+    // JSON bodies pass through as they are; plain-text ones become strings.
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    if !status.is_success() {
+        let message = match &body {
+            Value::String(text) => text.clone(),
+            body => body["error"].as_str().unwrap_or("request failed").to_string(),
+        };
+        return Err((SERVER_ERROR, message, Some(json!({ "status": status.as_u16() }))));
+    }
+    Ok(match tx_id {
+        Some(tx_id) => json!({ "tx_id": tx_id, "message": body }),
+        None => body,
+    })
+}
+
+// Parameters by position (`[value]`) or by name (`{"name": value}`). None,
+// so an invalid-params error, when the named one is missing.
+fn first_param(params: Value, name: &str) -> Option<Value> {
+    match params {
+        Value::Array(mut values) if !values.is_empty() => Some(values.swap_remove(0)),
+        Value::Object(mut values) => values.remove(name),
+        _ => None,
+    }
+}
+
+fn error(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{SharedLedger, SharedMempool, routes};
+    use crate::config::Config;
+    use crate::consensus::SharedConsensus;
+    use crate::ledger::Ledger;
+    use crate::mempool::Mempool;
+    use crate::proposer::propose_pending;
+    use crate::test_util::{consensus, tx};
+    use std::sync::{Arc, Mutex, RwLock};
+
+    fn node() -> (SharedLedger, SharedMempool, SharedConsensus) {
+        (
+            Arc::new(RwLock::new(Ledger::new())),
+            Arc::new(Mutex::new(Mempool::new())),
+            Arc::new(RwLock::new(consensus())),
+        )
+    }
+
+    async fn call(
+        api: &(impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static),
+        body: Value,
+    ) -> (u16, Value) {
+        let res = warp::test::request().method("POST").path("/rpc").json(&body).reply(api).await;
+        let body = serde_json::from_slice(res.body()).unwrap_or(Value::Null);
+        (res.status().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn single_calls_reach_the_rest_handlers() {
+        let (ledger, mempool, cons) = node();
+        let config = Arc::new(Config::default());
+        let api = routes(ledger.clone(), mempool.clone(), cons.clone(), config);
+
+        let submit = json!({ "jsonrpc": "2.0", "method": "gcl_submitTx", "params": [tx("tx1")],
+            "id": 1 });
+        let (status, res) = call(&api, submit).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["jsonrpc"], "2.0");
+        assert_eq!(res["id"], 1);
+        assert_eq!(res["result"]["tx_id"], "tx1");
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+
+        let get = json!({ "jsonrpc": "2.0", "method": "gcl_getBlock",
+            "params": { "height": 1 }, "id": "b" });
+        let (_, res) = call(&api, get).await;
+        assert_eq!(res["id"], "b");
+        assert_eq!(res["result"]["header"]["height"], 1);
+        assert_eq!(res["result"]["txs"][0]["tx_id"], "tx1");
+        assert!(res.get("error").is_none());
+
+        let (_, res) = call(&api, json!({ "jsonrpc": "2.0", "method": "gcl_getBlock",
+            "params": [7], "id": 2 }))
+        .await;
+        assert_eq!(res["error"]["code"], SERVER_ERROR);
+        assert_eq!(res["error"]["message"], "Block not found");
+        assert_eq!(res["error"]["data"]["status"], 404);
+    }
+
+    #[tokio::test]
+    async fn batches_answer_each_call_in_order() {
+        let (ledger, mempool, cons) = node();
+        mempool.lock().unwrap().insert(tx("tx1")).unwrap();
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        let api = routes(ledger, mempool.clone(), cons, Arc::new(Config::default()));
+
+        let batch = json!([
+            { "jsonrpc": "2.0", "method": "gcl_getHead", "id": 1 },
+            { "jsonrpc": "2.0", "method": "gcl_submitTx", "params": { "tx": tx("tx2") } },
+            { "jsonrpc": "2.0", "method": "gcl_getProof", "params": ["tx1"], "id": 2 },
+            { "jsonrpc": "1.0", "method": "gcl_getHead", "id": 3 },
+            { "jsonrpc": "2.0", "method": "gcl_getProof", "params": ["a b"], "id": 4 },
+        ]);
+        let (status, res) = call(&api, batch).await;
+        assert_eq!(status, 200);
+        let res = res.as_array().unwrap();
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[0]["id"], 1);
+        assert_eq!(res[0]["result"]["header"]["height"], 1);
+        assert_eq!(res[1]["id"], 2);
+        assert_eq!(res[1]["result"]["index"], 0);
+        assert_eq!(res[2]["id"], 3);
+        assert_eq!(res[2]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(res[3]["id"], 4);
+        assert_eq!(res[3]["error"]["code"], INVALID_PARAMS);
+        // A misspelled name is missing, not the whole object.
+        let (_, res) = call(&api, json!({ "jsonrpc": "2.0", "method": "gcl_submitTx",
+            "params": { "transaction": tx("tx3") }, "id": 5 }))
+        .await;
+        assert_eq!(res["error"]["code"], INVALID_PARAMS);
+        // The notification still ran; it just wasn't answered.
+        assert_eq!(mempool.lock().unwrap().len(), 1);
+
+        let notes = json!([{ "jsonrpc": "2.0", "method": "gcl_getHead" }]);
+        assert_eq!(call(&api, notes).await, (204, Value::Null));
+        let (_, res) = call(&api, json!([])).await;
+        assert_eq!(res["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn oversized_batches_get_one_error() {
+        let (ledger, mempool, cons) = node();
+        let config = Arc::new(Config {
+            max_rpc_batch: 2,
+            ..Config::default()
+        });
+        let api = routes(ledger, mempool.clone(), cons, config);
+        let submit = |id: &str| {
+            json!({ "jsonrpc": "2.0", "method": "gcl_submitTx", "params": [tx(id)], "id": id })
+        };
+
+        let (status, res) = call(&api, json!([submit("tx1"), submit("tx2"), submit("tx3")])).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["error"]["code"], INVALID_REQUEST);
+        assert_eq!(res["error"]["data"]["max_batch"], 2);
+        assert_eq!(res["id"], Value::Null);
+        assert!(mempool.lock().unwrap().is_empty());
+        let (_, res) = call(&api, json!([submit("tx1"), submit("tx2")])).await;
+        assert_eq!(res.as_array().unwrap().len(), 2);
+        assert_eq!(mempool.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_only_replicas_answer_reads_and_refuse_writes() {
+        let (ledger, mempool, cons) = node();
+        mempool.lock().unwrap().insert(tx("tx1")).unwrap();
+        propose_pending(&ledger, &mempool, &cons).unwrap();
+        let config = Arc::new(Config {
+            read_only: true,
+            ..Config::default()
+        });
+        let api = routes(ledger, mempool.clone(), cons, config);
+
+        let head = json!({ "jsonrpc": "2.0", "method": "gcl_getHead", "id": 1 });
+        let (status, res) = call(&api, head).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["result"]["header"]["height"], 1);
+        let submit = json!({ "jsonrpc": "2.0", "method": "gcl_submitTx", "params": [tx("tx2")],
+            "id": 2 });
+        let (_, res) = call(&api, submit).await;
+        assert_eq!(res["error"]["code"], SERVER_ERROR);
+        assert_eq!(res["error"]["data"]["status"], 405);
+        assert!(mempool.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_and_bad_json_are_errors() {
+        let (ledger, mempool, cons) = node();
+        let api = routes(ledger, mempool, cons, Arc::new(Config::default()));
+
+        let (status, res) =
+            call(&api, json!({ "jsonrpc": "2.0", "method": "gcl_mine", "id": 9 })).await;
+        assert_eq!(status, 200);
+        assert_eq!(res["id"], 9);
+        assert_eq!(res["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(res["error"]["message"], "Method not found");
+        assert!(res.get("result").is_none());
+
+        let res = warp::test::request().method("POST").path("/rpc").body("{").reply(&api).await;
+        let res: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(res["error"]["code"], PARSE_ERROR);
+        assert_eq!(res["id"], Value::Null);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::hash::Hash256;
+use crate::types::Signature;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Offense {
+    Equivocation {
+        height: u64,
+        first_hash: Hash256,
+        second_hash: Hash256,
+    },
+    InvalidSignature {
+        height: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlashRecord {
+    pub validator_id: String,
+    pub offense: Offense,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EquivocationReport {
+    pub first: Signature,
+    pub second: Signature,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SlashError {
+    UnknownValidator,
+    InvalidSignature,
+    NotConflicting,
+}
+
+impl fmt::Display for SlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlashError::UnknownValidator => write!(f, "unknown validator"),
+            SlashError::InvalidSignature => write!(f, "evidence signature does not verify"),
+            SlashError::NotConflicting => {
+                write!(f, "signatures are not from one validator for two blocks at one height")
+            }
+        }
+    }
+}
+
+// Jailed validators neither sign nor count toward quorum. The last signature
+// seen from each validator is kept to catch a second, conflicting one.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Slashing {
+    pub jailed: BTreeSet<String>,
+    pub records: Vec<SlashRecord>,
+    #[serde(skip)]
+    last_signed: HashMap<String, Signature>,
+}
+
+impl Slashing {
+    pub fn is_jailed(&self, validator_id: &str) -> bool {
+        self.jailed.contains(validator_id)
+    }
+
+    pub fn jail(&mut self, validator_id: &str, offense: Offense) -> SlashRecord {
+        let record = SlashRecord {
+            validator_id: validator_id.to_string(),
+            offense,
+        };
+        self.jailed.insert(validator_id.to_string());
+        self.records.push(record.clone());
+        record
+    }
+
+    // Lets a jailed validator sign and count again. Returns whether it was
+    // jailed; its records stay.
+    pub fn release(&mut self, validator_id: &str) -> bool {
+        self.jailed.remove(validator_id)
+    }
+
+    // Remembers a verified signature, returning the offense if the validator
+    // already signed a different block at the same height.
+    pub fn observe(&mut self, sig: &Signature) -> Option<Offense> {
+        let previous = self.last_signed.insert(sig.validator_id.clone(), sig.clone())?;
+        if previous.height == sig.height && previous.block_hash != sig.block_hash {
+            return Some(Offense::Equivocation {
+                height: sig.height,
+                first_hash: previous.block_hash,
+                second_hash: sig.block_hash,
+            });
+        }
+        None
+    }
+}
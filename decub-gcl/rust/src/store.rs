@@ -0,0 +1,342 @@
+use crate::types::Block;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Which BlockStore `main` opens. `sled` needs a data_dir and survives
+// restarts; `memory` keeps nothing once the process exits, for ephemeral
+// test nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    Memory,
+    #[default]
+    Sled,
+}
+
+// Committed blocks, in chain order. The ledger writes through this on every
+// append and rollback and reads it back once, on recovery.
+pub trait BlockStore: fmt::Debug + Send + Sync {
+    // Durable, for stores that are durable at all, once this returns.
+    fn put(&self, block: &Block) -> io::Result<()>;
+
+    // Removes every block above `height`.
+    fn truncate(&self, height: u64) -> io::Result<()>;
+
+    fn load(&self) -> io::Result<Vec<Block>>;
+
+    // Flushes and reclaims what space the backend can, returning the bytes
+    // freed. A store with nothing to reclaim frees none.
+    fn compact(&self) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+// Lets the ledger and the compaction task share one store.
+impl<S: BlockStore + ?Sized> BlockStore for Arc<S> {
+    fn put(&self, block: &Block) -> io::Result<()> {
+        (**self).put(block)
+    }
+
+    fn truncate(&self, height: u64) -> io::Result<()> {
+        (**self).truncate(height)
+    }
+
+    fn load(&self) -> io::Result<Vec<Block>> {
+        (**self).load()
+    }
+
+    fn compact(&self) -> io::Result<u64> {
+        (**self).compact()
+    }
+}
+
+// Compacts `store` every `interval`. Each run goes to the blocking pool, so
+// requests and the proposer never wait on it; sled is safe to use
+// meanwhile.
+pub async fn compact_every(store: Arc<dyn BlockStore>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick fires at once; nothing has built up yet.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let store = store.clone();
+        match tokio::task::spawn_blocking(move || store.compact()).await {
+            Ok(Ok(reclaimed)) => println!("Compacted block store, reclaimed {} bytes", reclaimed),
+            Ok(Err(err)) => eprintln!("Block store compaction failed: {}", err),
+            Err(err) => eprintln!("Block store compaction panicked: {}", err),
+        }
+    }
+}
+
+// Leads every record SledStore writes, ahead of the block's JSON. Records
+// from before it existed are bare JSON, which starts with `{`; they read as
+// schema 0 and are rewritten under the current schema on load.
+pub const SCHEMA_VERSION: u8 = 1;
+
+fn encode_record(block: &Block) -> io::Result<Vec<u8>> {
+    let mut record = vec![SCHEMA_VERSION];
+    serde_json::to_writer(&mut record, block).map_err(io::Error::from)?;
+    Ok(record)
+}
+
+// The block in a record of any schema up to SCHEMA_VERSION, and that
+// schema. Fields added to Block since a record was written take their
+// serde defaults, so no schema so far needs its JSON changed first.
+fn decode_record(record: &[u8]) -> io::Result<(u8, Block)> {
+    let (schema, json) = match record.first() {
+        Some(b'{') => (0, record),
+        Some(&schema) if schema <= SCHEMA_VERSION => (schema, &record[1..]),
+        Some(&schema) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block record schema {} is newer than {}", schema, SCHEMA_VERSION),
+            ));
+        }
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "empty block record")),
+    };
+    let block = serde_json::from_slice(json).map_err(io::Error::from)?;
+    Ok((schema, block))
+}
+
+// Committed blocks in a sled tree keyed by big-endian height, so iteration
+// order is chain order. Clones share the one open database.
+#[derive(Clone, Debug)]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+impl SledStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(SledStore {
+            db: sled::open(path)?,
+        })
+    }
+}
+
+impl BlockStore for SledStore {
+    fn put(&self, block: &Block) -> io::Result<()> {
+        self.db.insert(block.header.height.to_be_bytes(), encode_record(block)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn truncate(&self, height: u64) -> io::Result<()> {
+        let Some(first) = height.checked_add(1) else {
+            return Ok(());
+        };
+        for entry in self.db.range(first.to_be_bytes()..) {
+            let (key, _) = entry?;
+            self.db.remove(key)?;
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Vec<Block>> {
+        let mut blocks = Vec::new();
+        let mut outdated = 0;
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            let (schema, block) = decode_record(&value)?;
+            if schema < SCHEMA_VERSION {
+                self.db.insert(key, encode_record(&block)?)?;
+                outdated += 1;
+            }
+            blocks.push(block);
+        }
+        if outdated > 0 {
+            self.db.flush()?;
+        }
+        Ok(blocks)
+    }
+
+    // sled cleans up its segments as it flushes; the size on disk either
+    // side of the flush shows what that gave back.
+    fn compact(&self) -> io::Result<u64> {
+        let before = self.db.size_on_disk()?;
+        self.db.flush()?;
+        let after = self.db.size_on_disk()?;
+        Ok(before.saturating_sub(after))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    blocks: Mutex<BTreeMap<u64, Block>>,
+}
+
+impl BlockStore for MemoryStore {
+    fn put(&self, block: &Block) -> io::Result<()> {
+        self.blocks.lock().unwrap().insert(block.header.height, block.clone());
+        Ok(())
+    }
+
+    fn truncate(&self, height: u64) -> io::Result<()> {
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some(first) = height.checked_add(1) {
+            blocks.split_off(&first);
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Vec<Block>> {
+        Ok(self.blocks.lock().unwrap().values().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::Consensus;
+    use crate::ledger::Ledger;
+    use crate::test_util::{consensus, tx};
+    use crate::types::hash_block;
+    use std::sync::Arc;
+
+    fn chain(cons: &Consensus, len: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 1..=len {
+            let prev_hash = blocks.last().map(hash_block).unwrap_or_default();
+            let txs = vec![tx(&format!("tx{}", height))];
+            let block = cons.propose_block(height, prev_hash, txs, "val1".to_string()).unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    // The same checks for every backend, through the trait alone.
+    fn exercise(store: Box<dyn BlockStore>, cons: &Consensus) {
+        let blocks = chain(cons, 4);
+        for block in blocks.iter().rev() {
+            store.put(block).unwrap();
+        }
+        assert_eq!(store.load().unwrap(), blocks);
+        store.truncate(u64::MAX).unwrap();
+        store.truncate(2).unwrap();
+        assert_eq!(store.load().unwrap(), blocks[..2]);
+
+        let mut ledger = Ledger::new().recover(store, None, cons).unwrap();
+        assert!(ledger.blocks().iter().map(Arc::as_ref).eq(&blocks[..2]));
+        ledger.append(blocks[2].clone()).unwrap();
+        ledger.rollback(1).unwrap();
+        assert_eq!(ledger.tip_height(), 1);
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingStore {
+        inner: MemoryStore,
+        compactions: std::sync::atomic::AtomicUsize,
+    }
+
+    impl BlockStore for CountingStore {
+        fn put(&self, block: &Block) -> io::Result<()> {
+            self.inner.put(block)
+        }
+
+        fn truncate(&self, height: u64) -> io::Result<()> {
+            self.inner.truncate(height)
+        }
+
+        fn load(&self) -> io::Result<Vec<Block>> {
+            self.inner.load()
+        }
+
+        fn compact(&self) -> io::Result<u64> {
+            self.compactions.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn compaction_runs_on_schedule_and_keeps_the_blocks() {
+        let cons = consensus();
+        let counting = Arc::new(CountingStore::default());
+        let task = tokio::spawn(compact_every(counting.clone(), Duration::from_millis(20)));
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        task.abort();
+        let runs = counting.compactions.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(runs >= 3, "{} compactions", runs);
+
+        let dir = std::env::temp_dir().join(format!("gcl-compact-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store: Arc<dyn BlockStore> = Arc::new(SledStore::open(&dir).unwrap());
+        let blocks = chain(&cons, 6);
+        for block in &blocks {
+            store.put(block).unwrap();
+        }
+        store.truncate(3).unwrap();
+        let task = tokio::spawn(compact_every(store.clone(), Duration::from_millis(10)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        task.abort();
+        store.compact().unwrap();
+        assert_eq!(store.load().unwrap(), blocks[..3]);
+        let ledger = Ledger::new().recover(Box::new(store), None, &cons).unwrap();
+        assert_eq!(ledger.tip_height(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_from_older_schemas_are_upgraded_on_load() {
+        let dir = std::env::temp_dir().join(format!("gcl-schema-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = SledStore::open(&dir).unwrap();
+        // A block as written before record versioning and before headers
+        // had a version, round, sorted root or totals, or txs a fee.
+        let legacy = serde_json::json!({
+            "header": {
+                "height": 1,
+                "prev_hash": "0".repeat(64),
+                "merkle_root": "ab".repeat(32),
+                "proposer": "val1",
+                "timestamp": "2024-01-01T00:00:00Z"
+            },
+            "txs": [{
+                "tx_id": "old",
+                "tx_type": "message",
+                "origin": "user1",
+                "payload": "data",
+                "sig": "sig"
+            }]
+        });
+        store.db.insert(1u64.to_be_bytes(), serde_json::to_vec(&legacy).unwrap()).unwrap();
+        let current = chain(&consensus(), 2).remove(1);
+        store.put(&current).unwrap();
+
+        let blocks = store.load().unwrap();
+        assert_eq!(blocks.len(), 2);
+        let header = &blocks[0].header;
+        assert_eq!((header.version, header.round, header.tx_count), (0, 0, 0));
+        assert!(header.sorted_root.is_zero());
+        assert_eq!(blocks[0].txs[0].fee, 0);
+        assert_eq!(blocks[0].txs[0].depends_on, None);
+        assert_eq!(blocks[1], current);
+        for record in store.db.iter().values() {
+            assert_eq!(record.unwrap()[0], SCHEMA_VERSION);
+        }
+        assert_eq!(store.load().unwrap(), blocks);
+
+        let mut newer = encode_record(&current).unwrap();
+        newer[0] = SCHEMA_VERSION + 1;
+        store.db.insert(2u64.to_be_bytes(), newer).unwrap();
+        assert_eq!(store.load().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn both_backends_behave_alike() {
+        let cons = consensus();
+        exercise(Box::new(MemoryStore::default()), &cons);
+
+        let dir = std::env::temp_dir().join(format!("gcl-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        exercise(Box::new(SledStore::open(&dir).unwrap()), &cons);
+        let reopened = SledStore::open(&dir).unwrap().load().unwrap();
+        assert_eq!(reopened.iter().map(|b| b.header.height).collect::<Vec<_>>(), [1]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
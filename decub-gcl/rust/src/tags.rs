@@ -0,0 +1,46 @@
+use crate::types::Transaction;
+use std::fmt;
+
+pub const MAX_TAGS: usize = 8;
+pub const MAX_TAG_LEN: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum TagError {
+    TooMany { count: usize },
+    BadLength { tag: String },
+    BadCharacter { tag: String },
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::TooMany { count } => {
+                write!(f, "{} tags given, at most {} allowed", count, MAX_TAGS)
+            }
+            TagError::BadLength { tag } => {
+                write!(f, "tag {:?} must be 1 to {} bytes", tag, MAX_TAG_LEN)
+            }
+            TagError::BadCharacter { tag } => {
+                write!(f, "tag {:?} may only contain ASCII letters, digits and -_.:", tag)
+            }
+        }
+    }
+}
+
+// Tags are joined with ',' when hashed, so the character set excludes it.
+pub fn check_tags(tx: &Transaction) -> Result<(), TagError> {
+    if tx.tags.len() > MAX_TAGS {
+        return Err(TagError::TooMany {
+            count: tx.tags.len(),
+        });
+    }
+    for tag in &tx.tags {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+            return Err(TagError::BadLength { tag: tag.clone() });
+        }
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) {
+            return Err(TagError::BadCharacter { tag: tag.clone() });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+// Fixtures shared by the unit tests.
+use crate::consensus::{Consensus, Validator};
+use crate::types::Transaction;
+
+// Three validators, val1 to val3, with no keys.
+pub(crate) fn consensus() -> Consensus {
+    let validators = ["val1", "val2", "val3"]
+        .iter()
+        .map(|id| Validator::new(id.to_string(), String::new()))
+        .collect();
+    Consensus::new(validators)
+}
+
+// A plain message from user1; tests override fields with `..tx(id)`.
+pub(crate) fn tx(id: &str) -> Transaction {
+    Transaction {
+        tx_id: id.to_string(),
+        tx_type: "message".to_string(),
+        origin: "user1".to_string(),
+        payload: "data".to_string(),
+        sig: "sig".to_string(),
+        ..Transaction::default()
+    }
+}
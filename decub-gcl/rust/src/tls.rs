@@ -0,0 +1,145 @@
+use crate::api::{Responding, server_options};
+use crate::config::{Config, TlsConfig};
+use std::convert::Infallible;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use warp::hyper::server::accept::Accept;
+use warp::hyper::service::{make_service_fn, service_fn};
+
+// Finished handshakes waiting for the server to pick them up.
+const HANDSHAKE_BACKLOG: usize = 64;
+
+// Loads the PEM certificate chain and private key named by `tls`.
+pub fn acceptor(tls: &TlsConfig) -> io::Result<TlsAcceptor> {
+    let mut pem = BufReader::new(File::open(&tls.cert_path)?);
+    let certs = rustls_pemfile::certs(&mut pem).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&tls.key_path)?))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no private key found"))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+struct Handshaken(mpsc::Receiver<TlsStream<TcpStream>>);
+
+impl Accept for Handshaken {
+    type Conn = TlsStream<TcpStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Self::Conn>>> {
+        self.0.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+// Serves `service` over HTTPS, with `config`'s connection settings, until
+// `shutdown` resolves. Every handshake runs on a task of its own, so a slow
+// one holds up no other connection, and one that fails (a plain HTTP
+// client, say) is dropped.
+pub async fn serve_tls<S>(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    service: S,
+    config: Arc<Config>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), warp::hyper::Error>
+where
+    S: Fn(warp::http::Request<warp::hyper::Body>) -> Responding + Clone + Send + 'static,
+{
+    let (handshaken, connections) = mpsc::channel(HANDSHAKE_BACKLOG);
+    let accepting = tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let (acceptor, handshaken) = (acceptor.clone(), handshaken.clone());
+            tokio::spawn(async move {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    let _ = handshaken.send(stream).await;
+                }
+            });
+        }
+    });
+    let make_service = make_service_fn(move |_| {
+        let service = service.clone();
+        async move { Ok::<_, Infallible>(service_fn(service)) }
+    });
+    let builder = warp::hyper::Server::builder(Handshaken(connections));
+    let served = server_options(builder, &config)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await;
+    accepting.abort();
+    served
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::catch_panics;
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use warp::Filter;
+
+    const REQUEST: &[u8] = b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    #[tokio::test]
+    async fn https_is_served_and_plain_http_is_refused() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let tls = TlsConfig {
+            cert_path: dir.join("localhost.crt"),
+            key_path: dir.join("localhost.key"),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = catch_panics(warp::service(warp::path!("ping").map(|| "pong")));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let shutdown = async {
+            stopped.await.ok();
+        };
+        let acceptor = acceptor(&tls).unwrap();
+        let config = Arc::new(Config::default());
+        let server = tokio::spawn(serve_tls(listener, acceptor, service, config, shutdown));
+
+        let mut roots = RootCertStore::empty();
+        let mut pem = BufReader::new(File::open(&tls.cert_path).unwrap());
+        for cert in rustls_pemfile::certs(&mut pem) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let client = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client));
+        let name = ServerName::try_from("localhost").unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect(name, stream).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"), "{}", String::from_utf8_lossy(&response));
+        assert!(response.ends_with(b"pong"));
+
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain.write_all(REQUEST).await.unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/"), "{}", String::from_utf8_lossy(&response));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
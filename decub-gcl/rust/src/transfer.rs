@@ -0,0 +1,72 @@
+use crate::types::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+pub const TRANSFER: &str = "transfer";
+
+// Payload of a "transfer" transaction: `{"to": "bob", "amount": 10}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransferPayload {
+    pub to: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransferError {
+    MalformedPayload(String),
+    InsufficientBalance { balance: u64, amount: u64 },
+    BalanceOverflow,
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::MalformedPayload(err) => {
+                write!(f, "malformed transfer payload: {}", err)
+            }
+            TransferError::InsufficientBalance { balance, amount } => {
+                write!(f, "insufficient balance: have {}, need {}", balance, amount)
+            }
+            TransferError::BalanceOverflow => write!(f, "recipient balance would overflow"),
+        }
+    }
+}
+
+pub fn is_transfer(tx: &Transaction) -> bool {
+    tx.tx_type == TRANSFER
+}
+
+pub fn parse_transfer(tx: &Transaction) -> Result<TransferPayload, TransferError> {
+    serde_json::from_str(&tx.payload)
+        .map_err(|err| TransferError::MalformedPayload(err.to_string()))
+}
+
+// Moves funds for a transfer; other transaction types leave balances alone.
+// Nothing changes unless the whole transfer succeeds.
+pub fn apply_transfer(
+    balances: &mut HashMap<String, u64>,
+    tx: &Transaction,
+) -> Result<(), TransferError> {
+    if !is_transfer(tx) {
+        return Ok(());
+    }
+    let payload = parse_transfer(tx)?;
+    let balance = balances.get(&tx.origin).copied().unwrap_or_default();
+    let remaining = balance
+        .checked_sub(payload.amount)
+        .ok_or(TransferError::InsufficientBalance {
+            balance,
+            amount: payload.amount,
+        })?;
+    if payload.to == tx.origin {
+        return Ok(());
+    }
+    let received = balances.get(&payload.to).copied().unwrap_or_default();
+    let credited = received
+        .checked_add(payload.amount)
+        .ok_or(TransferError::BalanceOverflow)?;
+    balances.insert(tx.origin.clone(), remaining);
+    balances.insert(payload.to, credited);
+    Ok(())
+}
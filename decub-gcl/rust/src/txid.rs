@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const DEFAULT_MAX_TX_ID_LEN: usize = 128;
+
+// Characters a client-chosen tx_id may use. UrlSafe is RFC 3986's
+// unreserved set, so an id never needs escaping in a path; Hex matches ids
+// the node derives itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxIdCharset {
+    #[default]
+    UrlSafe,
+    Hex,
+    Base58,
+}
+
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+impl TxIdCharset {
+    fn allows(self, c: char) -> bool {
+        match self {
+            TxIdCharset::UrlSafe => c.is_ascii_alphanumeric() || "-_.~".contains(c),
+            TxIdCharset::Hex => c.is_ascii_hexdigit(),
+            TxIdCharset::Base58 => BASE58.contains(c),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            TxIdCharset::UrlSafe => "ASCII letters, digits and -_.~",
+            TxIdCharset::Hex => "hex digits",
+            TxIdCharset::Base58 => "base58 characters",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TxIdError {
+    TooLong { len: usize, max: usize },
+    BadCharacter { tx_id: String, charset: TxIdCharset },
+}
+
+impl fmt::Display for TxIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxIdError::TooLong { len, max } => {
+                write!(f, "tx_id is {} bytes; at most {} are accepted", len, max)
+            }
+            TxIdError::BadCharacter { tx_id, charset } => {
+                write!(f, "tx_id {:?} may only contain {}", tx_id, charset.describe())
+            }
+        }
+    }
+}
+
+// Ids end up as map keys and in URLs, so anything that would need escaping
+// or swell the indexes is turned away.
+pub fn check_tx_id(tx_id: &str, max_len: usize, charset: TxIdCharset) -> Result<(), TxIdError> {
+    if tx_id.len() > max_len {
+        return Err(TxIdError::TooLong {
+            len: tx_id.len(),
+            max: max_len,
+        });
+    }
+    if !tx_id.chars().all(|c| charset.allows(c)) {
+        return Err(TxIdError::BadCharacter {
+            tx_id: tx_id.to_string(),
+            charset,
+        });
+    }
+    Ok(())
+}
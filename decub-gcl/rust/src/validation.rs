@@ -0,0 +1,155 @@
+use crate::transfer::{TRANSFER, parse_transfer};
+use crate::types::Transaction;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+pub const DEPLOY: &str = "deploy";
+// Opaque binary data, carried as standard padded base64.
+pub const BLOB: &str = "blob";
+
+pub type TxRule = Arc<dyn Fn(&Transaction) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, PartialEq)]
+pub struct TxRuleError {
+    pub tx_type: String,
+    pub reason: String,
+}
+
+impl fmt::Display for TxRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} transaction: {}", self.tx_type, self.reason)
+    }
+}
+
+// Payload of a "deploy" transaction: `{"code": "..."}`.
+#[derive(Deserialize)]
+struct DeployPayload {
+    code: String,
+}
+
+// Per-type checks run on submission, keyed by tx_type. Types without a
+// rule of their own go to the fallback, which accepts anything unless
+// replaced.
+#[derive(Clone)]
+pub struct TxValidator {
+    rules: BTreeMap<String, TxRule>,
+    fallback: TxRule,
+}
+
+impl fmt::Debug for TxValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxValidator").field("types", &self.rules.keys()).finish()
+    }
+}
+
+impl Default for TxValidator {
+    fn default() -> Self {
+        TxValidator::empty()
+            .with_rule(TRANSFER, check_transfer_shape)
+            .with_rule(DEPLOY, check_deploy)
+            .with_rule(BLOB, check_blob)
+    }
+}
+
+impl TxValidator {
+    // No rules at all: every transaction goes to the fallback.
+    pub fn empty() -> Self {
+        TxValidator {
+            rules: BTreeMap::new(),
+            fallback: Arc::new(|_| Ok(())),
+        }
+    }
+
+    pub fn with_rule<F>(mut self, tx_type: &str, rule: F) -> Self
+    where
+        F: Fn(&Transaction) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.rules.insert(tx_type.to_string(), Arc::new(rule));
+        self
+    }
+
+    pub fn with_fallback<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&Transaction) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.fallback = Arc::new(rule);
+        self
+    }
+
+    pub fn validate(&self, tx: &Transaction) -> Result<(), TxRuleError> {
+        let rule = self.rules.get(&tx.tx_type).unwrap_or(&self.fallback);
+        rule(tx).map_err(|reason| TxRuleError {
+            tx_type: tx.tx_type.clone(),
+            reason,
+        })
+    }
+}
+
+// Only the payload's shape; whether the origin can pay is the ledger's call.
+fn check_transfer_shape(tx: &Transaction) -> Result<(), String> {
+    let payload = parse_transfer(tx).map_err(|err| err.to_string())?;
+    if payload.to.is_empty() {
+        return Err("transfer has no recipient".to_string());
+    }
+    Ok(())
+}
+
+fn check_deploy(tx: &Transaction) -> Result<(), String> {
+    let payload: DeployPayload =
+        serde_json::from_str(&tx.payload).map_err(|err| format!("malformed payload: {}", err))?;
+    if payload.code.trim().is_empty() {
+        return Err("deploy has no code".to_string());
+    }
+    Ok(())
+}
+
+fn check_blob(tx: &Transaction) -> Result<(), String> {
+    STANDARD
+        .decode(&tx.payload)
+        .map(drop)
+        .map_err(|err| format!("payload is not valid base64: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn tx(tx_type: &str, payload: &str) -> Transaction {
+        Transaction {
+            tx_type: tx_type.to_string(),
+            payload: payload.to_string(),
+            ..test_util::tx("tx1")
+        }
+    }
+
+    #[test]
+    fn each_type_gets_its_own_rule() {
+        let rules = TxValidator::default();
+        let err = rules.validate(&tx(TRANSFER, r#"{"to":"user2"}"#)).unwrap_err();
+        assert_eq!(err.tx_type, TRANSFER);
+        assert!(err.reason.contains("amount"), "{}", err);
+        let no_recipient = tx(TRANSFER, r#"{"to":"","amount":5}"#);
+        assert!(rules.validate(&no_recipient).is_err());
+        assert_eq!(rules.validate(&tx(TRANSFER, r#"{"to":"user2","amount":5}"#)), Ok(()));
+
+        assert_eq!(rules.validate(&tx(DEPLOY, r#"{"code":"fn main() {}"}"#)), Ok(()));
+        assert!(rules.validate(&tx(DEPLOY, r#"{"code":"  "}"#)).is_err());
+        assert!(rules.validate(&tx(DEPLOY, "fn main() {}")).is_err());
+
+        assert_eq!(rules.validate(&tx(BLOB, "aGVsbG8=")), Ok(()));
+        for malformed in ["aGVsbG8", "aGVs*G8=", "hello world"] {
+            assert!(rules.validate(&tx(BLOB, malformed)).is_err(), "{}", malformed);
+        }
+
+        assert_eq!(rules.validate(&tx("vote", "anything")), Ok(()));
+        let strict = rules.with_fallback(|tx| Err(format!("unknown type {:?}", tx.tx_type)));
+        let err = strict.validate(&tx("vote", "anything")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid vote transaction: unknown type \"vote\"");
+        assert_eq!(strict.validate(&tx(DEPLOY, r#"{"code":"x"}"#)), Ok(()));
+    }
+}
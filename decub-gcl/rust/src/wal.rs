@@ -0,0 +1,49 @@
+use crate::types::Block;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Write-ahead log of accepted blocks, one JSON line each. A block is written
+// and synced here before the store or any index sees it; the log is emptied
+// once the store has durably committed it.
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        Ok(Wal { file })
+    }
+
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let line = serde_json::to_string(block).map_err(io::Error::from)?;
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()
+    }
+
+    // Blocks logged since the last checkpoint, oldest first. A torn final
+    // line (a crash mid-write) is dropped: that block was never acknowledged.
+    pub fn entries(&self) -> io::Result<Vec<Block>> {
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut blocks = Vec::new();
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(block) => blocks.push(block),
+                Err(_) => break,
+            }
+        }
+        Ok(blocks)
+    }
+
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+}
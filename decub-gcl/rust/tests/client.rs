@@ -0,0 +1,151 @@
+#![cfg(feature = "client")]
+
+use decub_gcl::api::{self, SharedLedger, SharedMempool};
+use decub_gcl::client::{ClientError, GclClient, SubmitStatus};
+use decub_gcl::config::Config;
+use decub_gcl::consensus::{Consensus, SharedConsensus, Validator};
+use decub_gcl::ledger::Ledger;
+use decub_gcl::mempool::Mempool;
+use decub_gcl::proposer;
+use decub_gcl::replica;
+use decub_gcl::types::{Block, Transaction, hash_block};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+fn consensus() -> SharedConsensus {
+    let validators = ["val1", "val2", "val3"]
+        .iter()
+        .map(|id| Validator::new(id.to_string(), String::new()))
+        .collect();
+    Arc::new(RwLock::new(Consensus::new(validators)))
+}
+
+fn spawn_server() -> GclClient {
+    let cons = consensus();
+    let ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+    let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+    tokio::spawn(proposer::run(
+        ledger.clone(),
+        mempool.clone(),
+        cons.clone(),
+        Duration::from_millis(20),
+    ));
+    serve(ledger, mempool, cons)
+}
+
+fn serve(ledger: SharedLedger, mempool: SharedMempool, cons: SharedConsensus) -> GclClient {
+    let routes = api::routes(ledger, mempool, cons, Arc::new(Config::default()));
+    let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    GclClient::new(&format!("http://{}", addr))
+}
+
+fn tx(id: &str) -> Transaction {
+    Transaction {
+        tx_id: id.to_string(),
+        tx_type: "message".to_string(),
+        origin: "user1".to_string(),
+        payload: "data".to_string(),
+        sig: "sig".to_string(),
+        ..Transaction::default()
+    }
+}
+
+async fn wait_for_height(client: &GclClient, height: u64) {
+    for _ in 0..100 {
+        if let Ok(head) = client.get_head().await {
+            if head.header.height >= height {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("chain did not reach height {}", height);
+}
+
+#[tokio::test]
+async fn client_round_trip() {
+    let client = spawn_server();
+    assert!(matches!(client.get_head().await, Err(ClientError::NotFound(_))));
+
+    assert_eq!(client.submit_tx(&tx("tx1")).await.unwrap(), SubmitStatus::Queued);
+    wait_for_height(&client, 1).await;
+
+    let block = client.get_block(1).await.unwrap();
+    assert_eq!(block.txs[0].tx_id, "tx1");
+    assert_eq!(client.get_head().await.unwrap().header.height, 1);
+
+    let proof = client.get_proof("tx1").await.unwrap();
+    assert_eq!(proof.index, 0);
+    assert!(client.verify_proof(&tx("tx1"), 1).await.unwrap());
+    let mut tampered = tx("tx1");
+    tampered.payload = "forged".to_string();
+    assert!(!client.verify_proof(&tampered, 1).await.unwrap());
+}
+
+#[tokio::test]
+async fn client_maps_missing_resources() {
+    let client = spawn_server();
+    assert!(matches!(client.get_block(7).await, Err(ClientError::NotFound(_))));
+    assert!(matches!(client.get_proof("nope").await, Err(ClientError::NotFound(_))));
+}
+
+#[tokio::test]
+async fn replica_pulls_new_blocks_from_primary() {
+    let primary = spawn_server();
+    let replica_ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+    let cons = consensus();
+    let pull = || replica::pull(&replica_ledger, &cons, &primary, 0);
+    assert_eq!(pull().await.unwrap(), 0);
+
+    for (height, id) in [(1, "tx1"), (2, "tx2")] {
+        primary.submit_tx(&tx(id)).await.unwrap();
+        wait_for_height(&primary, height).await;
+    }
+    assert_eq!(pull().await.unwrap(), 2);
+    let head = primary.get_head().await.unwrap();
+    assert_eq!(replica_ledger.read().unwrap().tip().map(Arc::as_ref), Some(&head));
+
+    primary.submit_tx(&tx("tx3")).await.unwrap();
+    wait_for_height(&primary, 3).await;
+    assert_eq!(pull().await.unwrap(), 1);
+    let head = primary.get_head().await.unwrap();
+    assert_eq!(replica_ledger.read().unwrap().tip().map(Arc::as_ref), Some(&head));
+}
+
+#[tokio::test]
+async fn replica_follows_the_primary_across_a_reorg() {
+    let cons = consensus();
+    let primary_ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+    let mempool: SharedMempool = Arc::new(Mutex::new(Mempool::new()));
+    let primary = serve(primary_ledger.clone(), mempool, cons.clone());
+    let block = |height, prev, id: &str, proposer: &str| {
+        let cons = cons.read().unwrap();
+        let block = cons.propose_block(height, prev, vec![tx(id)], proposer.into()).unwrap();
+        Block {
+            signatures: cons.sign_block(&block),
+            ..block
+        }
+    };
+    let first = block(1, Default::default(), "tx1", "val1");
+    let second = block(2, hash_block(&first), "tx2", "val2");
+    for block in [&first, &second] {
+        primary_ledger.write().unwrap().append(block.clone()).unwrap();
+    }
+    let replica_ledger: SharedLedger = Arc::new(RwLock::new(Ledger::new()));
+    let pull = || replica::pull(&replica_ledger, &cons, &primary, 3);
+    assert_eq!(pull().await.unwrap(), 2);
+
+    // The primary switches to a longer branch off block 1.
+    let rival = block(2, hash_block(&first), "rival", "val2");
+    let third = block(3, hash_block(&rival), "tx3", "val3");
+    for block in [&rival, &third] {
+        let cons = cons.read().unwrap();
+        primary_ledger.write().unwrap().insert(block.clone(), &cons, 3).unwrap();
+    }
+    assert_eq!(pull().await.unwrap(), 2);
+    let replica = replica_ledger.read().unwrap();
+    assert_eq!(replica.tip().map(Arc::as_ref), Some(&third));
+    assert_eq!(replica.get(2).map(Arc::as_ref), Some(&rival));
+    assert_eq!(replica.known_at(2).len(), 2);
+}